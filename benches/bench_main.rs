@@ -1,7 +1,7 @@
 use std::time::Duration;
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use etch::{examples::{sorted_vec_intersect::{vec_intersect_manual, vec_intersect_streams_gallop, vec_intersect_streams_linear}, tree_iteration::{intersect2_iterators, intersect2_manual, intersect3_iterators, itersect3_manual}, triangle_query::{create_skewed_relation, triangle_query_fused, triangle_query_naive, triangle_query_unfused}}, streams::{sorted_vec::SortedVecGalloper, stream_defs::IndexedStream}};
+use etch::{examples::{sorted_vec_intersect::{vec_intersect_manual, vec_intersect_streams_adaptive, vec_intersect_streams_gallop, vec_intersect_streams_linear}, tree_iteration::{intersect2_bplus_trees, intersect2_iterators, intersect2_manual, intersect3_iterators, itersect3_manual}, triangle_query::{create_skewed_relation, triangle_query_fused, triangle_query_generic, triangle_query_naive, triangle_query_unfused}}, rbtree::bplus_tree::BPlusTree, streams::{add_stream::union, parallel::Splittable, sorted_vec::{SortedVecGalloper, SortedVecLinear}, stream_defs::IndexedStream}};
 use rand::{prelude::SliceRandom, rngs::StdRng, SeedableRng};
 
 fn gen_random_sorted_strings(n: usize, sparsity: usize, seed: u64) -> Vec<String> {
@@ -51,6 +51,13 @@ fn triangle_query_benchmark(c: &mut Criterion) {
             r3.stream_iter().map(|_, x| SortedVecGalloper::new(x))
         )));
     });
+    group.bench_function("tri.generic", |b| {
+        b.iter(|| black_box(triangle_query_generic(
+            r1.stream_iter().map(|_, x| SortedVecGalloper::new(x)),
+            r2.stream_iter().map(|_, x| SortedVecGalloper::new(x)),
+            r3.stream_iter().map(|_, x| SortedVecGalloper::new(x))
+        )));
+    });
     group.bench_function("tri.naive", |b| {
         b.iter(|| black_box(triangle_query_naive(
             &r1,
@@ -71,6 +78,9 @@ fn sorted_vec_sparse_intersect_benchmark(c: &mut Criterion) {
     group.bench_function("inter.sparse.indexed_streams_linear", |b|
         b.iter(|| black_box(vec_intersect_streams_linear(&s1, &s2)))
     );
+    group.bench_function("inter.sparse.indexed_streams_adaptive", |b|
+        b.iter(|| black_box(vec_intersect_streams_adaptive(&s1, &s2)))
+    );
     group.bench_function("inter.sparse.manual", |b|
         b.iter(|| black_box(vec_intersect_manual(&s1, &s2)))
     );
@@ -87,9 +97,27 @@ fn sorted_vec_dense_intersect_benchmark(c: &mut Criterion) {
     group.bench_function("inter.dense.indexed_streams_linear", |b|
         b.iter(|| black_box(vec_intersect_streams_linear(&s1, &s2)))
     );
+    group.bench_function("inter.dense.indexed_streams_adaptive", |b|
+        b.iter(|| black_box(vec_intersect_streams_adaptive(&s1, &s2)))
+    );
     group.bench_function("inter.dense.manual", |b|
         b.iter(|| black_box(vec_intersect_manual(&s1, &s2)))
     );
+    group.bench_function("inter.dense.count", |b|
+        b.iter(|| black_box(SortedVecGalloper::new(&s1).zip_with(SortedVecGalloper::new(&s2), |_, _| 1u64).contract()))
+    );
+    group.bench_function("inter.dense.count_linear", |b|
+        b.iter(|| black_box(SortedVecLinear::new(&s1).zip_with(SortedVecLinear::new(&s2), |_, _| 1u64).contract()))
+    );
+    group.bench_function("inter.dense.simd", |b|
+        b.iter(|| black_box(SortedVecGalloper::new(&s1).zip_with(SortedVecGalloper::new(&s2), |_, _| ()).collect_indices()))
+    );
+    group.bench_function("inter.dense.union_count", |b|
+        b.iter(|| black_box(union(SortedVecGalloper::new(&s1), SortedVecGalloper::new(&s2), |_| 1u64).contract()))
+    );
+    group.bench_function("inter.dense.par_count", |b|
+        b.iter(|| black_box(SortedVecGalloper::new(&s1).zip_with(SortedVecGalloper::new(&s2), |_, _| 1u64).par_contract()))
+    );
 }
 
 fn rbtree_intersect_benchmark(c: &mut Criterion) {
@@ -130,6 +158,15 @@ fn rbtree_intersect_benchmark(c: &mut Criterion) {
             &tree_a
         )));
     });
+
+    let bplus_a: BPlusTree<u32, ()> = BPlusTree::from_sorted_iter(gen_random_sorted_ints(1_000_000, 10, 0).into_iter().map(|x| (x, ())));
+    let bplus_b: BPlusTree<u32, ()> = BPlusTree::from_sorted_iter(gen_random_sorted_ints(1_000_000, 10, 1).into_iter().map(|x| (x, ())));
+    group.bench_function("rbtree.intersect2_bplus", |b| {
+        b.iter(|| black_box(intersect2_bplus_trees(
+            &bplus_a,
+            &bplus_b
+        )))
+    });
     group.finish();
 }
 
@@ -0,0 +1,245 @@
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+const STREAMS: &str = "::etch::streams::stream_defs";
+
+/// Einstein-summation over nested indexed streams.
+///
+/// `einsum!("ij,j->i", a, x)` expands to nested `zip_with`/`map`/`contract` calls:
+/// the loops run over the output indices (in output order) followed by the contracted
+/// indices (in order of first appearance), every operand taking part in the loops over its indices.
+/// Operands can be anything implementing `IntoStreamIterator`, nested once per index,
+/// e.g. `&SparseCSRMat<T>` for two indices or `&SparseVec<I, T>` for one.
+///
+/// Since streams can only be traversed in their storage order, the indices of every operand
+/// must appear in the same relative order as in the loop order.
+/// For example, a matrix product has to take its right operand transposed (`"ij,kj->ik"`).
+///
+/// By default the innermost values are multiplied with `*`.
+/// A custom combiner taking one argument per operand can be given after a `;`:
+/// `einsum!("ab,bc,ac->abc", r1, r2, r3; |_, _, _| ())`.
+/// Operands containing top-level commas (e.g. turbofish calls) must be parenthesized.
+#[proc_macro]
+pub fn einsum(input: TokenStream) -> TokenStream {
+    let mut tokens = input.into_iter();
+    let (spec, span) = match tokens.next() {
+        Some(TokenTree::Literal(lit)) => match parse_string_literal(&lit.to_string()) {
+            Some(spec) => (spec, lit.span()),
+            None => return compile_error("einsum! expects a string literal spec such as \"ij,j->i\"", lit.span()),
+        },
+        Some(other) => return compile_error("einsum! expects a string literal spec such as \"ij,j->i\"", other.span()),
+        None => return compile_error("einsum! expects a spec and operands", Span::call_site()),
+    };
+
+    let mut operands: Vec<TokenStream> = Vec::new();
+    let mut combiner: Option<TokenStream> = None;
+    let mut current: Option<TokenStream> = None;
+    for token in tokens {
+        if let Some(combiner) = combiner.as_mut() {
+            combiner.extend([token]);
+            continue;
+        }
+        match &token {
+            TokenTree::Punct(p) if p.as_char() == ',' => {
+                operands.extend(current.take());
+                current = Some(TokenStream::new());
+            },
+            TokenTree::Punct(p) if p.as_char() == ';' => {
+                operands.extend(current.take());
+                combiner = Some(TokenStream::new());
+            },
+            _ => match current.as_mut() {
+                Some(operand) => operand.extend([token]),
+                None => return compile_error("expected `,` after the einsum! spec", token.span()),
+            },
+        }
+    }
+    operands.extend(current.take());
+    operands.retain(|operand| !operand.is_empty());
+    if combiner.as_ref().is_some_and(|c| c.is_empty()) {
+        return compile_error("expected a combiner after `;`", span);
+    }
+
+    let plan = match Plan::parse(&spec, operands.len()) {
+        Ok(plan) => plan,
+        Err(message) => return compile_error(&message, span),
+    };
+
+    let mut body = TokenStream::new();
+    for (k, operand) in operands.into_iter().enumerate() {
+        body.extend(parse(&format!("let {} =", operand_name(k))));
+        body.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, operand))]);
+        body.extend(parse(";"));
+    }
+    if let Some(combiner) = &combiner {
+        body.extend(parse("let __etch_combine ="));
+        body.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, combiner.clone()))]);
+        body.extend(parse(";"));
+    }
+    body.extend(parse(&plan.expand(combiner.is_some())));
+    TokenStream::from(TokenTree::Group(Group::new(Delimiter::Brace, body)))
+}
+
+fn parse(code: &str) -> TokenStream {
+    code.parse().expect("einsum! generated invalid tokens")
+}
+
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut args = Group::new(Delimiter::Parenthesis, TokenStream::from(TokenTree::Literal(message)));
+    args.set_span(span);
+    TokenStream::from_iter([
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(args),
+    ])
+}
+
+/// Unquotes a plain (non-raw) string literal; einsum specs never need escapes
+fn parse_string_literal(literal: &str) -> Option<String> {
+    literal.strip_prefix('"')?.strip_suffix('"').map(|s| s.to_string())
+}
+
+fn operand_name(k: usize) -> String {
+    format!("__etch_op{k}")
+}
+
+/// A validated einsum spec, together with the loop order it is evaluated in
+#[derive(Debug, PartialEq, Eq)]
+struct Plan {
+    inputs: Vec<Vec<char>>,
+    output: Vec<char>,
+    order: Vec<char>,
+}
+
+impl Plan {
+    fn parse(spec: &str, operands: usize) -> Result<Plan, String> {
+        let (lhs, rhs) = spec.split_once("->")
+            .ok_or_else(|| format!("einsum spec `{spec}` must contain `->`"))?;
+        let parse_indices = |s: &str| -> Result<Vec<char>, String> {
+            let indices: Vec<char> = s.trim().chars().collect();
+            if let Some(c) = indices.iter().find(|c| !c.is_ascii_alphabetic()) {
+                return Err(format!("invalid index `{c}` in einsum spec `{spec}`; indices are single letters"));
+            }
+            if let Some(c) = indices.iter().enumerate().find_map(|(k, c)| indices[..k].contains(c).then_some(c)) {
+                return Err(format!("index `{c}` is repeated in `{}`; diagonals are not supported", s.trim()));
+            }
+            Ok(indices)
+        };
+        let inputs = lhs.split(',').map(parse_indices).collect::<Result<Vec<_>, _>>()?;
+        let output = parse_indices(rhs)?;
+        if inputs.len() != operands {
+            return Err(format!("einsum spec `{spec}` has {} inputs but {operands} operands were given", inputs.len()));
+        }
+        if let Some(c) = output.iter().find(|c| !inputs.iter().any(|input| input.contains(c))) {
+            return Err(format!("output index `{c}` does not appear in any input"));
+        }
+
+        let mut order = output.clone();
+        for c in inputs.iter().flatten() {
+            if !order.contains(c) {
+                order.push(*c);
+            }
+        }
+        let position = |c: &char| order.iter().position(|o| o == c).unwrap();
+        if let Some(input) = inputs.iter().find(|input| input.windows(2).any(|w| position(&w[0]) > position(&w[1]))) {
+            return Err(format!(
+                "input `{}` cannot be traversed in loop order `{}`; \
+                 indices must appear in the same relative order as the output indices followed by the contracted ones",
+                input.iter().collect::<String>(),
+                order.iter().collect::<String>(),
+            ));
+        }
+        Ok(Plan { inputs, output, order })
+    }
+
+    fn expand(&self, combine: bool) -> String {
+        let handles: Vec<String> = (0..self.inputs.len()).map(operand_name).collect();
+        self.expand_level(0, &handles, combine)
+    }
+
+    /// Generates the loop over `self.order[level]`, given the expressions currently
+    /// bound to each operand (its stream at the current nesting depth, or its value once exhausted)
+    fn expand_level(&self, level: usize, handles: &[String], combine: bool) -> String {
+        if level == self.order.len() {
+            return if combine {
+                format!("__etch_combine({})", handles.join(", "))
+            } else {
+                handles.join(" * ")
+            };
+        }
+        let var = self.order[level];
+        let participants: Vec<usize> = (0..self.inputs.len()).filter(|&k| {
+            let depth = self.inputs[k].iter().filter(|c| self.order[..level].contains(c)).count();
+            self.inputs[k].get(depth) == Some(&var)
+        }).collect();
+
+        let stream = participants.iter()
+            .map(|&k| format!("{STREAMS}::IntoStreamIterator::into_stream_iterator({})", handles[k]))
+            .reduce(|acc, s| format!("{STREAMS}::IndexedStream::zip_with({acc}, {s}, |a, b| (a, b))"))
+            .expect("every loop index appears in some input");
+        let mut new_handles = handles.to_vec();
+        let mut pattern = String::new();
+        for (n, &k) in participants.iter().enumerate() {
+            new_handles[k] = format!("__etch_{var}_op{k}");
+            pattern = if n == 0 { new_handles[k].clone() } else { format!("({pattern}, {})", new_handles[k]) };
+        }
+        // The closure is called once per entry, so operands captured from outside are cloned
+        let captured = (0..handles.len())
+            .filter(|k| !participants.contains(k))
+            .map(|k| handles[k].as_str())
+            .chain(combine.then_some("__etch_combine"));
+        let clones: String = captured
+            .map(|h| format!("let {h} = ::core::clone::Clone::clone(&{h});"))
+            .collect();
+        let body = self.expand_level(level + 1, &new_handles, combine);
+        let mapped = format!("{STREAMS}::IndexedStream::map({stream}, move |_, {pattern}| {{ {clones} {body} }})");
+        if self.output.contains(&var) {
+            mapped
+        } else {
+            format!("{STREAMS}::IndexedStream::contract({mapped})")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Plan;
+
+    #[test]
+    fn test_plan_order() {
+        let plan = Plan::parse("ij,kj->ik", 2).unwrap();
+        assert_eq!(plan.order, vec!['i', 'k', 'j']);
+        let plan = Plan::parse("ab,bc,ac->", 3).unwrap();
+        assert_eq!(plan.order, vec!['a', 'b', 'c']);
+        assert!(plan.output.is_empty());
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        assert!(Plan::parse("ij,j", 2).unwrap_err().contains("->"));
+        assert!(Plan::parse("ij,j->i", 3).unwrap_err().contains("2 inputs but 3 operands"));
+        assert!(Plan::parse("ii->i", 1).unwrap_err().contains("repeated"));
+        assert!(Plan::parse("ij,j->k", 2).unwrap_err().contains("`k` does not appear"));
+        assert!(Plan::parse("i1->i", 1).unwrap_err().contains("invalid index"));
+        // The right operand of a matrix product must be transposed
+        assert!(Plan::parse("ij,jk->ik", 2).unwrap_err().contains("cannot be traversed"));
+        assert!(Plan::parse("ij->ji", 1).unwrap_err().contains("cannot be traversed"));
+    }
+
+    #[test]
+    fn test_expand_dot() {
+        let plan = Plan::parse("i,i->", 2).unwrap();
+        assert_eq!(
+            plan.expand(false),
+            "::etch::streams::stream_defs::IndexedStream::contract(\
+             ::etch::streams::stream_defs::IndexedStream::map(\
+             ::etch::streams::stream_defs::IndexedStream::zip_with(\
+             ::etch::streams::stream_defs::IntoStreamIterator::into_stream_iterator(__etch_op0), \
+             ::etch::streams::stream_defs::IntoStreamIterator::into_stream_iterator(__etch_op1), |a, b| (a, b)), \
+             move |_, (__etch_i_op0, __etch_i_op1)| {  __etch_i_op0 * __etch_i_op1 }))"
+        );
+    }
+}
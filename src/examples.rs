@@ -1,3 +1,4 @@
 pub mod triangle_query;
 pub mod sorted_vec_intersect;
 pub mod tree_iteration;
+pub mod einsum;
@@ -0,0 +1,66 @@
+use std::ops::{AddAssign, Mul};
+
+use num_traits::Zero;
+
+use crate::{einsum, streams::{csr_mat::SparseCSRMat, sparse_vec::SparseVec, stream_defs::IndexedStream}};
+
+/// Computes `a x`
+pub fn matvec<T: Copy + Mul<Output = T> + AddAssign + Zero>(a: &SparseCSRMat<T>, x: &SparseVec<usize, T>) -> SparseVec<usize, T> {
+    einsum!("ij,j->i", a, x; |a: &T, b: &T| *a * *b).collect()
+}
+
+/// Computes `a b`, given `b` transposed
+/// Entries whose rows and columns do not overlap are stored as explicit zeros
+pub fn matmul_transposed<T: Copy + Mul<Output = T> + AddAssign + Zero>(a: &SparseCSRMat<T>, b_transposed: &SparseCSRMat<T>) -> SparseCSRMat<T> {
+    einsum!("ij,kj->ik", a, b_transposed; |a: &T, b: &T| *a * *b).collect()
+}
+
+pub fn dot<T: Copy + Mul<Output = T> + AddAssign + Zero>(x: &SparseVec<usize, T>, y: &SparseVec<usize, T>) -> T {
+    einsum!("i,i->", x, y; |a: &T, b: &T| *a * *b)
+}
+
+pub fn outer<T: Copy + Mul<Output = T>>(x: &SparseVec<usize, T>, y: &SparseVec<usize, T>) -> SparseCSRMat<T> {
+    einsum!("i,j->ij", x, y; |a: &T, b: &T| *a * *b).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{einsum, streams::{csr_mat::SparseCSRMat, sparse_vec::SparseVec, stream_defs::{IndexedStream, IntoStreamIterator}}};
+
+    use super::{dot, matmul_transposed, matvec, outer};
+
+    fn dense(mat: &SparseCSRMat<i64>, rows: usize, cols: usize) -> Vec<Vec<i64>> {
+        let mut result = vec![vec![0; cols]; rows];
+        mat.into_stream_iterator().for_each(|i, row| row.for_each(|j, v| result[i][j] = *v));
+        result
+    }
+
+    #[test]
+    fn test_matvec() {
+        let a = SparseCSRMat::from_iter([(0, 0, 1), (0, 2, 2), (1, 1, 3), (3, 0, 4), (3, 3, 5)]);
+        let x = SparseVec::from_iter([(0, 10), (2, 20), (3, 30)]);
+        assert_eq!(matvec(&a, &x), SparseVec::from_iter([(0, 50), (1, 0), (2, 0), (3, 190)]));
+        // Default combiner: the values are multiplied
+        let sums: Vec<(usize, i64)> = einsum!("ij,j->i", &a, &x).collect();
+        assert_eq!(sums, vec![(0, 50), (1, 0), (2, 0), (3, 190)]);
+    }
+
+    #[test]
+    fn test_matmul() {
+        let a = SparseCSRMat::from_iter([(0, 0, 1), (0, 1, 2), (1, 1, 3)]);
+        let b_transposed = SparseCSRMat::from_iter([(0, 0, 4), (1, 1, 5), (2, 0, 6), (2, 1, 7)]);
+        assert_eq!(dense(&matmul_transposed(&a, &b_transposed), 2, 3), vec![vec![4, 10, 20], vec![0, 15, 21]]);
+    }
+
+    #[test]
+    fn test_dot_outer() {
+        let x = SparseVec::from_iter([(1, 2), (4, 3), (7, 5)]);
+        let y = SparseVec::from_iter([(0, 1), (4, 10), (7, 100)]);
+        assert_eq!(dot(&x, &y), 530);
+        assert_eq!(dot(&x, &x), 38);
+        assert_eq!(
+            dense(&outer(&x, &y), 8, 8)[4],
+            vec![3, 0, 0, 0, 30, 0, 0, 300]
+        );
+    }
+}
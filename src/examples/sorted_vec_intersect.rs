@@ -1,4 +1,4 @@
-use crate::streams::{sorted_vec::{SortedVecGalloper, SortedVecLinear}, stream_defs::IndexedStream};
+use crate::streams::{sorted_vec::{SortedVecAdaptive, SortedVecGalloper, SortedVecLinear}, stream_defs::IndexedStream};
 
 /// Given two strictly sorted vectors, compute their intersection
 pub fn vec_intersect_manual<I: Ord + Copy>(x: &Vec<I>, y: &Vec<I>) -> Vec<I> {
@@ -36,6 +36,14 @@ pub fn vec_intersect_streams_linear<I: Ord + Copy>(x: &Vec<I>, y: &Vec<I>) -> Ve
         .collect_indices()
 }
 
+/// Given two strictly sorted vectors, compute their intersection
+/// This uses adaptive indexed streams
+pub fn vec_intersect_streams_adaptive<I: Ord + Copy>(x: &[I], y: &[I]) -> Vec<I> {
+     SortedVecAdaptive::new(x)
+        .zip_with(SortedVecAdaptive::new(y), |_, _| ())
+        .collect_indices()
+}
+
 #[test]
 fn test_vec_intersect() {
     let x = vec![1, 2, 3, 4, 5];
@@ -43,5 +51,10 @@ fn test_vec_intersect() {
     assert_eq!(vec_intersect_manual(&x, &y), vec![3, 4, 5]);
     assert_eq!(vec_intersect_streams_gallop(&x, &y), vec![3, 4, 5]);
     assert_eq!(vec_intersect_streams_linear(&x, &y), vec![3, 4, 5]);
+    assert_eq!(vec_intersect_streams_adaptive(&x, &y), vec![3, 4, 5]);
+    // Dense at first, then sparse
+    let x: Vec<u32> = (0..100).chain((100..10_000).step_by(97)).collect();
+    let y: Vec<u32> = (0..10_000).step_by(2).collect();
+    assert_eq!(vec_intersect_streams_adaptive(&x, &y), vec_intersect_manual(&x, &y));
 }
 
@@ -1,4 +1,4 @@
-use crate::{rbtree::rbtree_lib::RBTree, streams::stream_defs::IndexedStream};
+use crate::{rbtree::{bplus_tree::BPlusTree, rbtree_lib::RBTree}, streams::stream_defs::IndexedStream};
 
 pub fn intersect2_iterators<I: Ord + Copy>(a: &RBTree<I, ()>, b: &RBTree<I, ()>) -> usize {
     a.stream_iter()
@@ -6,6 +6,12 @@ pub fn intersect2_iterators<I: Ord + Copy>(a: &RBTree<I, ()>, b: &RBTree<I, ()>)
         .fold(0, |acc, _, _| acc + 1)
 }
 
+pub fn intersect2_bplus_trees<I: Ord + Copy>(a: &BPlusTree<I, ()>, b: &BPlusTree<I, ()>) -> usize {
+    a.stream_iter()
+        .zip_with(b.stream_iter(), |_, _| ())
+        .fold(0, |acc, _, _| acc + 1)
+}
+
 pub fn intersect2_manual<I: Ord + Copy>(a: &RBTree<I, ()>, b: &RBTree<I, ()>) -> usize {
     a.iter()
         .filter(|(k, _)| b.contains_key(k))
@@ -31,7 +37,7 @@ mod test {
 
     use quickcheck_macros::quickcheck;
 
-    use crate::{examples::tree_iteration::{intersect2_iterators, intersect2_manual, intersect3_iterators, itersect3_manual}, rbtree::rbtree_lib::RBTree, streams::stream_defs::IndexedStream};
+    use crate::{examples::tree_iteration::{intersect2_bplus_trees, intersect2_iterators, intersect2_manual, intersect3_iterators, itersect3_manual}, rbtree::{bplus_tree::BPlusTree, rbtree_lib::RBTree}, streams::stream_defs::IndexedStream};
 
     
     #[test]
@@ -70,6 +76,9 @@ mod test {
         let tree_b = make_rbset(b);
         let tree_c = make_rbset(c);
         assert_eq!(intersect2_iterators(&tree_a, &tree_b), intersect2_manual(&tree_a, &tree_b));
+        let bplus_a: BPlusTree<u8, ()> = tree_a.iter().map(|(&k, _)| (k, ())).collect();
+        let bplus_b: BPlusTree<u8, ()> = tree_b.iter().map(|(&k, _)| (k, ())).collect();
+        assert_eq!(intersect2_bplus_trees(&bplus_a, &bplus_b), intersect2_manual(&tree_a, &tree_b));
         assert_eq!(intersect3_iterators(&tree_a, &tree_b, &tree_c), itersect3_manual(&tree_a, &tree_b, &tree_c));
     }
 }
@@ -1,4 +1,4 @@
-use crate::{indexed_stream, streams::{
+use crate::{einsum, indexed_stream, query::generic_join::Query, streams::{
     sorted_vec::SortedVecGalloper, sparse_vec::SparseVec, stream_defs::IndexedStream
 }};
 
@@ -74,21 +74,16 @@ pub fn triangle_query_naive<A: Ord + Copy, B: Ord + Copy, C: Ord + Copy>(
 
 /// Perform the triangle query on s1, s2, s3
 /// Assumes s1, s2, s3 are sorted
-/// This version is unfused
+/// This version is unfused: the join of s1 and s2 is materialized before joining with s3
 pub fn triangle_query_unfused<A: Ord + Copy, B: Ord + Copy, C: Ord + Copy>(
     t1: indexed_stream!(A, B, (); Clone),
     t2: indexed_stream!(B, C, (); Clone),
     t3: indexed_stream!(A, C, (); Clone)
 ) -> SparseVec<A, SparseVec<B, Vec<C>>> {
     let tmp = join_1(t1, t2)
-        .map(|_, a| a.map(|_, b| b.collect_indices())
-            .collect::<SparseVec<B, Vec<C>>>())
-        .collect::<SparseVec<A, SparseVec<B, Vec<C>>>>();
-    let tmp_as_iter = tmp.stream_iter()
-        .map(|_, x|
-            x.stream_iter().map(|_, y|
-                SortedVecGalloper::new(y)));
-    let result = join_2(tmp_as_iter, t3);
+        .map(|_, a| a.materialize_nested())
+        .materialize();
+    let result = join_2(tmp, t3);
 
     result
         .map(|_, a| {
@@ -116,6 +111,37 @@ pub fn triangle_query_fused<A: Ord + Copy, B: Ord + Copy, C: Ord + Copy>(
         .collect()
 }
 
+/// Perform the triangle query on s1, s2, s3 with the `einsum!` macro
+/// Expands to the same plan as `triangle_query_fused`
+pub fn triangle_query_einsum<A: Ord + Copy, B: Ord + Copy, C: Ord + Copy>(
+    t1: indexed_stream!(A, B, (); Clone),
+    t2: indexed_stream!(B, C, (); Clone),
+    t3: indexed_stream!(A, C, (); Clone)
+) -> SparseVec<A, SparseVec<B, Vec<C>>> {
+    einsum!("ab,bc,ac->abc", t1, t2, t3; |_, _, _| ())
+        .map(|_, a| {
+            a.map(|_, b| b.collect_indices())
+                .collect::<SparseVec<B, Vec<C>>>()
+        })
+        .collect()
+}
+
+/// Perform the triangle query on s1, s2, s3 with the generic join engine
+/// Returns the triangles (a, b, c) in lexicographic order
+pub fn triangle_query_generic<'a, I: Ord + Copy + 'a>(
+    t1: indexed_stream!(I, I, (); Clone, 'a),
+    t2: indexed_stream!(I, I, (); Clone, 'a),
+    t3: indexed_stream!(I, I, (); Clone, 'a)
+) -> Vec<Vec<I>> {
+    Query::new()
+        .atom(&["a", "b"], t1)
+        .atom(&["b", "c"], t2)
+        .atom(&["a", "c"], t3)
+        .join(&["a", "b", "c"])
+        .expect("triangle query is well-formed")
+        .collect_tuples()
+}
+
 pub fn all_combinations<A: Ord + Copy, B: Ord + Copy, C: Ord + Copy>(
     s1: &[A],
     s2: &[B],
@@ -153,4 +179,32 @@ fn test_triangle_query() {
             create_all_pairs_table(&s1, &s3),
         )
     );
+    let triangles = triangle_query_generic(
+        create_all_pairs_table(&s1, &s1),
+        create_all_pairs_table(&s1, &s1),
+        create_all_pairs_table(&s1, &s1),
+    );
+    assert_eq!(triangles.len(), s1.len() * s1.len() * s1.len());
+}
+
+#[test]
+fn test_triangle_query_generic() {
+    let s = [1, 2, 3, 4, 5, 6, 7, 8];
+    let r = create_skewed_relation(&s, &s, 3);
+    let stream = || r.stream_iter().map(|_, x| SortedVecGalloper::new(x));
+    let mut fused = Vec::new();
+    triangle_query_fused(stream(), stream(), stream())
+        .iter()
+        .for_each(|(a, bs)| bs.iter()
+            .for_each(|(b, cs)| cs.iter()
+                .for_each(|c| fused.push(vec![*a, *b, *c]))));
+    assert_eq!(triangle_query_generic(stream(), stream(), stream()), fused);
+    assert_eq!(
+        triangle_query_einsum(stream(), stream(), stream()),
+        triangle_query_fused(stream(), stream(), stream())
+    );
+
+    let trie = crate::streams::trie::Trie::from_tuples(2, r.iter().flat_map(|(a, bs)| bs.iter().map(move |b| [*a, *b])));
+    let trie_stream = || trie.stream_iter().map(|_, x| x.map(|_, _| ()));
+    assert_eq!(triangle_query_generic(trie_stream(), trie_stream(), trie_stream()), fused);
 }
@@ -1,6 +1,11 @@
+extern crate self as etch;
+
+pub use etch_macros::einsum;
+
 pub mod streams;
 pub mod rbtree;
 pub mod examples;
+pub mod query;
 
 #[cfg(test)]
 mod test {
@@ -0,0 +1,4 @@
+pub mod generic_join;
+pub mod fixpoint;
+pub mod stats;
+pub mod planner;
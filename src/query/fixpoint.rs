@@ -0,0 +1,224 @@
+use crate::{indexed_stream, rbtree::rbtree_lib::RBTree, streams::{sorted_vec::SortedVecGalloper, stream_defs::{FromStreamIterator, IndexedStream}}};
+
+/// A set of pairs `(x, y)`, stored as a tree from each `x` to the tree of its `y`s.
+/// Every `x` in the outer tree has at least one `y`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryRelation<I: Ord> {
+    tree: RBTree<I, RBTree<I, ()>>,
+    len: usize,
+}
+
+impl<I: Ord + Copy> BinaryRelation<I> {
+    pub fn new() -> Self {
+        BinaryRelation { tree: RBTree::new(), len: 0 }
+    }
+
+    /// The number of pairs in the relation
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of distinct `x`s
+    pub fn domain_len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn tree(&self) -> &RBTree<I, RBTree<I, ()>> {
+        &self.tree
+    }
+
+    /// Inserts `(x, y)`, returning whether it was absent
+    pub fn insert(&mut self, x: I, y: I) -> bool {
+        match self.tree.get_mut(&x) {
+            Some(ys) if ys.contains_key(&y) => return false,
+            Some(ys) => ys.insert(y, ()),
+            None => self.tree.insert(x, [(y, ())].into_iter().collect()),
+        }
+        self.len += 1;
+        true
+    }
+
+    pub fn contains(&self, x: I, y: I) -> bool {
+        self.tree.get(&x).is_some_and(|ys| ys.contains_key(&y))
+    }
+
+    /// Inserts every pair of `other`, returning the number of new pairs
+    pub fn extend_from(&mut self, other: &BinaryRelation<I>) -> usize {
+        let before = self.len;
+        other.for_each_pair(|x, y| { self.insert(x, y); });
+        self.len - before
+    }
+
+    pub fn for_each_pair(&self, mut f: impl FnMut(I, I)) {
+        self.stream_iter().for_each(|x, ys| ys.for_each(|y, _| f(x, y)));
+    }
+
+    pub fn to_pairs(&self) -> Vec<(I, I)> {
+        let mut result = Vec::with_capacity(self.len);
+        self.for_each_pair(|x, y| result.push((x, y)));
+        result
+    }
+
+    pub fn stream_iter(&self) -> indexed_stream!(I, I, (); Clone, '_) {
+        self.tree.stream_iter().map(|_, ys| ys.stream_iter().map(|_, _| ()))
+    }
+}
+
+impl<I: Ord + Copy> Default for BinaryRelation<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Ord + Copy> FromIterator<(I, I)> for BinaryRelation<I> {
+    fn from_iter<T: IntoIterator<Item = (I, I)>>(iter: T) -> Self {
+        let mut result = BinaryRelation::new();
+        for (x, y) in iter {
+            result.insert(x, y);
+        }
+        result
+    }
+}
+
+/// Collects a nested stream; `x`s whose inner stream is empty are left out
+impl<I: Ord + Copy, S: IndexedStream<I = I>> FromStreamIterator<I, S> for BinaryRelation<I> {
+    fn from_stream_iterator<Iter: IndexedStream<I = I, V = S>>(iter: Iter) -> Self {
+        let mut result = BinaryRelation::new();
+        result.extend_from_stream_iterator(iter);
+        result
+    }
+
+    fn extend_from_stream_iterator<Iter: IndexedStream<I = I, V = S>>(&mut self, iter: Iter) {
+        iter.for_each(|x, ys| ys.for_each(|y, _| { self.insert(x, y); }));
+    }
+}
+
+/// The composition `{(x, z) | left(x, y), right(y, z)}`.
+/// Each inner tree of `left` is intersected with the domain of `right` by galloping.
+pub fn compose<I: Ord + Copy>(left: &BinaryRelation<I>, right: &BinaryRelation<I>) -> BinaryRelation<I> {
+    let mut result = BinaryRelation::new();
+    left.stream_iter().for_each(|x, ys| {
+        ys.zip_with(right.stream_iter(), |_, zs| zs)
+            .for_each(|_, zs| zs.for_each(|z, _| { result.insert(x, z); }));
+    });
+    result
+}
+
+/// Semi-naive evaluation of a recursive rule, starting from the facts in `initial`.
+/// Each round calls `rule(full, delta)`, which should derive the tuples obtained by joining
+/// the tuples found in the previous round (`delta`) with the base relations, e.g. `compose(delta, edges)`
+/// for `path(x, z) :- path(x, y), edge(y, z)`.
+/// The derived tuples not already in `full` form the next `delta`; evaluation stops when it is empty.
+pub fn semi_naive<I: Ord + Copy>(
+    initial: BinaryRelation<I>,
+    mut rule: impl FnMut(&BinaryRelation<I>, &BinaryRelation<I>) -> BinaryRelation<I>
+) -> BinaryRelation<I> {
+    let mut full = initial.clone();
+    let mut delta = initial;
+    while !delta.is_empty() {
+        let derived = rule(&full, &delta);
+        delta = derived.stream_iter().minus_nested(full.stream_iter()).collect();
+        full.extend_from(&delta);
+    }
+    full
+}
+
+/// The pairs `(x, y)` such that `y` can be reached from `x` through one or more edges
+pub fn transitive_closure<I: Ord + Copy>(edges: &BinaryRelation<I>) -> BinaryRelation<I> {
+    semi_naive(edges.clone(), |_, delta| compose(delta, edges))
+}
+
+/// The nodes that can be reached from some node in `sources` through one or more edges, in order
+pub fn reachable<I: Ord + Copy>(edges: &BinaryRelation<I>, sources: &[I]) -> Vec<I> {
+    let mut sources = sources.to_vec();
+    sources.sort_unstable();
+    sources.dedup();
+    let start: BinaryRelation<I> = SortedVecGalloper::new(&sources)
+        .zip_with(edges.stream_iter(), |_, ys| ys)
+        .collect();
+    let closure = semi_naive(start, |_, delta| compose(delta, edges));
+    let mut result: RBTree<I, ()> = RBTree::new();
+    closure.for_each_pair(|_, y| { result.replace_or_insert(y, ()); });
+    result.keys().copied().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use quickcheck_macros::quickcheck;
+
+    use super::{compose, reachable, semi_naive, transitive_closure, BinaryRelation};
+
+    fn naive_closure(edges: &BTreeSet<(u8, u8)>) -> BTreeSet<(u8, u8)> {
+        let mut closure = edges.clone();
+        loop {
+            let derived: BTreeSet<(u8, u8)> = closure.iter()
+                .flat_map(|&(x, y)| edges.iter().filter(move |&&(y2, _)| y2 == y).map(move |&(_, z)| (x, z)))
+                .collect();
+            let before = closure.len();
+            closure.extend(derived);
+            if closure.len() == before {
+                return closure;
+            }
+        }
+    }
+
+    #[test]
+    fn test_relation() {
+        let mut rel = BinaryRelation::new();
+        assert!(rel.insert(1, 2));
+        assert!(rel.insert(1, 3));
+        assert!(!rel.insert(1, 2));
+        assert!(rel.insert(0, 5));
+        assert_eq!(rel.len(), 3);
+        assert_eq!(rel.domain_len(), 2);
+        assert!(rel.contains(1, 3));
+        assert!(!rel.contains(3, 1));
+        assert_eq!(rel.to_pairs(), vec![(0, 5), (1, 2), (1, 3)]);
+    }
+
+    #[test]
+    fn test_compose() {
+        let left: BinaryRelation<u32> = [(1, 2), (1, 3), (4, 5)].into_iter().collect();
+        let right: BinaryRelation<u32> = [(2, 7), (3, 7), (3, 8), (6, 9)].into_iter().collect();
+        assert_eq!(compose(&left, &right).to_pairs(), vec![(1, 7), (1, 8)]);
+    }
+
+    #[test]
+    fn test_transitive_closure() {
+        // A chain with a cycle at the end: 1 -> 2 -> 3 -> 4 -> 3
+        let edges: BinaryRelation<u32> = [(1, 2), (2, 3), (3, 4), (4, 3)].into_iter().collect();
+        assert_eq!(
+            transitive_closure(&edges).to_pairs(),
+            vec![(1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 3), (3, 4), (4, 3), (4, 4)]
+        );
+        assert_eq!(reachable(&edges, &[2]), vec![3, 4]);
+        assert_eq!(reachable(&edges, &[4, 1]), vec![2, 3, 4]);
+        assert_eq!(reachable(&edges, &[5]), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_semi_naive_rounds() {
+        // Each round only sees the tuples derived in the previous one
+        let edges: BinaryRelation<u32> = (0..10).map(|i| (i, i + 1)).collect();
+        let mut delta_sizes = Vec::new();
+        let closure = semi_naive(edges.clone(), |_, delta| {
+            delta_sizes.push(delta.len());
+            compose(delta, &edges)
+        });
+        assert_eq!(closure.len(), 55);
+        assert_eq!(delta_sizes, (1..=10).rev().collect::<Vec<_>>());
+    }
+
+    #[quickcheck]
+    fn test_closure_matches_naive(edges: BTreeSet<(u8, u8)>) {
+        let relation: BinaryRelation<u8> = edges.iter().cloned().collect();
+        let expected: Vec<(u8, u8)> = naive_closure(&edges).into_iter().collect();
+        assert_eq!(transitive_closure(&relation).to_pairs(), expected);
+    }
+}
@@ -0,0 +1,578 @@
+use std::{collections::BTreeMap, convert::Infallible, ops::ControlFlow};
+
+use crate::streams::{stream_defs::IndexedStream, trie::{Trie, TrieStream}};
+
+use super::planner::Plan;
+
+/// A type-erased cursor over one level of a trie.
+/// Follows the same protocol as `IndexedStream`, with `open` descending into the current entry.
+pub trait TrieCursor<'a, I> {
+    fn valid(&self) -> bool;
+
+    /// Will only be called when `valid` is true
+    fn ready(&self) -> bool;
+
+    /// Will only be called when `valid` is true
+    fn index(&self) -> I;
+
+    /// Will only be called when `valid` is true
+    fn seek(&mut self, index: I, strict: bool);
+
+    /// Will only be called when `valid` is true
+    fn next(&mut self);
+
+    /// See `IndexedStream::remaining_estimate`
+    fn remaining_estimate(&self) -> Option<usize> {
+        None
+    }
+
+    /// Opens a cursor over the level below the current entry,
+    /// or returns `None` if this is the last level.
+    /// Will only be called when `valid` and `ready` are true
+    fn open(&self) -> Option<BoxedCursor<'a, I>>;
+
+    fn boxed_clone(&self) -> BoxedCursor<'a, I>;
+}
+
+pub type BoxedCursor<'a, I> = Box<dyn TrieCursor<'a, I> + 'a>;
+
+/// A nested stream whose levels all share the index type `I`, i.e. a trie.
+/// The innermost values must be `()` (or `&()`), which marks the end of the trie.
+/// Every level must be `Clone`, since atoms are re-scanned for each binding of the variables before them.
+pub trait NestedStream<'a, I> {
+    /// The number of levels of the nested stream
+    const DEPTH: usize;
+
+    fn into_cursor(self) -> Option<BoxedCursor<'a, I>>;
+}
+
+impl<'a, I> NestedStream<'a, I> for () {
+    const DEPTH: usize = 0;
+
+    fn into_cursor(self) -> Option<BoxedCursor<'a, I>> {
+        None
+    }
+}
+
+impl<'a, I> NestedStream<'a, I> for &() {
+    const DEPTH: usize = 0;
+
+    fn into_cursor(self) -> Option<BoxedCursor<'a, I>> {
+        None
+    }
+}
+
+impl<'a, S> NestedStream<'a, S::I> for S
+where
+    S: IndexedStream + Clone + 'a,
+    S::V: NestedStream<'a, S::I>,
+{
+    const DEPTH: usize = 1 + <S::V as NestedStream<'a, S::I>>::DEPTH;
+
+    fn into_cursor(self) -> Option<BoxedCursor<'a, S::I>> {
+        Some(Box::new(StreamCursor { stream: self }))
+    }
+}
+
+/// A relation that can be scanned as a trie, one level per column
+pub trait Relation<'a, I> {
+    fn arity(&self) -> usize;
+
+    fn into_cursor(self) -> Option<BoxedCursor<'a, I>>;
+}
+
+impl<'a, S> Relation<'a, S::I> for S
+where
+    S: IndexedStream + Clone + 'a,
+    S::V: NestedStream<'a, S::I>,
+{
+    fn arity(&self) -> usize {
+        <S as NestedStream<'a, S::I>>::DEPTH
+    }
+
+    fn into_cursor(self) -> Option<BoxedCursor<'a, S::I>> {
+        NestedStream::into_cursor(self)
+    }
+}
+
+#[derive(Clone)]
+struct StreamCursor<S> {
+    stream: S,
+}
+
+impl<'a, S> TrieCursor<'a, S::I> for StreamCursor<S>
+where
+    S: IndexedStream + Clone + 'a,
+    S::V: NestedStream<'a, S::I>,
+{
+    fn valid(&self) -> bool {
+        self.stream.valid()
+    }
+
+    fn ready(&self) -> bool {
+        self.stream.ready()
+    }
+
+    fn index(&self) -> S::I {
+        self.stream.index()
+    }
+
+    fn seek(&mut self, index: S::I, strict: bool) {
+        self.stream.seek(index, strict);
+    }
+
+    fn next(&mut self) {
+        self.stream.next();
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        self.stream.remaining_estimate()
+    }
+
+    fn open(&self) -> Option<BoxedCursor<'a, S::I>> {
+        NestedStream::into_cursor(self.stream.value())
+    }
+
+    fn boxed_clone(&self) -> BoxedCursor<'a, S::I> {
+        Box::new(self.clone())
+    }
+}
+
+impl<'a, I: Ord + Copy + 'a> TrieCursor<'a, I> for TrieStream<'a, I> {
+    fn valid(&self) -> bool {
+        IndexedStream::valid(self)
+    }
+
+    fn ready(&self) -> bool {
+        IndexedStream::ready(self)
+    }
+
+    fn index(&self) -> I {
+        IndexedStream::index(self)
+    }
+
+    fn seek(&mut self, index: I, strict: bool) {
+        IndexedStream::seek(self, index, strict);
+    }
+
+    fn next(&mut self) {
+        IndexedStream::next(self);
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        IndexedStream::remaining_estimate(self)
+    }
+
+    fn open(&self) -> Option<BoxedCursor<'a, I>> {
+        if self.is_last_level() {
+            None
+        } else {
+            Some(Box::new(self.value()))
+        }
+    }
+
+    fn boxed_clone(&self) -> BoxedCursor<'a, I> {
+        Box::new(*self)
+    }
+}
+
+impl<'a, I: Ord + Copy + 'a> Relation<'a, I> for &'a Trie<I> {
+    fn arity(&self) -> usize {
+        Trie::arity(self)
+    }
+
+    fn into_cursor(self) -> Option<BoxedCursor<'a, I>> {
+        Some(Box::new(self.stream_iter()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    /// An atom mentions a variable that is missing from the variable order
+    UnknownVariable { atom: usize, var: &'static str },
+    /// A variable in the variable order does not appear in any atom
+    UnboundVariable(&'static str),
+    /// A variable appears twice in the variable order
+    RepeatedVariable(&'static str),
+    /// The columns of an atom are not ordered consistently with the variable order
+    ColumnOrder { atom: usize },
+    /// An atom lists a different number of variables than its relation has levels
+    Arity { atom: usize, expected: usize, found: usize },
+}
+
+struct Atom<'a, I> {
+    vars: Vec<&'static str>,
+    arity: usize,
+    cursor: Option<BoxedCursor<'a, I>>,
+}
+
+/// A conjunctive query, given as a list of atoms.
+/// Each atom is a relation (e.g. a nested stream) together with the variables bound by its levels.
+pub struct Query<'a, I> {
+    atoms: Vec<Atom<'a, I>>,
+}
+
+impl<'a, I: Ord + Copy> Default for Query<'a, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, I: Ord + Copy> Query<'a, I> {
+    pub fn new() -> Self {
+        Query { atoms: Vec::new() }
+    }
+
+    /// Adds the atom `relation(vars[0], vars[1], ...)` to the query,
+    /// where `vars[k]` is bound by the `k`-th level of `relation`.
+    pub fn atom<R: Relation<'a, I>>(mut self, vars: &[&'static str], relation: R) -> Self {
+        self.atoms.push(Atom {
+            vars: vars.to_vec(),
+            arity: relation.arity(),
+            cursor: relation.into_cursor(),
+        });
+        self
+    }
+
+    /// Prepares the query for evaluation with the given variable order.
+    /// The columns of every atom must appear in the same relative order as in `order`,
+    /// since a trie can only be traversed from its outermost level inwards.
+    pub fn join(self, order: &[&'static str]) -> Result<GenericJoin<'a, I>, QueryError> {
+        for (d, var) in order.iter().enumerate() {
+            if order[..d].contains(var) {
+                return Err(QueryError::RepeatedVariable(var));
+            }
+        }
+        let mut participants: Vec<Vec<Participant>> = vec![Vec::new(); order.len()];
+        for (a, atom) in self.atoms.iter().enumerate() {
+            if atom.vars.len() != atom.arity {
+                return Err(QueryError::Arity { atom: a, expected: atom.vars.len(), found: atom.arity });
+            }
+            let mut last = None;
+            for (column, var) in atom.vars.iter().enumerate() {
+                let d = order.iter().position(|v| v == var)
+                    .ok_or(QueryError::UnknownVariable { atom: a, var })?;
+                if last.is_some_and(|last| last >= d) {
+                    return Err(QueryError::ColumnOrder { atom: a });
+                }
+                // The cursor can only be consumed if it was opened by the previous level of the join
+                // (or is the root and this is the first level); otherwise it is re-scanned for
+                // every binding of the variables in between.
+                let consume = last.map_or(d == 0, |last| last + 1 == d);
+                last = Some(d);
+                participants[d].push(Participant { atom: a, column, consume });
+            }
+        }
+        if let Some(d) = participants.iter().position(|atoms| atoms.is_empty()) {
+            return Err(QueryError::UnboundVariable(order[d]));
+        }
+        let cursors = self.atoms.into_iter()
+            .map(|atom| {
+                let mut levels: Vec<_> = (0..atom.arity).map(|_| None).collect();
+                if let Some(first) = levels.first_mut() {
+                    *first = atom.cursor;
+                }
+                levels
+            })
+            .collect();
+        let memos = (0..order.len()).map(|_| None).collect();
+        Ok(GenericJoin { order: order.to_vec(), participants, cursors, memos })
+    }
+
+    /// Prepares the query for evaluation with the variable order of `plan`,
+    /// materializing the results of the join below each of the plan's materialization points
+    /// so that they are computed once per binding of their key and replayed afterwards.
+    pub fn join_planned(self, plan: &Plan) -> Result<GenericJoin<'a, I>, QueryError> {
+        let order = plan.order();
+        let mut join = self.join(&order)?;
+        for m in plan.materializations() {
+            let key = m.key.iter()
+                .map(|var| order.iter().position(|v| v == var).expect("materialization keys are planned variables"))
+                .collect();
+            join.memos[m.after] = Some(Memo { key, results: BTreeMap::new() });
+        }
+        Ok(join)
+    }
+}
+
+/// The materialized results of the join below some depth
+struct Memo<I> {
+    /// The positions in the tuple of the variables the results depend on
+    key: Vec<usize>,
+    /// For each binding of the key, the suffixes of the result tuples, concatenated
+    results: BTreeMap<Vec<I>, Vec<I>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Participant {
+    atom: usize,
+    column: usize,
+    consume: bool,
+}
+
+/// Evaluates a conjunctive query with Generic Join:
+/// variables are bound one at a time in the chosen order, and at every level
+/// the candidate values are the leapfrog intersection of the atoms containing that variable.
+pub struct GenericJoin<'a, I> {
+    order: Vec<&'static str>,
+    /// `participants[d]` lists the atoms that contain the variable `order[d]`
+    participants: Vec<Vec<Participant>>,
+    /// `cursors[a][k]` is the cursor over the `k`-th level of atom `a` under the current bindings
+    cursors: Vec<Vec<Option<BoxedCursor<'a, I>>>>,
+    /// `memos[d]` caches the results of the join of the variables from `order[d]` on, if planned
+    memos: Vec<Option<Memo<I>>>,
+}
+
+impl<'a, I: Ord + Copy> GenericJoin<'a, I> {
+    /// The variable order; result tuples list their values in this order.
+    pub fn variables(&self) -> &[&'static str] {
+        &self.order
+    }
+
+    /// Calls `f` on every result tuple in lexicographic order, stopping early on `Break`.
+    pub fn try_for_each<R>(mut self, mut f: impl FnMut(&[I]) -> ControlFlow<R>) -> ControlFlow<R> {
+        let mut tuple = Vec::with_capacity(self.order.len());
+        search(&mut self.cursors, &self.participants, &mut self.memos, &mut tuple, &mut f)
+    }
+
+    pub fn for_each(self, mut f: impl FnMut(&[I])) {
+        let _ = self.try_for_each(|tuple| {
+            f(tuple);
+            ControlFlow::<()>::Continue(())
+        });
+    }
+
+    pub fn fold<B>(self, init: B, mut f: impl FnMut(B, &[I]) -> B) -> B {
+        let mut acc = Some(init);
+        self.for_each(|tuple| acc = acc.take().map(|acc| f(acc, tuple)));
+        acc.expect("fold accumulator is always restored")
+    }
+
+    pub fn count(self) -> usize {
+        self.fold(0, |acc, _| acc + 1)
+    }
+
+    /// Determines whether the query has at least one result, stopping at the first one.
+    pub fn exists(self) -> bool {
+        self.try_for_each(|_| ControlFlow::Break(())).is_break()
+    }
+
+    pub fn collect_tuples(self) -> Vec<Vec<I>> {
+        let mut result = Vec::new();
+        self.for_each(|tuple| result.push(tuple.to_vec()));
+        result
+    }
+}
+
+fn search<'a, I: Ord + Copy, R>(
+    cursors: &mut [Vec<Option<BoxedCursor<'a, I>>>],
+    participants: &[Vec<Participant>],
+    memos: &mut [Option<Memo<I>>],
+    tuple: &mut Vec<I>,
+    f: &mut dyn FnMut(&[I]) -> ControlFlow<R>,
+) -> ControlFlow<R> {
+    let depth = tuple.len();
+    if let Some(mut memo) = memos.get_mut(depth).and_then(Option::take) {
+        let key: Vec<I> = memo.key.iter().map(|&d| tuple[d]).collect();
+        // The results are collected in full on the first visit, even if `f` breaks early,
+        // so that they can be replayed on the next one
+        let rows = memo.results.entry(key).or_insert_with(|| {
+            let mut rows = Vec::new();
+            let _ = search::<I, Infallible>(cursors, participants, memos, tuple, &mut |t| {
+                rows.extend_from_slice(&t[depth..]);
+                ControlFlow::Continue(())
+            });
+            rows
+        });
+        let mut result = ControlFlow::Continue(());
+        for row in rows.chunks(participants.len()) {
+            tuple.extend_from_slice(row);
+            result = f(tuple);
+            tuple.truncate(depth);
+            if result.is_break() {
+                break;
+            }
+        }
+        memos[depth] = Some(memo);
+        return result;
+    }
+    let Some((atoms, rest)) = participants.split_first() else {
+        return f(tuple);
+    };
+    let mut level: Vec<BoxedCursor<'a, I>> = atoms.iter()
+        .map(|p| {
+            let slot = &mut cursors[p.atom][p.column];
+            let cursor = if p.consume { slot.take() } else { slot.as_ref().map(|cursor| cursor.boxed_clone()) };
+            cursor.expect("cursor is opened before its level is reached")
+        })
+        .collect();
+    leapfrog(&mut level, |i, level| {
+        for (cursor, p) in level.iter().zip(atoms) {
+            if let Some(slot) = cursors[p.atom].get_mut(p.column + 1) {
+                *slot = cursor.open();
+            }
+        }
+        tuple.push(i);
+        let result = search(cursors, rest, memos, tuple, f);
+        tuple.pop();
+        result
+    })
+}
+
+/// Intersects the cursors of one level, calling `f` on every common index
+/// while all cursors are positioned on it.
+/// After a match, only the cursor expected to have the fewest entries (if any cursor can tell)
+/// is advanced; the others are then sought directly to its next index.
+fn leapfrog<'a, I: Ord + Copy, R>(
+    level: &mut [BoxedCursor<'a, I>],
+    mut f: impl FnMut(I, &[BoxedCursor<'a, I>]) -> ControlFlow<R>,
+) -> ControlFlow<R> {
+    let lead = level.iter()
+        .enumerate()
+        .filter_map(|(k, cursor)| Some((cursor.remaining_estimate()?, k)))
+        .min()
+        .map(|(_, k)| k);
+    while level.iter().all(|cursor| cursor.valid()) {
+        let max = level.iter().map(|cursor| cursor.index()).max().expect("every variable has an atom");
+        let mut aligned = true;
+        for cursor in level.iter_mut() {
+            if cursor.index() < max {
+                cursor.seek(max, false);
+                aligned = false;
+            }
+        }
+        if !aligned {
+            continue;
+        }
+        if level.iter().all(|cursor| cursor.ready()) {
+            f(max, level)?;
+            match lead {
+                Some(k) => level[k].next(),
+                None => level.iter_mut().for_each(|cursor| cursor.next()),
+            }
+        } else {
+            level.iter_mut()
+                .filter(|cursor| !cursor.ready())
+                .for_each(|cursor| cursor.seek(max, false));
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::{rbtree::rbtree_lib::RBTree, streams::{csr_mat::SparseCSRMat, sorted_vec::SortedVecGalloper, sparse_vec::SparseVec, stream_defs::{IndexedStream, IntoStreamIterator}, trie::Trie}};
+
+    use super::{Query, QueryError};
+
+    fn to_sparse_vec(edges: &BTreeSet<(u8, u8)>) -> SparseVec<u8, Vec<u8>> {
+        let mut result: SparseVec<u8, Vec<u8>> = SparseVec::empty();
+        for &(a, b) in edges.iter() {
+            if result.inds.last() != Some(&a) {
+                result.inds.push(a);
+                result.vals.push(Vec::new());
+            }
+            result.vals.last_mut().unwrap().push(b);
+        }
+        result
+    }
+
+    #[test]
+    fn test_path_query() {
+        let r = SparseVec::from_iter([(1, vec![2, 3]), (2, vec![3])]);
+        let s: RBTree<u32, RBTree<u32, ()>> = [
+            (2, [(5, ())].into_iter().collect()),
+            (3, [(6, ()), (7, ())].into_iter().collect()),
+        ].into_iter().collect();
+        let result = Query::new()
+            .atom(&["x", "y"], r.stream_iter().map(|_, v| SortedVecGalloper::new(v)))
+            .atom(&["y", "z"], s.stream_iter().map(|_, t| t.stream_iter()))
+            .join(&["x", "y", "z"])
+            .unwrap()
+            .collect_tuples();
+        assert_eq!(result, vec![vec![1, 2, 5], vec![1, 3, 6], vec![1, 3, 7], vec![2, 3, 6], vec![2, 3, 7]]);
+    }
+
+    #[test]
+    fn test_csr_relation() {
+        let mat = SparseCSRMat::from_iter([(0, 1, 1.0), (1, 2, 2.0), (2, 0, 3.0)]);
+        let edges = || mat.into_stream_iterator().map(|_, row| row.map(|_, _| ()));
+        let triangles = Query::new()
+            .atom(&["a", "b"], edges())
+            .atom(&["b", "c"], edges())
+            .atom(&["a", "c"], edges())
+            .join(&["a", "b", "c"])
+            .unwrap();
+        // 0 -> 1 -> 2 with the closing edge 0 -> 2 missing
+        assert_eq!(triangles.count(), 0);
+        let paths = Query::new()
+            .atom(&["a", "b"], edges())
+            .atom(&["b", "c"], edges())
+            .atom(&["c", "a2"], edges())
+            .join(&["a", "b", "c", "a2"])
+            .unwrap();
+        assert_eq!(paths.count(), 3);
+    }
+
+    #[test]
+    fn test_query_errors() {
+        let r = SparseVec::from_iter([(1, vec![2])]);
+        let rel = || r.stream_iter().map(|_, v| SortedVecGalloper::new(v));
+        assert_eq!(
+            Query::new().atom(&["x", "y"], rel()).join(&["y", "x"]).err(),
+            Some(QueryError::ColumnOrder { atom: 0 })
+        );
+        assert_eq!(
+            Query::new().atom(&["x"], rel()).join(&["x"]).err(),
+            Some(QueryError::Arity { atom: 0, expected: 1, found: 2 })
+        );
+        assert_eq!(
+            Query::new().atom(&["x", "y"], rel()).join(&["x"]).err(),
+            Some(QueryError::UnknownVariable { atom: 0, var: "y" })
+        );
+        assert_eq!(
+            Query::new().atom(&["x", "y"], rel()).join(&["x", "y", "z"]).err(),
+            Some(QueryError::UnboundVariable("z"))
+        );
+    }
+
+    #[quickcheck]
+    fn test_triangles(r: BTreeSet<(u8, u8)>, s: BTreeSet<(u8, u8)>, t: BTreeSet<(u8, u8)>) {
+        let (vec_r, vec_s, vec_t) = (to_sparse_vec(&r), to_sparse_vec(&s), to_sparse_vec(&t));
+        let result = Query::new()
+            .atom(&["a", "b"], vec_r.stream_iter().map(|_, v| SortedVecGalloper::new(v)))
+            .atom(&["b", "c"], vec_s.stream_iter().map(|_, v| SortedVecGalloper::new(v)))
+            .atom(&["a", "c"], vec_t.stream_iter().map(|_, v| SortedVecGalloper::new(v)))
+            .join(&["a", "b", "c"])
+            .unwrap()
+            .collect_tuples();
+        let mut expected = Vec::new();
+        for &(a, b) in r.iter() {
+            for &(_, c) in s.range((b, 0)..=(b, u8::MAX)) {
+                if t.contains(&(a, c)) {
+                    expected.push(vec![a, b, c]);
+                }
+            }
+        }
+        expected.sort();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_trie_join() {
+        let edges = [[1, 2], [2, 3], [1, 3], [3, 4], [2, 4]];
+        let forward = Trie::from_tuples(2, edges);
+        let result = Query::new()
+            .atom(&["a", "b"], &forward)
+            .atom(&["b", "c"], &forward)
+            .atom(&["a", "c"], &forward)
+            .join(&["a", "b", "c"])
+            .unwrap()
+            .collect_tuples();
+        assert_eq!(result, vec![vec![1, 2, 3], vec![2, 3, 4]]);
+    }
+}
@@ -0,0 +1,320 @@
+use std::fmt;
+
+use super::{generic_join::QueryError, stats::RelationStats};
+
+/// Binding one variable of the plan
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanStep {
+    pub var: &'static str,
+    /// The atoms intersected to bind the variable, with the column holding it
+    pub atoms: Vec<(usize, usize)>,
+    /// The estimated number of values per binding of the previous variables:
+    /// the smallest average fanout among `atoms`
+    pub fanout: f64,
+    /// The estimated number of bindings of the variables up to and including this one
+    pub estimate: f64,
+}
+
+/// A point of the plan where the rest of the join only depends on some of the variables bound so far,
+/// so its result can be materialized once per binding of those variables and reused
+#[derive(Debug, Clone, PartialEq)]
+pub struct Materialization {
+    /// The number of variables bound before the materialized suffix
+    pub after: usize,
+    /// The variables the suffix depends on
+    pub key: Vec<&'static str>,
+    /// The atoms joined in the suffix
+    pub atoms: Vec<usize>,
+    /// The estimated number of times each materialized result is reused
+    pub reuse: f64,
+}
+
+/// A variable order for a conjunctive query, together with the estimates that led to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    steps: Vec<PlanStep>,
+    materializations: Vec<Materialization>,
+}
+
+impl Plan {
+    /// The variable order, to be passed to `Query::join`
+    pub fn order(&self) -> Vec<&'static str> {
+        self.steps.iter().map(|step| step.var).collect()
+    }
+
+    pub fn steps(&self) -> &[PlanStep] {
+        &self.steps
+    }
+
+    pub fn materializations(&self) -> &[Materialization] {
+        &self.materializations
+    }
+
+    /// The estimated number of results of the query
+    pub fn estimate(&self) -> f64 {
+        self.steps.last().map_or(1.0, |step| step.estimate)
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "order: {}", self.order().join(", "))?;
+        for (d, step) in self.steps.iter().enumerate() {
+            let atoms: Vec<String> = step.atoms.iter()
+                .map(|(atom, column)| format!("atom {atom} column {column}"))
+                .collect();
+            writeln!(
+                f,
+                "  {}. {}: intersect {}; fanout ~{:.1}, ~{:.1} bindings",
+                d + 1, step.var, atoms.join(", "), step.fanout, step.estimate
+            )?;
+        }
+        for m in &self.materializations {
+            writeln!(
+                f,
+                "  materialize atoms {:?} after {}, keyed by ({}); reused ~{:.1} times",
+                m.atoms, self.steps[m.after - 1].var, m.key.join(", "), m.reuse
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Picks a variable order for a conjunctive query from the statistics of its atoms.
+///
+/// Variables are chosen greedily: among the variables whose atoms have all their earlier
+/// columns bound (as required by `Query::join`), the one with the smallest estimated fanout
+/// comes first, preferring variables shared by more atoms on ties.
+pub struct Planner<'s> {
+    atoms: Vec<(Vec<&'static str>, &'s RelationStats)>,
+}
+
+impl<'s> Default for Planner<'s> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'s> Planner<'s> {
+    pub fn new() -> Self {
+        Planner { atoms: Vec::new() }
+    }
+
+    /// Adds the atom binding `vars[k]` to the `k`-th level of a relation with the given statistics
+    pub fn atom(mut self, vars: &[&'static str], stats: &'s RelationStats) -> Self {
+        self.atoms.push((vars.to_vec(), stats));
+        self
+    }
+
+    pub fn plan(&self) -> Result<Plan, QueryError> {
+        let mut vars: Vec<&'static str> = Vec::new();
+        for (a, (atom_vars, stats)) in self.atoms.iter().enumerate() {
+            if atom_vars.len() != stats.arity() {
+                return Err(QueryError::Arity { atom: a, expected: atom_vars.len(), found: stats.arity() });
+            }
+            for (column, var) in atom_vars.iter().enumerate() {
+                if atom_vars[..column].contains(var) {
+                    return Err(QueryError::RepeatedVariable(var));
+                }
+                if !vars.contains(var) {
+                    vars.push(var);
+                }
+            }
+        }
+
+        let mut steps: Vec<PlanStep> = Vec::new();
+        let mut bound: Vec<&'static str> = Vec::new();
+        let mut estimate = 1.0;
+        while bound.len() < vars.len() {
+            let mut best: Option<PlanStep> = None;
+            for &var in vars.iter().filter(|v| !bound.contains(v)) {
+                let Some(atoms) = self.available(var, &bound) else { continue };
+                let fanout = atoms.iter()
+                    .map(|&(atom, column)| self.atoms[atom].1.fanout(column))
+                    .fold(f64::INFINITY, f64::min);
+                let better = best.as_ref().is_none_or(|best| {
+                    fanout < best.fanout || (fanout == best.fanout && atoms.len() > best.atoms.len())
+                });
+                if better {
+                    best = Some(PlanStep { var, atoms, fanout, estimate: estimate * fanout });
+                }
+            }
+            let step = best.ok_or_else(|| QueryError::ColumnOrder { atom: self.blocked_atom(&bound) })?;
+            estimate = step.estimate;
+            bound.push(step.var);
+            steps.push(step);
+        }
+
+        let materializations = (1..steps.len())
+            .filter_map(|after| self.materialization(&steps, after))
+            .collect();
+        Ok(Plan { steps, materializations })
+    }
+
+    /// The atoms containing `var` with the column holding it,
+    /// or `None` if some atom has an unbound column before `var`
+    fn available(&self, var: &'static str, bound: &[&'static str]) -> Option<Vec<(usize, usize)>> {
+        let mut atoms = Vec::new();
+        for (a, (atom_vars, _)) in self.atoms.iter().enumerate() {
+            if let Some(column) = atom_vars.iter().position(|v| *v == var) {
+                if !atom_vars[..column].iter().all(|v| bound.contains(v)) {
+                    return None;
+                }
+                atoms.push((a, column));
+            }
+        }
+        Some(atoms)
+    }
+
+    /// The first atom whose next unbound column can not be bound yet
+    fn blocked_atom(&self, bound: &[&'static str]) -> usize {
+        self.atoms.iter()
+            .position(|(atom_vars, _)| atom_vars.iter()
+                .find(|v| !bound.contains(v))
+                .is_some_and(|&next| self.available(next, bound).is_none()))
+            .unwrap_or(0)
+    }
+
+    fn materialization(&self, steps: &[PlanStep], after: usize) -> Option<Materialization> {
+        let prefix: Vec<&'static str> = steps[..after].iter().map(|step| step.var).collect();
+        let suffix: Vec<&'static str> = steps[after..].iter().map(|step| step.var).collect();
+        let atoms: Vec<usize> = (0..self.atoms.len())
+            .filter(|&a| self.atoms[a].0.iter().any(|v| suffix.contains(v)))
+            .collect();
+        let key: Vec<&'static str> = prefix.iter()
+            .filter(|v| atoms.iter().any(|&a| self.atoms[a].0.contains(v)))
+            .copied()
+            .collect();
+        if key.len() == prefix.len() {
+            return None;
+        }
+        // The distinct bindings of the key are bounded by the distinct values of each of its variables
+        let domain = |var: &'static str| self.atoms.iter()
+            .filter_map(|(atom_vars, stats)| atom_vars.iter().position(|v| *v == var).map(|column| stats.distinct[column]))
+            .min()
+            .unwrap_or(0) as f64;
+        let prefix_estimate = steps[after - 1].estimate;
+        let key_estimate = key.iter().map(|&v| domain(v)).product::<f64>().min(prefix_estimate);
+        let reuse = prefix_estimate / key_estimate;
+        (reuse > 1.0).then_some(Materialization { after, key, atoms, reuse })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::ControlFlow;
+
+    use crate::{
+        query::{generic_join::{Query, QueryError}, stats::{RelationStats, Statistics}},
+        streams::{sparse_vec::SparseVec, trie::Trie},
+    };
+
+    use super::Planner;
+
+    #[test]
+    fn test_triangle_plan() {
+        let edges = Trie::from_tuples(2, [[1, 2], [2, 3], [1, 3], [3, 4], [2, 4]]);
+        let stats = edges.stats();
+        let plan = Planner::new()
+            .atom(&["a", "b"], &stats)
+            .atom(&["b", "c"], &stats)
+            .atom(&["a", "c"], &stats)
+            .plan()
+            .unwrap();
+        // The column orders leave no choice
+        assert_eq!(plan.order(), vec!["a", "b", "c"]);
+        assert_eq!(plan.steps()[1].atoms, vec![(0, 1), (1, 0)]);
+        assert!(plan.materializations().is_empty());
+        let triangles = Query::new()
+            .atom(&["a", "b"], &edges)
+            .atom(&["b", "c"], &edges)
+            .atom(&["a", "c"], &edges)
+            .join(&plan.order())
+            .unwrap()
+            .count();
+        assert_eq!(triangles, 2);
+    }
+
+    #[test]
+    fn test_smaller_first() {
+        let big = SparseVec::from_iter((0..100).map(|i| (i, ()))).stats();
+        let small = SparseVec::from_iter([(3, ()), (5, ())]).stats();
+        let pairs = Trie::from_tuples(2, (0..100).map(|i| [i, i % 7])).stats();
+        let plan = Planner::new()
+            .atom(&["x"], &big)
+            .atom(&["y"], &small)
+            .atom(&["x", "z"], &pairs)
+            .plan()
+            .unwrap();
+        assert_eq!(plan.order(), vec!["y", "x", "z"]);
+        assert_eq!(plan.estimate(), 200.0);
+        // `x` and `z` do not depend on `y` at all, and once `x` is bound `z` no longer depends on `y`
+        let m = plan.materializations();
+        assert_eq!(m.len(), 2);
+        assert_eq!((m[0].after, m[0].key.clone(), m[0].atoms.clone()), (1, vec![], vec![0, 2]));
+        assert_eq!((m[1].after, m[1].key.clone(), m[1].atoms.clone()), (2, vec!["x"], vec![2]));
+        assert_eq!(m[1].reuse, 2.0);
+        let explain = plan.to_string();
+        assert!(explain.starts_with("order: y, x, z\n"));
+        assert!(explain.contains("materialize atoms [2] after x, keyed by (x)"));
+    }
+
+    #[test]
+    fn test_path_materialization() {
+        let r = RelationStats { distinct: vec![10, 1000], degrees: Vec::new() };
+        let s = RelationStats { distinct: vec![20, 40], degrees: Vec::new() };
+        let plan = Planner::new()
+            .atom(&["x", "y"], &r)
+            .atom(&["y", "z"], &s)
+            .plan()
+            .unwrap();
+        assert_eq!(plan.order(), vec!["x", "y", "z"]);
+        // The ~200 bindings of (x, y) only have 20 distinct y's
+        assert_eq!(plan.estimate(), 400.0);
+        let m = &plan.materializations()[0];
+        assert_eq!((m.after, m.key.clone(), m.reuse), (2, vec!["y"], 10.0));
+    }
+
+    #[test]
+    fn test_planned_join() {
+        // Many x's share each y, so the z's of each y are only joined once
+        let r = Trie::from_tuples(2, (0..60).map(|x| [x, 100 + x % 3]));
+        let s = Trie::from_tuples(2, (100..103).flat_map(|y| (0..y - 98).map(move |z| [y, 200 + z])));
+        let (r_stats, s_stats) = (r.stats(), s.stats());
+        let plan = Planner::new()
+            .atom(&["x", "y"], &r_stats)
+            .atom(&["y", "z"], &s_stats)
+            .plan()
+            .unwrap();
+        assert_eq!(plan.order(), vec!["x", "y", "z"]);
+        assert_eq!(plan.materializations()[0].key, vec!["y"]);
+        let query = || Query::new().atom(&["x", "y"], &r).atom(&["y", "z"], &s);
+        let expected = query().join(&plan.order()).unwrap().collect_tuples();
+        assert_eq!(expected.len(), 20 * (2 + 3 + 4));
+        assert_eq!(query().join_planned(&plan).unwrap().collect_tuples(), expected);
+        // Stopping early in the middle of a materialized suffix
+        let mut first = Vec::new();
+        let _ = query().join_planned(&plan).unwrap().try_for_each(|tuple| {
+            first.push(tuple.to_vec());
+            if first.len() == 12 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        });
+        assert_eq!(first, expected[..12]);
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        let stats = RelationStats { distinct: vec![2, 3], degrees: Vec::new() };
+        assert_eq!(
+            Planner::new().atom(&["x", "y"], &stats).atom(&["y", "x"], &stats).plan().err(),
+            Some(QueryError::ColumnOrder { atom: 0 })
+        );
+        assert_eq!(
+            Planner::new().atom(&["x"], &stats).plan().err(),
+            Some(QueryError::Arity { atom: 0, expected: 1, found: 2 })
+        );
+        assert_eq!(
+            Planner::new().atom(&["x", "x"], &stats).plan().err(),
+            Some(QueryError::RepeatedVariable("x"))
+        );
+    }
+}
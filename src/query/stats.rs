@@ -0,0 +1,214 @@
+use crate::{rbtree::rbtree_lib::RBTree, streams::{csr_mat::SparseCSRMat, sparse_vec::SparseVec, trie::Trie}};
+
+use super::{fixpoint::BinaryRelation, generic_join::{BoxedCursor, Relation}};
+
+/// A histogram of the number of children of the entries at one level of a relation.
+/// Bucket `b` counts the entries whose degree has `b` significant bits,
+/// i.e. bucket 0 holds degree 0 and bucket `b > 0` holds degrees in `[2^(b - 1), 2^b)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DegreeHistogram {
+    buckets: Vec<usize>,
+    entries: usize,
+    total: usize,
+    max: usize,
+}
+
+impl DegreeHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, degree: usize) {
+        let bucket = (usize::BITS - degree.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.entries += 1;
+        self.total += degree;
+        self.max = self.max.max(degree);
+    }
+
+    pub fn buckets(&self) -> &[usize] {
+        &self.buckets
+    }
+
+    /// The number of entries recorded
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.entries == 0 {
+            0.0
+        } else {
+            self.total as f64 / self.entries as f64
+        }
+    }
+}
+
+impl FromIterator<usize> for DegreeHistogram {
+    fn from_iter<T: IntoIterator<Item = usize>>(iter: T) -> Self {
+        let mut result = DegreeHistogram::new();
+        for degree in iter {
+            result.record(degree);
+        }
+        result
+    }
+}
+
+/// Statistics of a relation seen as a trie, one level per column
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelationStats {
+    /// `distinct[l]` is the number of entries at level `l`,
+    /// i.e. the number of distinct prefixes of length `l + 1`
+    pub distinct: Vec<usize>,
+    /// `degrees[l]` is the histogram of the number of children of the entries at level `l`,
+    /// for every level but the last
+    pub degrees: Vec<DegreeHistogram>,
+}
+
+impl RelationStats {
+    pub fn arity(&self) -> usize {
+        self.distinct.len()
+    }
+
+    /// The number of tuples in the relation
+    pub fn cardinality(&self) -> usize {
+        self.distinct.last().copied().unwrap_or(0)
+    }
+
+    /// The average number of entries at `level` per entry of the level above it
+    /// (for level 0, the number of entries)
+    pub fn fanout(&self, level: usize) -> f64 {
+        match level.checked_sub(1) {
+            None => self.distinct[0] as f64,
+            Some(_) if self.distinct[level] == 0 => 0.0,
+            Some(parent) => self.distinct[level] as f64 / self.distinct[parent] as f64,
+        }
+    }
+
+    /// Collects the statistics of any relation by scanning it.
+    /// Entries whose inner streams are empty are not counted.
+    pub fn from_relation<'a, I, R: Relation<'a, I>>(relation: R) -> Self {
+        fn visit<I>(cursor: Option<BoxedCursor<'_, I>>, level: usize, stats: &mut RelationStats) -> usize {
+            let Some(mut cursor) = cursor else { return 0 };
+            let mut entries = 0;
+            while cursor.valid() {
+                if cursor.ready() {
+                    if level + 1 < stats.arity() {
+                        // Entries without children are not the prefix of any tuple
+                        let degree = visit(cursor.open(), level + 1, stats);
+                        if degree > 0 {
+                            stats.degrees[level].record(degree);
+                            entries += 1;
+                        }
+                    } else {
+                        entries += 1;
+                    }
+                }
+                cursor.next();
+            }
+            stats.distinct[level] += entries;
+            entries
+        }
+        let arity = relation.arity();
+        let mut stats = RelationStats {
+            distinct: vec![0; arity],
+            degrees: vec![DegreeHistogram::new(); arity.saturating_sub(1)],
+        };
+        if arity > 0 {
+            visit(relation.into_cursor(), 0, &mut stats);
+        }
+        stats
+    }
+}
+
+/// Relations that can report their statistics without a full scan of the data
+pub trait Statistics {
+    fn stats(&self) -> RelationStats;
+}
+
+impl<I, T> Statistics for SparseVec<I, T> {
+    fn stats(&self) -> RelationStats {
+        RelationStats { distinct: vec![self.len()], degrees: Vec::new() }
+    }
+}
+
+impl<K: Ord, V> Statistics for RBTree<K, V> {
+    fn stats(&self) -> RelationStats {
+        RelationStats { distinct: vec![self.len()], degrees: Vec::new() }
+    }
+}
+
+impl<T> Statistics for SparseCSRMat<T> {
+    fn stats(&self) -> RelationStats {
+        let degrees: DegreeHistogram = (0..self.rows())
+            .map(|row| self.row_len(row))
+            .filter(|&len| len > 0)
+            .collect();
+        RelationStats { distinct: vec![degrees.entries(), self.nnz()], degrees: vec![degrees] }
+    }
+}
+
+impl<I: Ord + Copy> Statistics for Trie<I> {
+    fn stats(&self) -> RelationStats {
+        RelationStats {
+            distinct: (0..self.arity()).map(|l| self.distinct_prefixes(l)).collect(),
+            degrees: (0..self.arity() - 1).map(|l| self.degrees(l).collect()).collect(),
+        }
+    }
+}
+
+impl<I: Ord + Copy> Statistics for BinaryRelation<I> {
+    fn stats(&self) -> RelationStats {
+        let degrees: DegreeHistogram = self.tree().values().map(|ys| ys.len()).collect();
+        RelationStats { distinct: vec![self.domain_len(), self.len()], degrees: vec![degrees] }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        query::fixpoint::BinaryRelation,
+        streams::{csr_mat::SparseCSRMat, sorted_vec::SortedVecGalloper, sparse_vec::SparseVec, stream_defs::{IndexedStream, IntoStreamIterator}, trie::Trie},
+    };
+
+    use super::{DegreeHistogram, RelationStats, Statistics};
+
+    #[test]
+    fn test_histogram() {
+        let hist: DegreeHistogram = [1, 1, 2, 3, 4, 9, 0].into_iter().collect();
+        assert_eq!(hist.buckets(), &[1, 2, 2, 1, 1]);
+        assert_eq!(hist.max(), 9);
+        assert_eq!(hist.entries(), 7);
+        assert_eq!(hist.mean(), 20.0 / 7.0);
+    }
+
+    #[test]
+    fn test_stats_agree() {
+        let pairs = [(0, 1), (0, 2), (0, 3), (2, 1), (5, 0), (5, 4)];
+        let mat = SparseCSRMat::from_iter(pairs.iter().map(|&(i, j)| (i, j, ())));
+        let relation: BinaryRelation<usize> = pairs.iter().cloned().collect();
+        let trie = Trie::from_tuples(2, pairs.iter().map(|&(i, j)| [i, j]));
+        let scanned = RelationStats::from_relation(mat.into_stream_iterator());
+        assert_eq!(scanned.distinct, vec![3, 6]);
+        assert_eq!(scanned.degrees[0].buckets(), &[0, 1, 2]);
+        assert_eq!(mat.stats(), scanned);
+        assert_eq!(relation.stats(), scanned);
+        assert_eq!(trie.stats(), scanned);
+        assert_eq!(scanned.cardinality(), 6);
+        assert_eq!(scanned.fanout(0), 3.0);
+        assert_eq!(scanned.fanout(1), 2.0);
+
+        let vec = SparseVec::from_iter([(1, vec![2, 3]), (4, vec![])]);
+        let nested = RelationStats::from_relation(vec.stream_iter().map(|_, v| SortedVecGalloper::new(v)));
+        assert_eq!(nested.distinct, vec![1, 2]);
+        assert_eq!(nested.degrees[0].buckets(), &[0, 0, 1]);
+        assert_eq!(vec.stats().distinct, vec![2]);
+    }
+}
@@ -1 +1,11 @@
+#[cfg(test)]
+#[macro_use]
+mod map_tests;
+
 pub mod rbtree_lib;
+pub mod persistent;
+pub mod aggregate;
+pub mod arena;
+pub mod bplus_tree;
+pub mod skip_list;
+mod traversal;
@@ -0,0 +1,115 @@
+use std::marker::PhantomData;
+
+use num_traits::Zero;
+
+/// A summary of the entries of a subtree, which an `RBTree` keeps in each of its nodes
+/// and maintains through insertions, removals and rotations, so that
+/// `RBTree::range_aggregate` takes O(log n) time.
+/// The summaries form a monoid: `combine` must be associative, with `empty()` as its identity.
+/// It need not be commutative; entries are always combined in key order.
+pub trait Aggregate<K, V> {
+    type Value: Clone;
+
+    /// The summary of no entries
+    fn empty() -> Self::Value;
+
+    /// The summary of a single entry
+    fn lift(key: &K, value: &V) -> Self::Value;
+
+    /// The summary of the entries summarized by `left` followed by those summarized by `right`
+    fn combine(left: &Self::Value, right: &Self::Value) -> Self::Value;
+}
+
+/// No summary, which costs nothing to maintain
+impl<K, V> Aggregate<K, V> for () {
+    type Value = ();
+
+    #[inline]
+    fn empty() {}
+
+    #[inline]
+    fn lift(_: &K, _: &V) {}
+
+    #[inline]
+    fn combine(_: &(), _: &()) {}
+}
+
+/// The sum of the values
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sum;
+
+impl<K, V: Zero + Clone> Aggregate<K, V> for Sum {
+    type Value = V;
+
+    fn empty() -> V {
+        V::zero()
+    }
+
+    fn lift(_: &K, value: &V) -> V {
+        value.clone()
+    }
+
+    fn combine(left: &V, right: &V) -> V {
+        left.clone() + right.clone()
+    }
+}
+
+/// The greatest value, or `None` for no entries
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Max;
+
+impl<K, V: Ord + Clone> Aggregate<K, V> for Max {
+    type Value = Option<V>;
+
+    fn empty() -> Option<V> {
+        None
+    }
+
+    fn lift(_: &K, value: &V) -> Option<V> {
+        Some(value.clone())
+    }
+
+    fn combine(left: &Option<V>, right: &Option<V>) -> Option<V> {
+        left.clone().max(right.clone())
+    }
+}
+
+/// The number of entries
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Count;
+
+impl<K, V> Aggregate<K, V> for Count {
+    type Value = usize;
+
+    fn empty() -> usize {
+        0
+    }
+
+    fn lift(_: &K, _: &V) -> usize {
+        1
+    }
+
+    fn combine(left: &usize, right: &usize) -> usize {
+        left + right
+    }
+}
+
+/// Both of two summaries, e.g. `Pair<Sum, Count>` to compute averages
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pair<A, B>(PhantomData<(A, B)>);
+
+impl<K, V, A: Aggregate<K, V>, B: Aggregate<K, V>> Aggregate<K, V> for Pair<A, B> {
+    type Value = (A::Value, B::Value);
+
+    fn empty() -> Self::Value {
+        (A::empty(), B::empty())
+    }
+
+    fn lift(key: &K, value: &V) -> Self::Value {
+        (A::lift(key, value), B::lift(key, value))
+    }
+
+    fn combine(left: &Self::Value, right: &Self::Value) -> Self::Value {
+        (A::combine(&left.0, &right.0), B::combine(&left.1, &right.1))
+    }
+}
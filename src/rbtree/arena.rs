@@ -0,0 +1,489 @@
+use std::{cmp::Ordering, fmt::{self, Debug}, mem};
+
+use crate::streams::stream_defs::{FromStreamIterator, IndexedStream, IntoStreamIterator};
+
+use super::{persistent::{DELTA, GAMMA}, traversal::{Nodes, Traversal}};
+
+/// The position of a node in the arena
+type Link = Option<usize>;
+
+#[derive(Clone)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    /// The number of entries in the subtree rooted at this node
+    size: usize,
+    left: Link,
+    right: Link,
+}
+
+/// An ordered map whose nodes live in a single `Vec` and refer to each other by index,
+/// written without any `unsafe` code.
+/// It shares these methods with `RBTree`, so that either can stand behind a type alias:
+/// `new`, `len`, `is_empty`, `clear`, `insert`, `replace_or_insert`, `get`, `get_mut`, `contains_key`,
+/// `remove`, `remove_entry`, `iter` and `stream_iter` (with O(log n) seeks),
+/// along with collecting from iterators and streams.
+/// Like `RBTree::insert`, `insert` keeps duplicate keys, placing the new entry after the equal ones,
+/// and so does collecting.
+/// The nodes stay contiguous: removing an entry moves the last node into its slot.
+/// The tree is weight-balanced like `PersistentMap`.
+#[derive(Clone)]
+pub struct ArenaMap<K, V> {
+    nodes: Vec<Node<K, V>>,
+    root: Link,
+}
+
+impl<K, V> Default for ArenaMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for ArenaMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V> ArenaMap<K, V> {
+    pub fn new() -> Self {
+        ArenaMap { nodes: Vec::new(), root: None }
+    }
+
+    /// An empty map with room for `capacity` entries before the arena grows
+    pub fn with_capacity(capacity: usize) -> Self {
+        ArenaMap { nodes: Vec::with_capacity(capacity), root: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.root = None;
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { traversal: Traversal::new(&self.nodes[..], self.root) }
+    }
+
+    #[inline]
+    fn size(&self, link: Link) -> usize {
+        link.map_or(0, |n| self.nodes[n].size)
+    }
+
+    #[inline]
+    fn update(&mut self, n: usize) {
+        self.nodes[n].size = 1 + self.size(self.nodes[n].left) + self.size(self.nodes[n].right);
+    }
+
+    /// Moves the right child of `n` up into its place, returning it
+    fn rotate_left(&mut self, n: usize) -> usize {
+        let right = self.nodes[n].right.expect("a rotated node has the child moving up");
+        self.nodes[n].right = self.nodes[right].left;
+        self.nodes[right].left = Some(n);
+        self.update(n);
+        self.update(right);
+        right
+    }
+
+    /// Moves the left child of `n` up into its place, returning it
+    fn rotate_right(&mut self, n: usize) -> usize {
+        let left = self.nodes[n].left.expect("a rotated node has the child moving up");
+        self.nodes[n].left = self.nodes[left].right;
+        self.nodes[left].right = Some(n);
+        self.update(n);
+        self.update(left);
+        left
+    }
+
+    /// Restores the balance of the subtree rooted at `n` after one entry was inserted into or removed
+    /// from one of its children, with at most one (single or double) rotation.
+    /// Returns the new root of the subtree.
+    fn balance(&mut self, n: usize) -> usize {
+        let (l, r) = (self.size(self.nodes[n].left), self.size(self.nodes[n].right));
+        if l + r <= 1 {
+            self.update(n);
+            n
+        } else if r > DELTA * l {
+            let right = self.nodes[n].right.expect("the heavier side is not empty");
+            if self.size(self.nodes[right].left) >= GAMMA * self.size(self.nodes[right].right) {
+                self.nodes[n].right = Some(self.rotate_right(right));
+            }
+            self.rotate_left(n)
+        } else if l > DELTA * r {
+            let left = self.nodes[n].left.expect("the heavier side is not empty");
+            if self.size(self.nodes[left].right) >= GAMMA * self.size(self.nodes[left].left) {
+                self.nodes[n].left = Some(self.rotate_left(left));
+            }
+            self.rotate_right(n)
+        } else {
+            self.update(n);
+            n
+        }
+    }
+
+    /// Joins the two children of a removed node, replacing it with an entry from the larger one
+    fn glue(&mut self, left: Link, right: Link) -> Link {
+        let (Some(l), Some(r)) = (left, right) else {
+            return left.or(right);
+        };
+        let n = if self.nodes[l].size > self.nodes[r].size {
+            let (max, rest) = self.remove_max(l);
+            self.nodes[max].left = rest;
+            self.nodes[max].right = right;
+            max
+        } else {
+            let (min, rest) = self.remove_min(r);
+            self.nodes[min].left = left;
+            self.nodes[min].right = rest;
+            min
+        };
+        Some(self.balance(n))
+    }
+
+    /// Detaches the first node of the subtree rooted at `n`, returning it and the new root of the subtree
+    fn remove_min(&mut self, n: usize) -> (usize, Link) {
+        match self.nodes[n].left {
+            None => (n, self.nodes[n].right),
+            Some(left) => {
+                let (min, rest) = self.remove_min(left);
+                self.nodes[n].left = rest;
+                (min, Some(self.balance(n)))
+            }
+        }
+    }
+
+    /// Detaches the last node of the subtree rooted at `n`, returning it and the new root of the subtree
+    fn remove_max(&mut self, n: usize) -> (usize, Link) {
+        match self.nodes[n].right {
+            None => (n, self.nodes[n].left),
+            Some(right) => {
+                let (max, rest) = self.remove_max(right);
+                self.nodes[n].right = rest;
+                (max, Some(self.balance(n)))
+            }
+        }
+    }
+}
+
+impl<K: Ord, V> ArenaMap<K, V> {
+    fn find(&self, key: &K) -> Link {
+        let mut link = self.root;
+        while let Some(n) = link {
+            match key.cmp(&self.nodes[n].key) {
+                Ordering::Less => link = self.nodes[n].left,
+                Ordering::Greater => link = self.nodes[n].right,
+                Ordering::Equal => return Some(n),
+            }
+        }
+        None
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.find(key).map(|n| &self.nodes[n].value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.find(key).map(|n| &mut self.nodes[n].value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// Inserts an entry, after any entries with the same key
+    pub fn insert(&mut self, key: K, value: V) {
+        let (root, _) = self.insert_at(self.root, key, value, false);
+        self.root = Some(root);
+    }
+
+    /// Inserts `value` at `key`, returning the previous value if there was one
+    pub fn replace_or_insert(&mut self, key: K, value: V) -> Option<V> {
+        let (root, old) = self.insert_at(self.root, key, value, true);
+        self.root = Some(root);
+        old
+    }
+
+    /// Inserts into the subtree at `link`, returning its new root and the replaced value.
+    /// An entry with the same key is replaced if `replace`, and otherwise kept before the new one.
+    fn insert_at(&mut self, link: Link, key: K, value: V, replace: bool) -> (usize, Option<V>) {
+        let Some(n) = link else {
+            self.nodes.push(Node { key, value, size: 1, left: None, right: None });
+            return (self.nodes.len() - 1, None);
+        };
+        match key.cmp(&self.nodes[n].key) {
+            Ordering::Less => {
+                let (left, old) = self.insert_at(self.nodes[n].left, key, value, replace);
+                self.nodes[n].left = Some(left);
+                if old.is_some() {
+                    return (n, old);
+                }
+                (self.balance(n), None)
+            }
+            Ordering::Equal if replace => (n, Some(mem::replace(&mut self.nodes[n].value, value))),
+            Ordering::Greater | Ordering::Equal => {
+                let (right, old) = self.insert_at(self.nodes[n].right, key, value, replace);
+                self.nodes[n].right = Some(right);
+                if old.is_some() {
+                    return (n, old);
+                }
+                (self.balance(n), None)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    /// Removes the entry with the key `key`, returning the stored key along with the value
+    pub fn remove_entry(&mut self, key: &K) -> Option<(K, V)> {
+        let (root, removed) = self.remove_at(self.root, key)?;
+        self.root = root;
+        Some(self.free(removed))
+    }
+
+    /// Removes `key` from the subtree at `link`, returning the new root of the subtree and the detached node,
+    /// or `None` if `key` is not in it
+    fn remove_at(&mut self, link: Link, key: &K) -> Option<(Link, usize)> {
+        let n = link?;
+        match key.cmp(&self.nodes[n].key) {
+            Ordering::Less => {
+                let (left, removed) = self.remove_at(self.nodes[n].left, key)?;
+                self.nodes[n].left = left;
+                Some((Some(self.balance(n)), removed))
+            }
+            Ordering::Greater => {
+                let (right, removed) = self.remove_at(self.nodes[n].right, key)?;
+                self.nodes[n].right = right;
+                Some((Some(self.balance(n)), removed))
+            }
+            Ordering::Equal => Some((self.glue(self.nodes[n].left, self.nodes[n].right), n)),
+        }
+    }
+
+    /// Takes the detached node `n` out of the arena, moving the last node into its slot
+    fn free(&mut self, n: usize) -> (K, V) {
+        let last = self.nodes.len() - 1;
+        if n != last {
+            // Find the link to the last node by its key, and point it to the slot it moves to
+            let root = self.root.expect("the last node is in the tree");
+            match (root != last).then(|| self.parent(root, last).expect("the last node is in the tree")) {
+                None => self.root = Some(n),
+                Some(p) if self.nodes[p].left == Some(last) => self.nodes[p].left = Some(n),
+                Some(p) => self.nodes[p].right = Some(n),
+            }
+        }
+        let node = self.nodes.swap_remove(n);
+        (node.key, node.value)
+    }
+
+    /// The parent of the node `target` below the node `n`, found by the key of `target`.
+    /// Entries with the same key may lie on both sides of each other, so both sides of those are searched.
+    fn parent(&self, n: usize, target: usize) -> Option<usize> {
+        let node = &self.nodes[n];
+        let children = match self.nodes[target].key.cmp(&node.key) {
+            Ordering::Less => [node.left, None],
+            Ordering::Greater => [node.right, None],
+            Ordering::Equal => [node.left, node.right],
+        };
+        children.into_iter().flatten().find_map(|child| if child == target { Some(n) } else { self.parent(child, target) })
+    }
+
+    pub fn stream_iter(&self) -> ArenaStream<'_, K, V> {
+        ArenaStream { iter: self.iter() }
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for ArenaMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut map = ArenaMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for ArenaMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K: Ord, V> FromStreamIterator<K, V> for ArenaMap<K, V> {
+    fn from_stream_iterator<S: IndexedStream<I = K, V = V>>(iter: S) -> Self {
+        let mut map = ArenaMap::new();
+        map.extend_from_stream_iterator(iter);
+        map
+    }
+
+    fn extend_from_stream_iterator<S: IndexedStream<I = K, V = V>>(&mut self, iter: S) {
+        iter.for_each(|k, v| {
+            self.insert(k, v);
+        });
+    }
+}
+
+impl<'a, K, V> Nodes<'a> for &'a [Node<K, V>] {
+    type Handle = usize;
+    type K = K;
+    type V = V;
+
+    fn left(self, node: usize) -> Link {
+        self[node].left
+    }
+
+    fn right(self, node: usize) -> Link {
+        self[node].right
+    }
+
+    fn key(self, node: usize) -> &'a K {
+        &self[node].key
+    }
+
+    fn value(self, node: usize) -> &'a V {
+        &self[node].value
+    }
+
+    fn size(self, node: Link) -> usize {
+        node.map_or(0, |n| self[n].size)
+    }
+}
+
+/// An in-order iterator over an `ArenaMap`
+pub struct Iter<'a, K, V> {
+    traversal: Traversal<'a, &'a [Node<K, V>]>,
+}
+
+impl<K, V> Clone for Iter<'_, K, V> {
+    fn clone(&self) -> Self {
+        Iter { traversal: self.traversal.clone() }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.traversal.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.traversal.remaining();
+        (len, Some(len))
+    }
+}
+
+/// A stream over an `ArenaMap`, seeking like a `PersistentStream`
+#[derive(Clone)]
+pub struct ArenaStream<'a, K, V> {
+    iter: Iter<'a, K, V>,
+}
+
+impl<'a, K: Ord + Copy, V> IndexedStream for ArenaStream<'a, K, V> {
+    type I = K;
+    type V = &'a V;
+
+    fn valid(&self) -> bool {
+        self.iter.traversal.peek().is_some()
+    }
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    fn seek(&mut self, index: K, strict: bool) {
+        self.iter.traversal.seek(|k| if strict { *k <= index } else { *k < index });
+    }
+
+    fn next(&mut self) {
+        self.iter.next();
+    }
+
+    fn index(&self) -> K {
+        *self.iter.traversal.peek().expect("stream is valid").0
+    }
+
+    fn value(&self) -> &'a V {
+        self.iter.traversal.peek().expect("stream is valid").1
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.iter.traversal.remaining())
+    }
+}
+
+impl<'a, K: Ord + Copy, V> IntoStreamIterator for &'a ArenaMap<K, V> {
+    type IndexType = K;
+    type ValueType = &'a V;
+    type StreamType = ArenaStream<'a, K, V>;
+
+    fn into_stream_iterator(self) -> Self::StreamType {
+        self.stream_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::{rbtree::persistent::DELTA, streams::stream_defs::IndexedStream};
+
+    use super::{ArenaMap, Link};
+
+    ordered_map_tests!(ArenaMap);
+
+    /// Checks the sizes, the weight balance and the key order of a subtree, returning its size
+    fn assert_balanced<K: Ord, V>(map: &ArenaMap<K, V>, link: Link) -> usize {
+        let Some(n) = link else {
+            return 0;
+        };
+        let node = &map.nodes[n];
+        let (l, r) = (assert_balanced(map, node.left), assert_balanced(map, node.right));
+        assert_eq!(node.size, 1 + l + r);
+        assert!(l + r <= 1 || (l <= DELTA * r && r <= DELTA * l));
+        assert!(node.left.is_none_or(|left| map.nodes[left].key < node.key));
+        assert!(node.right.is_none_or(|right| node.key < map.nodes[right].key));
+        node.size
+    }
+
+    #[quickcheck]
+    fn test_arena_stays_dense(ops: Vec<(u8, bool)>) {
+        let mut map = ArenaMap::new();
+        let mut expected = BTreeMap::new();
+        for (k, insert) in ops {
+            if insert {
+                map.replace_or_insert(k, k as u32);
+                expected.insert(k, k as u32);
+            } else {
+                assert_eq!(map.remove_entry(&k), expected.remove_entry(&k));
+            }
+            // Every node of the arena is in the tree
+            assert_eq!(assert_balanced(&map, map.root), map.nodes.len());
+        }
+        assert!(map.iter().eq(expected.iter()));
+    }
+
+    #[test]
+    fn test_sequential() {
+        let mut map: ArenaMap<u32, u32> = (0..1000).map(|k| (k, k * k)).collect();
+        assert_balanced(&map, map.root);
+        for k in (0..1000).step_by(2) {
+            assert_eq!(map.remove(&k), Some(k * k));
+        }
+        assert_balanced(&map, map.root);
+        assert_eq!(map.len(), 500);
+        assert_eq!(map.stream_iter().map(|_, v| *v as u64).contract(), (1..1000).step_by(2).map(|k: u64| k * k).sum());
+        let copy: ArenaMap<u32, u32> = map.stream_iter().map(|_, v| *v).collect();
+        assert!(copy.iter().eq(map.iter()));
+    }
+}
@@ -0,0 +1,696 @@
+use std::{fmt::{self, Debug}, mem, ops::RangeInclusive};
+
+use crate::streams::stream_defs::{FromStreamIterator, IndexedStream, IntoStreamIterator};
+
+#[derive(Clone)]
+struct Leaf<K, V> {
+    keys: Vec<K>,
+    values: Vec<V>,
+}
+
+#[derive(Clone)]
+struct Internal<K, V> {
+    /// `keys[i]` is at least the keys of `children[i]`, and at most those of `children[i + 1]`.
+    /// Entries with the same key may thus span several children, with separators equal to that key.
+    keys: Vec<K>,
+    children: Vec<Node<K, V>>,
+    /// The number of entries below each child
+    counts: Vec<usize>,
+}
+
+#[derive(Clone)]
+enum Node<K, V> {
+    Leaf(Leaf<K, V>),
+    Internal(Internal<K, V>),
+}
+
+impl<K, V> Node<K, V> {
+    /// The number of entries of a leaf, or of children of an internal node
+    fn len(&self) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.keys.len(),
+            Node::Internal(node) => node.children.len(),
+        }
+    }
+
+    /// The number of entries in the subtree
+    fn count(&self) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.keys.len(),
+            Node::Internal(node) => node.counts.iter().sum(),
+        }
+    }
+}
+
+impl<K: Ord, V> Node<K, V> {
+    fn get(&self, key: &K) -> Option<&V> {
+        match self {
+            Node::Leaf(leaf) => leaf.keys.binary_search(key).ok().map(|i| &leaf.values[i]),
+            Node::Internal(node) => node.children[node.candidates(key)].iter().find_map(|child| child.get(key)),
+        }
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        match self {
+            Node::Leaf(leaf) => leaf.keys.binary_search(key).ok().map(|i| &mut leaf.values[i]),
+            Node::Internal(node) => {
+                let candidates = node.candidates(key);
+                node.children[candidates].iter_mut().find_map(|child| child.get_mut(key))
+            }
+        }
+    }
+}
+
+impl<K: Ord + Clone, V> Node<K, V> {
+    /// Inserts into the subtree after any entries with the same key, returning the separator
+    /// and new right sibling if the node had to be split
+    fn insert<const B: usize>(&mut self, key: K, value: V) -> Option<(K, Node<K, V>)> {
+        match self {
+            Node::Leaf(leaf) => {
+                let i = leaf.keys.partition_point(|k| *k <= key);
+                leaf.keys.insert(i, key);
+                leaf.values.insert(i, value);
+                if leaf.keys.len() <= B {
+                    return None;
+                }
+                let mid = leaf.keys.len() / 2;
+                let right = Leaf { keys: leaf.keys.split_off(mid), values: leaf.values.split_off(mid) };
+                Some((right.keys[0].clone(), Node::Leaf(right)))
+            }
+            Node::Internal(node) => {
+                let j = node.keys.partition_point(|s| *s <= key);
+                node.counts[j] += 1;
+                let (separator, sibling) = node.children[j].insert::<B>(key, value)?;
+                let moved = sibling.count();
+                node.counts[j] -= moved;
+                node.counts.insert(j + 1, moved);
+                node.keys.insert(j, separator);
+                node.children.insert(j + 1, sibling);
+                if node.children.len() <= B {
+                    return None;
+                }
+                let mid = node.children.len() / 2;
+                let children = node.children.split_off(mid);
+                let counts = node.counts.split_off(mid);
+                let mut keys = node.keys.split_off(mid - 1);
+                let separator = keys.remove(0);
+                Some((separator, Node::Internal(Internal { keys, children, counts })))
+            }
+        }
+    }
+
+    /// Removes an entry with the key `key` from the subtree, leaving its root with possibly too few entries or children
+    fn remove<const B: usize>(&mut self, key: &K) -> Option<(K, V)> {
+        match self {
+            Node::Leaf(leaf) => {
+                let i = leaf.keys.binary_search(key).ok()?;
+                Some((leaf.keys.remove(i), leaf.values.remove(i)))
+            }
+            Node::Internal(node) => {
+                let (j, removed) = node.candidates(key).find_map(|j| Some((j, node.children[j].remove::<B>(key)?)))?;
+                node.counts[j] -= 1;
+                if node.children[j].len() < B / 2 {
+                    node.refill::<B>(j);
+                }
+                Some(removed)
+            }
+        }
+    }
+}
+
+impl<K: Ord, V> Internal<K, V> {
+    /// The children that may hold entries with the key `key`: the first one whose separator is not below it,
+    /// and those after it up to the first one whose separator is above it
+    fn candidates(&self, key: &K) -> RangeInclusive<usize> {
+        self.keys.partition_point(|s| s < key)..=self.keys.partition_point(|s| s <= key)
+    }
+}
+
+impl<K: Clone, V> Internal<K, V> {
+    /// Brings `children[j]` back to at least `B / 2` entries or children, by moving one over from a sibling
+    /// if the sibling can spare it, and otherwise by merging it with the sibling.
+    /// The separators may stay below the first keys of their right children, which is allowed.
+    fn refill<const B: usize>(&mut self, j: usize) {
+        if j > 0 && self.children[j - 1].len() > B / 2 {
+            let (left, right) = self.children.split_at_mut(j);
+            match (&mut left[j - 1], &mut right[0]) {
+                (Node::Leaf(from), Node::Leaf(to)) => {
+                    to.keys.insert(0, from.keys.pop().expect("the sibling can spare an entry"));
+                    to.values.insert(0, from.values.pop().expect("the sibling can spare an entry"));
+                    self.keys[j - 1] = to.keys[0].clone();
+                    self.counts[j - 1] -= 1;
+                    self.counts[j] += 1;
+                }
+                (Node::Internal(from), Node::Internal(to)) => {
+                    to.children.insert(0, from.children.pop().expect("the sibling can spare a child"));
+                    let moved = from.counts.pop().expect("the sibling can spare a child");
+                    to.counts.insert(0, moved);
+                    self.counts[j - 1] -= moved;
+                    self.counts[j] += moved;
+                    let separator = from.keys.pop().expect("the sibling can spare a child");
+                    to.keys.insert(0, mem::replace(&mut self.keys[j - 1], separator));
+                }
+                _ => unreachable!("siblings are at the same depth"),
+            }
+        } else if j + 1 < self.children.len() && self.children[j + 1].len() > B / 2 {
+            let (left, right) = self.children.split_at_mut(j + 1);
+            match (&mut left[j], &mut right[0]) {
+                (Node::Leaf(to), Node::Leaf(from)) => {
+                    to.keys.push(from.keys.remove(0));
+                    to.values.push(from.values.remove(0));
+                    self.keys[j] = from.keys[0].clone();
+                    self.counts[j + 1] -= 1;
+                    self.counts[j] += 1;
+                }
+                (Node::Internal(to), Node::Internal(from)) => {
+                    to.children.push(from.children.remove(0));
+                    let moved = from.counts.remove(0);
+                    to.counts.push(moved);
+                    self.counts[j + 1] -= moved;
+                    self.counts[j] += moved;
+                    let separator = from.keys.remove(0);
+                    to.keys.push(mem::replace(&mut self.keys[j], separator));
+                }
+                _ => unreachable!("siblings are at the same depth"),
+            }
+        } else {
+            // Merge the child with its right sibling, or with its left one if it is the last child
+            let j = if j + 1 < self.children.len() { j } else { j - 1 };
+            let separator = self.keys.remove(j);
+            let right = self.children.remove(j + 1);
+            self.counts[j] += self.counts.remove(j + 1);
+            match (&mut self.children[j], right) {
+                (Node::Leaf(to), Node::Leaf(mut from)) => {
+                    to.keys.append(&mut from.keys);
+                    to.values.append(&mut from.values);
+                }
+                (Node::Internal(to), Node::Internal(mut from)) => {
+                    to.keys.push(separator);
+                    to.keys.append(&mut from.keys);
+                    to.children.append(&mut from.children);
+                    to.counts.append(&mut from.counts);
+                }
+                _ => unreachable!("siblings are at the same depth"),
+            }
+        }
+    }
+}
+
+/// An ordered map stored in a B+ tree: the entries are kept sorted in leaves of up to `B` entries,
+/// below internal nodes of up to `B` children, so a lookup touches O(log_B n) nodes and
+/// scanning the entries reads them from contiguous arrays.
+/// Its streams seek like those over an `RBTree` (see `NodePtr::seek`): they climb from the current leaf
+/// to the lowest ancestor covering the target, and binary search within each node on the way down,
+/// so a sequence of seeks through the whole map takes O(n) time in total.
+/// It shares the same methods with `RBTree` as `ArenaMap` does, `insert` included, along with `from_sorted_iter`.
+/// Inserting entries one by one leaves the nodes about half full, while `from_sorted_iter` fills them.
+/// A fan-out `B` of 16 to 64 keeps each node within a few cache lines. Fan-outs down to 4 are allowed
+/// so that the tests reach several levels with few entries.
+#[derive(Clone)]
+pub struct BPlusTree<K, V, const B: usize = 32> {
+    root: Node<K, V>,
+    len: usize,
+}
+
+impl<K, V, const B: usize> Default for BPlusTree<K, V, B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Debug, V: Debug, const B: usize> Debug for BPlusTree<K, V, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V, const B: usize> BPlusTree<K, V, B> {
+    pub fn new() -> Self {
+        assert!(B >= 4, "a B+ tree needs a fan-out of at least 4, and 16 to 64 is best");
+        BPlusTree { root: Node::Leaf(Leaf { keys: Vec::new(), values: Vec::new() }), len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        *self = BPlusTree::new();
+    }
+
+    /// The number of levels of the tree, 1 if the root is a leaf
+    pub fn depth(&self) -> usize {
+        let mut depth = 1;
+        let mut node = &self.root;
+        while let Node::Internal(internal) = node {
+            depth += 1;
+            node = &internal.children[0];
+        }
+        depth
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { cursor: Cursor::first(&self.root), len: self.len }
+    }
+}
+
+impl<K: Ord, V, const B: usize> BPlusTree<K, V, B> {
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.root.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.root.get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.root.get(key).is_some()
+    }
+
+    pub fn stream_iter(&self) -> BPlusTreeStream<'_, K, V> {
+        BPlusTreeStream { cursor: Cursor::first(&self.root), len: self.len }
+    }
+}
+
+impl<K: Ord + Clone, V, const B: usize> BPlusTree<K, V, B> {
+    /// Inserts an entry, after any entries with the same key
+    pub fn insert(&mut self, key: K, value: V) {
+        if let Some((separator, sibling)) = self.root.insert::<B>(key, value) {
+            let left = mem::replace(&mut self.root, Node::Leaf(Leaf { keys: Vec::new(), values: Vec::new() }));
+            let counts = vec![left.count(), sibling.count()];
+            self.root = Node::Internal(Internal { keys: vec![separator], children: vec![left, sibling], counts });
+        }
+        self.len += 1;
+    }
+
+    /// Inserts `value` at `key`, returning the previous value if there was one
+    pub fn replace_or_insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(old) = self.get_mut(&key) {
+            return Some(mem::replace(old, value));
+        }
+        self.insert(key, value);
+        None
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    /// Removes an entry with the key `key`, returning the stored key along with the value
+    pub fn remove_entry(&mut self, key: &K) -> Option<(K, V)> {
+        let removed = self.root.remove::<B>(key)?;
+        self.len -= 1;
+        if let Node::Internal(root) = &mut self.root {
+            if root.children.len() == 1 {
+                self.root = root.children.pop().expect("the root has a child");
+            }
+        }
+        Some(removed)
+    }
+}
+
+/// Splits `n` items into as few groups of at most `max` items as possible, of as even sizes as possible,
+/// so that each has at least `max / 2` items if there are several
+fn group_sizes(n: usize, max: usize) -> impl Iterator<Item = usize> {
+    let groups = n.div_ceil(max);
+    (0..groups).map(move |i| n / groups + usize::from(i < n % groups))
+}
+
+impl<K: Ord + Clone, V, const B: usize> BPlusTree<K, V, B> {
+    /// Builds a tree from entries sorted by key in O(n) time, with every node as full as possible:
+    /// the leaves hold `B` entries but for rounding, and the internal nodes `B` children.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::bplus_tree::BPlusTree;
+    /// let m: BPlusTree<u32, u32> = BPlusTree::from_sorted_iter((0..32 * 32 * 32).map(|k| (k, k * 2)));
+    /// assert_eq!(m.depth(), 3);
+    /// assert_eq!(m.get(&7), Some(&14));
+    /// ```
+    pub fn from_sorted_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let entries: Vec<(K, V)> = iter.into_iter().collect();
+        debug_assert!(entries.windows(2).all(|pair| pair[0].0 <= pair[1].0), "from_sorted_iter requires sorted keys");
+        let len = entries.len();
+        let mut entries = entries.into_iter();
+        // The nodes of the level being built, along with their first keys
+        let mut level: Vec<(K, Node<K, V>)> = group_sizes(len, B)
+            .map(|size| {
+                let (keys, values): (Vec<K>, Vec<V>) = entries.by_ref().take(size).unzip();
+                (keys[0].clone(), Node::Leaf(Leaf { keys, values }))
+            })
+            .collect();
+        while level.len() > 1 {
+            let mut nodes = mem::take(&mut level).into_iter();
+            level = group_sizes(nodes.len(), B)
+                .map(|size| {
+                    let mut children = nodes.by_ref().take(size);
+                    let (first, child) = children.next().expect("a group is not empty");
+                    let mut node = Internal { keys: Vec::new(), counts: vec![child.count()], children: vec![child] };
+                    for (key, child) in children {
+                        node.keys.push(key);
+                        node.counts.push(child.count());
+                        node.children.push(child);
+                    }
+                    (first, Node::Internal(node))
+                })
+                .collect();
+        }
+        match level.pop() {
+            Some((_, root)) => BPlusTree { root, len },
+            None => BPlusTree::new(),
+        }
+    }
+}
+
+impl<K: Ord + Clone, V, const B: usize> FromIterator<(K, V)> for BPlusTree<K, V, B> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut map = BPlusTree::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Ord + Clone, V, const B: usize> Extend<(K, V)> for BPlusTree<K, V, B> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+/// Builds a tree from sorted entries in O(n) time, see `BPlusTree::from_sorted_iter`
+impl<K: Ord + Clone, V, const B: usize> FromStreamIterator<K, V> for BPlusTree<K, V, B> {
+    fn from_stream_iterator<S: IndexedStream<I = K, V = V>>(iter: S) -> Self {
+        let mut entries = Vec::new();
+        iter.for_each(|k, v| entries.push((k, v)));
+        BPlusTree::from_sorted_iter(entries)
+    }
+
+    fn extend_from_stream_iterator<S: IndexedStream<I = K, V = V>>(&mut self, iter: S) {
+        iter.for_each(|k, v| {
+            self.insert(k, v);
+        });
+    }
+}
+
+/// A position in a `BPlusTree`: an entry of a leaf, along with the path to the leaf
+struct Cursor<'a, K, V> {
+    /// The ancestors of `leaf`, from the root down, with the index of the child leading to it
+    path: Vec<(&'a Internal<K, V>, usize)>,
+    leaf: &'a Leaf<K, V>,
+    /// The position in `leaf`, which is past its end once the cursor has passed the last entry
+    pos: usize,
+}
+
+impl<K, V> Clone for Cursor<'_, K, V> {
+    fn clone(&self) -> Self {
+        Cursor { path: self.path.clone(), leaf: self.leaf, pos: self.pos }
+    }
+}
+
+impl<'a, K, V> Cursor<'a, K, V> {
+    fn first(root: &'a Node<K, V>) -> Self {
+        let mut path = Vec::new();
+        let mut node = root;
+        loop {
+            match node {
+                Node::Leaf(leaf) => return Cursor { path, leaf, pos: 0 },
+                Node::Internal(internal) => {
+                    path.push((internal, 0));
+                    node = &internal.children[0];
+                }
+            }
+        }
+    }
+
+    fn valid(&self) -> bool {
+        self.pos < self.leaf.keys.len()
+    }
+
+    /// The number of entries before the current one, for a valid cursor, in O(B log_B n) time
+    fn rank(&self) -> usize {
+        self.path.iter().map(|(node, i)| node.counts[..*i].iter().sum::<usize>()).sum::<usize>() + self.pos
+    }
+
+    /// Moves to the first entry of the next leaf, or past the end if there is none
+    fn next_leaf(&mut self) {
+        while let Some((node, i)) = self.path.pop() {
+            if i + 1 < node.children.len() {
+                self.path.push((node, i + 1));
+                let mut child = &node.children[i + 1];
+                while let Node::Internal(internal) = child {
+                    self.path.push((internal, 0));
+                    child = &internal.children[0];
+                }
+                let Node::Leaf(leaf) = child else { unreachable!() };
+                self.leaf = leaf;
+                self.pos = 0;
+                return;
+            }
+        }
+        self.pos = self.leaf.keys.len();
+    }
+
+    fn next(&mut self) {
+        self.pos += 1;
+        if self.pos == self.leaf.keys.len() {
+            self.next_leaf();
+        }
+    }
+
+    /// Moves forward to the first entry whose key does not satisfy `before`,
+    /// which must be downwards closed
+    fn seek(&mut self, before: impl Fn(&K) -> bool) {
+        let keys = &self.leaf.keys;
+        if !before(&keys[self.pos]) {
+            return;
+        }
+        if !before(keys.last().expect("the leaf of a valid cursor is not empty")) {
+            self.pos += keys[self.pos..].partition_point(&before);
+            return;
+        }
+        // Climb to the lowest ancestor whose last child starts past the target, which thus lies below it.
+        // The root is the last resort, below which the target may not be at all.
+        while self.path.len() > 1 {
+            let (node, _) = self.path[self.path.len() - 1];
+            if !before(node.keys.last().expect("an internal node has several children")) {
+                break;
+            }
+            self.path.pop();
+        }
+        let Some((node, i)) = self.path.pop() else {
+            // The root is this leaf
+            self.pos = self.leaf.keys.len();
+            return;
+        };
+        // The children before `i` are behind the cursor
+        let j = i.max(node.keys.partition_point(&before));
+        self.path.push((node, j));
+        let mut child = &node.children[j];
+        loop {
+            match child {
+                Node::Internal(internal) => {
+                    let j = internal.keys.partition_point(&before);
+                    self.path.push((internal, j));
+                    child = &internal.children[j];
+                }
+                Node::Leaf(leaf) => {
+                    self.leaf = leaf;
+                    self.pos = leaf.keys.partition_point(&before);
+                    break;
+                }
+            }
+        }
+        // The target may be the first entry of the next leaf
+        if self.pos == self.leaf.keys.len() {
+            self.next_leaf();
+        }
+    }
+}
+
+/// An in-order iterator over a `BPlusTree`
+pub struct Iter<'a, K, V> {
+    cursor: Cursor<'a, K, V>,
+    len: usize,
+}
+
+impl<K, V> Clone for Iter<'_, K, V> {
+    fn clone(&self) -> Self {
+        Iter { cursor: self.cursor.clone(), len: self.len }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        if !self.cursor.valid() {
+            return None;
+        }
+        let (leaf, pos) = (self.cursor.leaf, self.cursor.pos);
+        self.cursor.next();
+        self.len -= 1;
+        Some((&leaf.keys[pos], &leaf.values[pos]))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+/// A stream over a `BPlusTree`
+#[derive(Clone)]
+pub struct BPlusTreeStream<'a, K, V> {
+    cursor: Cursor<'a, K, V>,
+    len: usize,
+}
+
+impl<'a, K: Ord + Copy, V> IndexedStream for BPlusTreeStream<'a, K, V> {
+    type I = K;
+    type V = &'a V;
+
+    fn valid(&self) -> bool {
+        self.cursor.valid()
+    }
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    fn seek(&mut self, index: K, strict: bool) {
+        if strict {
+            self.cursor.seek(|k| *k <= index);
+        } else {
+            self.cursor.seek(|k| *k < index);
+        }
+    }
+
+    fn next(&mut self) {
+        self.cursor.next();
+    }
+
+    fn index(&self) -> K {
+        self.cursor.leaf.keys[self.cursor.pos]
+    }
+
+    fn value(&self) -> &'a V {
+        &self.cursor.leaf.values[self.cursor.pos]
+    }
+
+    /// Exact, in O(B log_B n) time
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(if self.valid() { self.len - self.cursor.rank() } else { 0 })
+    }
+}
+
+impl<'a, K: Ord + Copy, V, const B: usize> IntoStreamIterator for &'a BPlusTree<K, V, B> {
+    type IndexType = K;
+    type ValueType = &'a V;
+    type StreamType = BPlusTreeStream<'a, K, V>;
+
+    fn into_stream_iterator(self) -> Self::StreamType {
+        self.stream_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::streams::stream_defs::IndexedStream;
+
+    use super::{BPlusTree, Node};
+
+    ordered_map_tests!(BPlusTree);
+
+    /// The shared tests again with a fan-out of 4, so that seeks climb and descend several levels
+    mod small_fanout {
+        use super::BPlusTree;
+
+        type SmallTree<K, V> = BPlusTree<K, V, 4>;
+
+        ordered_map_tests!(SmallTree);
+    }
+
+    /// Checks the occupancy, the depth and the key order of a subtree whose keys lie in `lo..hi`,
+    /// returning its depth and number of entries
+    fn assert_valid<const B: usize>(node: &Node<u16, u16>, is_root: bool, lo: Option<u16>, hi: Option<u16>) -> (usize, usize) {
+        assert!(is_root || node.len() >= B / 2);
+        assert!(node.len() <= B);
+        let in_range = |k: u16| lo.is_none_or(|lo| lo <= k) && hi.is_none_or(|hi| k <= hi);
+        match node {
+            Node::Leaf(leaf) => {
+                assert!(leaf.keys.windows(2).all(|pair| pair[0] <= pair[1]));
+                assert!(leaf.keys.iter().all(|&k| in_range(k)));
+                (1, leaf.keys.len())
+            }
+            Node::Internal(internal) => {
+                assert!(internal.children.len() >= 2);
+                assert_eq!(internal.keys.len() + 1, internal.children.len());
+                assert!(internal.keys.iter().all(|&k| in_range(k)));
+                let mut depth = None;
+                let mut count = 0;
+                for (j, child) in internal.children.iter().enumerate() {
+                    let child_lo = if j == 0 { lo } else { Some(internal.keys[j - 1]) };
+                    let child_hi = internal.keys.get(j).copied().or(hi);
+                    let (d, c) = assert_valid::<B>(child, false, child_lo, child_hi);
+                    assert_eq!(*depth.get_or_insert(d), d);
+                    assert_eq!(internal.counts[j], c);
+                    count += c;
+                }
+                (depth.unwrap() + 1, count)
+            }
+        }
+    }
+
+    #[quickcheck]
+    fn test_small_fanout(ops: Vec<(u16, bool)>) {
+        let mut map: BPlusTree<u16, u16, 4> = BPlusTree::new();
+        let mut expected = BTreeMap::new();
+        for (k, insert) in ops {
+            let k = k % 512;
+            if insert {
+                assert_eq!(map.replace_or_insert(k, k), expected.insert(k, k));
+            } else {
+                assert_eq!(map.remove_entry(&k), expected.remove_entry(&k));
+            }
+            assert_eq!(assert_valid::<4>(&map.root, true, None, None).1, expected.len());
+        }
+        assert!(map.iter().eq(expected.iter()));
+    }
+
+    #[quickcheck]
+    fn test_from_sorted_iter(keys: Vec<u16>, inserts: Vec<u16>) {
+        let mut keys: Vec<u16> = keys.into_iter().map(|k| k % 512).collect();
+        keys.sort();
+        let mut map: BPlusTree<u16, u16, 4> = BPlusTree::from_sorted_iter(keys.iter().map(|&k| (k, k)));
+        assert_eq!(assert_valid::<4>(&map.root, true, None, None).1, keys.len());
+        // The full nodes split as usual
+        for k in inserts {
+            map.insert(k % 512, k % 512);
+            keys.insert(keys.partition_point(|&x| x <= k % 512), k % 512);
+            assert_eq!(assert_valid::<4>(&map.root, true, None, None).1, keys.len());
+        }
+        assert!(map.iter().map(|(&k, _)| k).eq(keys.iter().copied()));
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut map: BPlusTree<u32, u32> = (0..100_000).map(|k| (k, k)).collect();
+        assert_eq!(map.len(), 100_000);
+        // Sequential inserts leave the leaves half full, while the bulk loader fills them
+        assert_eq!(map.depth(), 4);
+        let packed: BPlusTree<u16, u16> = BPlusTree::from_sorted_iter((0..32 * 32 * 32).map(|k| (k, k)));
+        assert_eq!(assert_valid::<32>(&packed.root, true, None, None), (3, 32 * 32 * 32));
+        for k in 0..99_990 {
+            assert_eq!(map.remove(&k), Some(k));
+        }
+        assert_eq!(map.depth(), 1);
+        assert_eq!(map.stream_iter().collect_indices(), (99_990..100_000).collect::<Vec<_>>());
+    }
+}
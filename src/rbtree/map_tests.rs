@@ -0,0 +1,122 @@
+/// Tests of the API shared by the ordered maps of this module, run against a model `BTreeMap`.
+/// Expands to a module `ordered_map` of tests for the map type `$map` (which must be in scope),
+/// so that an implementation can replace another behind a type alias. It includes `stream_seek_tests!`.
+/// `test_operations` only calls `insert` for absent keys, as `insert` keeps duplicates,
+/// which `test_duplicate_keys` checks on its own.
+macro_rules! ordered_map_tests {
+    ($map:ident) => {
+        mod ordered_map {
+            use std::collections::{BTreeMap, BTreeSet};
+
+            use quickcheck_macros::quickcheck;
+
+            use super::$map;
+
+            #[quickcheck]
+            fn test_operations(ops: Vec<(u8, Option<u16>)>) {
+                let mut map: $map<u8, u16> = $map::new();
+                let mut expected = BTreeMap::new();
+                for (k, v) in ops {
+                    match v {
+                        Some(v) if v % 2 == 0 && !expected.contains_key(&k) => {
+                            map.insert(k, v);
+                            expected.insert(k, v);
+                        }
+                        Some(v) => assert_eq!(map.replace_or_insert(k, v), expected.insert(k, v)),
+                        None => assert_eq!(map.remove(&k), expected.remove(&k)),
+                    }
+                    assert_eq!(map.get(&k), expected.get(&k));
+                    assert_eq!(map.contains_key(&k), expected.contains_key(&k));
+                    assert_eq!(map.len(), expected.len());
+                }
+                assert!(map.iter().eq(expected.iter()));
+                assert_eq!(map.is_empty(), expected.is_empty());
+                map.clear();
+                assert!(map.is_empty() && map.iter().next().is_none());
+            }
+
+            #[quickcheck]
+            fn test_duplicate_keys(inserts: Vec<u8>, removals: Vec<u8>) {
+                let mut map: $map<u8, usize> = $map::new();
+                for (i, &k) in inserts.iter().enumerate() {
+                    map.insert(k % 16, i);
+                }
+                // Entries with the same key stay in the order they were inserted in
+                let mut expected: Vec<(u8, usize)> = inserts.iter().enumerate().map(|(i, &k)| (k % 16, i)).collect();
+                expected.sort_by_key(|&(k, _)| k);
+                assert!(map.iter().map(|(&k, &i)| (k, i)).eq(expected.iter().copied()));
+
+                for k in removals {
+                    let k = k % 16;
+                    let position = expected.iter().position(|&(key, _)| key == k);
+                    assert_eq!(map.remove(&k).is_some(), position.is_some());
+                    if let Some(position) = position {
+                        expected.remove(position);
+                    }
+                    assert_eq!(map.len(), expected.len());
+                    assert_eq!(map.get(&k).is_some(), expected.iter().any(|&(key, _)| key == k));
+                    assert!(map.iter().map(|(&k, _)| k).eq(expected.iter().map(|&(k, _)| k)));
+                }
+            }
+
+            #[quickcheck]
+            fn test_get_mut(keys: BTreeSet<u8>) {
+                let mut map: $map<u8, u32> = keys.iter().map(|&k| (k, k as u32)).collect();
+                for k in &keys {
+                    *map.get_mut(k).unwrap() *= 3;
+                }
+                assert_eq!(map.get_mut(&0).is_some(), keys.contains(&0));
+                assert!(map.iter().all(|(k, v)| *v == *k as u32 * 3));
+            }
+
+            stream_seek_tests!($map);
+        }
+    };
+}
+
+/// Tests of the streams over an ordered map `$map` (which must be in scope), intersecting them and
+/// seeking through them, for the maps that can be collected from entries.
+/// Expands to a module `stream_seek`; `no_estimate` is for maps whose streams give no `remaining_estimate`,
+/// which is otherwise checked to be exact.
+macro_rules! stream_seek_tests {
+    ($map:ident) => {
+        stream_seek_tests!(@tests $map, Some);
+    };
+    ($map:ident, no_estimate) => {
+        stream_seek_tests!(@tests $map, |_| None);
+    };
+    (@tests $map:ident, $estimate:expr) => {
+        mod stream_seek {
+            use std::collections::BTreeSet;
+
+            use quickcheck_macros::quickcheck;
+
+            use crate::streams::{sorted_vec::SortedVecGalloper, stream_defs::IndexedStream};
+
+            use super::$map;
+
+            #[quickcheck]
+            fn test_stream_seek(keys: BTreeSet<u16>, other: BTreeSet<u16>, seeks: Vec<(u16, bool)>) {
+                let estimate: fn(usize) -> Option<usize> = $estimate;
+                let map: $map<u16, u16> = keys.iter().map(|&k| (k, k)).collect();
+                let other: Vec<u16> = other.into_iter().collect();
+                let expected: Vec<u16> = keys.iter().filter(|k| other.contains(k)).copied().collect();
+                assert_eq!(map.stream_iter().zip_with(SortedVecGalloper::new(&other), |_, _| ()).collect_indices(), expected);
+                assert_eq!(SortedVecGalloper::new(&other).zip_with(map.stream_iter(), |_, _| ()).collect_indices(), expected);
+
+                let keys: Vec<u16> = keys.into_iter().collect();
+                let mut stream = map.stream_iter();
+                let mut position = 0;
+                for (target, strict) in seeks {
+                    if !stream.valid() {
+                        break;
+                    }
+                    stream.seek(target, strict);
+                    position = position.max(keys.partition_point(|&k| if strict { k <= target } else { k < target }));
+                    assert_eq!(stream.remaining_estimate(), estimate(keys.len() - position));
+                    assert_eq!(stream.valid().then(|| (stream.index(), *stream.value())), keys.get(position).map(|&k| (k, k)));
+                }
+            }
+        }
+    };
+}
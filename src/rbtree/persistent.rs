@@ -0,0 +1,407 @@
+use std::{cmp::Ordering, fmt::{self, Debug}, marker::PhantomData, sync::Arc};
+
+use crate::streams::stream_defs::{IndexedStream, IntoStreamIterator};
+
+use super::traversal::{Nodes, Traversal};
+
+/// A subtree is balanced if neither side holds more than `DELTA` times the entries of the other
+pub(super) const DELTA: usize = 3;
+/// A rotation is single if the inner grandchild holds less than `GAMMA` times the entries of the outer one
+pub(super) const GAMMA: usize = 2;
+
+type Link<K, V> = Option<Arc<Node<K, V>>>;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    /// The number of entries in the subtree rooted at this node
+    size: usize,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+#[inline]
+fn size<K, V>(link: &Link<K, V>) -> usize {
+    link.as_ref().map_or(0, |node| node.size)
+}
+
+fn node<K, V>(key: K, value: V, left: Link<K, V>, right: Link<K, V>) -> Link<K, V> {
+    let size = 1 + size(&left) + size(&right);
+    Some(Arc::new(Node { key, value, size, left, right }))
+}
+
+/// Builds a node from parts that were balanced before one entry was inserted into or removed from them,
+/// with at most one rotation
+fn balance<K: Clone, V: Clone>(key: K, value: V, left: Link<K, V>, right: Link<K, V>) -> Link<K, V> {
+    let (l, r) = (size(&left), size(&right));
+    if l + r <= 1 {
+        node(key, value, left, right)
+    } else if r > DELTA * l {
+        rotate_left(key, value, left, right.expect("the heavier side is not empty"))
+    } else if l > DELTA * r {
+        rotate_right(key, value, left.expect("the heavier side is not empty"), right)
+    } else {
+        node(key, value, left, right)
+    }
+}
+
+fn rotate_left<K: Clone, V: Clone>(key: K, value: V, left: Link<K, V>, right: Arc<Node<K, V>>) -> Link<K, V> {
+    let (k2, v2) = (right.key.clone(), right.value.clone());
+    if size(&right.left) < GAMMA * size(&right.right) {
+        node(k2, v2, node(key, value, left, right.left.clone()), right.right.clone())
+    } else {
+        let inner = right.left.as_ref().expect("a double rotation has an inner grandchild");
+        node(
+            inner.key.clone(),
+            inner.value.clone(),
+            node(key, value, left, inner.left.clone()),
+            node(k2, v2, inner.right.clone(), right.right.clone()),
+        )
+    }
+}
+
+fn rotate_right<K: Clone, V: Clone>(key: K, value: V, left: Arc<Node<K, V>>, right: Link<K, V>) -> Link<K, V> {
+    let (k2, v2) = (left.key.clone(), left.value.clone());
+    if size(&left.right) < GAMMA * size(&left.left) {
+        node(k2, v2, left.left.clone(), node(key, value, left.right.clone(), right))
+    } else {
+        let inner = left.right.as_ref().expect("a double rotation has an inner grandchild");
+        node(
+            inner.key.clone(),
+            inner.value.clone(),
+            node(k2, v2, left.left.clone(), inner.left.clone()),
+            node(key, value, inner.right.clone(), right),
+        )
+    }
+}
+
+fn insert<K: Ord + Clone, V: Clone>(link: &Link<K, V>, key: K, value: V) -> Link<K, V> {
+    let Some(n) = link else {
+        return node(key, value, None, None);
+    };
+    match key.cmp(&n.key) {
+        Ordering::Less => balance(n.key.clone(), n.value.clone(), insert(&n.left, key, value), n.right.clone()),
+        Ordering::Greater => balance(n.key.clone(), n.value.clone(), n.left.clone(), insert(&n.right, key, value)),
+        Ordering::Equal => node(key, value, n.left.clone(), n.right.clone()),
+    }
+}
+
+/// Returns the subtree without `key`, or `None` if `key` is not in it (so nothing needs to be copied)
+fn remove<K: Ord + Clone, V: Clone>(link: &Link<K, V>, key: &K) -> Option<Link<K, V>> {
+    let n = link.as_ref()?;
+    Some(match key.cmp(&n.key) {
+        Ordering::Less => balance(n.key.clone(), n.value.clone(), remove(&n.left, key)?, n.right.clone()),
+        Ordering::Greater => balance(n.key.clone(), n.value.clone(), n.left.clone(), remove(&n.right, key)?),
+        Ordering::Equal => glue(&n.left, &n.right),
+    })
+}
+
+/// Joins the two children of a removed node, replacing it with an entry from the larger one
+fn glue<K: Clone, V: Clone>(left: &Link<K, V>, right: &Link<K, V>) -> Link<K, V> {
+    match (left, right) {
+        (None, _) => right.clone(),
+        (_, None) => left.clone(),
+        (Some(l), Some(r)) if l.size > r.size => {
+            let (key, value, rest) = remove_max(l);
+            balance(key, value, rest, right.clone())
+        }
+        (_, Some(r)) => {
+            let (key, value, rest) = remove_min(r);
+            balance(key, value, left.clone(), rest)
+        }
+    }
+}
+
+fn remove_min<K: Clone, V: Clone>(n: &Node<K, V>) -> (K, V, Link<K, V>) {
+    match &n.left {
+        None => (n.key.clone(), n.value.clone(), n.right.clone()),
+        Some(left) => {
+            let (key, value, rest) = remove_min(left);
+            (key, value, balance(n.key.clone(), n.value.clone(), rest, n.right.clone()))
+        }
+    }
+}
+
+fn remove_max<K: Clone, V: Clone>(n: &Node<K, V>) -> (K, V, Link<K, V>) {
+    match &n.right {
+        None => (n.key.clone(), n.value.clone(), n.left.clone()),
+        Some(right) => {
+            let (key, value, rest) = remove_max(right);
+            (key, value, balance(n.key.clone(), n.value.clone(), n.left.clone(), rest))
+        }
+    }
+}
+
+/// An immutable ordered map whose versions share their structure.
+/// `insert` and `remove` return a new version in O(log n) time, copying only the nodes on one path,
+/// and cloning a version is O(1), so readers can keep streaming over a snapshot
+/// (even from other threads) while writers derive new versions from it.
+/// The tree is weight-balanced: each subtree holds at most `DELTA` times the entries of its sibling.
+pub struct PersistentMap<K, V> {
+    root: Link<K, V>,
+}
+
+impl<K, V> Clone for PersistentMap<K, V> {
+    fn clone(&self) -> Self {
+        PersistentMap { root: self.root.clone() }
+    }
+}
+
+impl<K, V> Default for PersistentMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for PersistentMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V> PersistentMap<K, V> {
+    pub fn new() -> Self {
+        PersistentMap { root: None }
+    }
+
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Whether `self` and `other` are the same version (or share their whole tree),
+    /// which is cheaper than comparing their entries
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { traversal: Traversal::new(Refs(PhantomData), self.root.as_deref()) }
+    }
+}
+
+impl<K: Ord, V> PersistentMap<K, V> {
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let mut link = &self.root;
+        while let Some(n) = link {
+            match key.cmp(&n.key) {
+                Ordering::Less => link = &n.left,
+                Ordering::Greater => link = &n.right,
+                Ordering::Equal => return Some(&n.value),
+            }
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn stream_iter(&self) -> PersistentStream<'_, K, V> {
+        PersistentStream { iter: self.iter() }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> PersistentMap<K, V> {
+    /// A new version with `value` at `key`, replacing any previous value
+    pub fn insert(&self, key: K, value: V) -> Self {
+        PersistentMap { root: insert(&self.root, key, value) }
+    }
+
+    /// A new version without `key`; shares the whole tree with `self` if `key` is absent
+    pub fn remove(&self, key: &K) -> Self {
+        match remove(&self.root, key) {
+            Some(root) => PersistentMap { root },
+            None => self.clone(),
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> FromIterator<(K, V)> for PersistentMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        iter.into_iter().fold(PersistentMap::new(), |map, (k, v)| map.insert(k, v))
+    }
+}
+
+/// The nodes of a `PersistentMap`, which are reached through references
+struct Refs<'a, K, V>(PhantomData<&'a Node<K, V>>);
+
+impl<K, V> Clone for Refs<'_, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V> Copy for Refs<'_, K, V> {}
+
+impl<'a, K, V> Nodes<'a> for Refs<'a, K, V> {
+    type Handle = &'a Node<K, V>;
+    type K = K;
+    type V = V;
+
+    fn left(self, node: &'a Node<K, V>) -> Option<&'a Node<K, V>> {
+        node.left.as_deref()
+    }
+
+    fn right(self, node: &'a Node<K, V>) -> Option<&'a Node<K, V>> {
+        node.right.as_deref()
+    }
+
+    fn key(self, node: &'a Node<K, V>) -> &'a K {
+        &node.key
+    }
+
+    fn value(self, node: &'a Node<K, V>) -> &'a V {
+        &node.value
+    }
+
+    fn size(self, node: Option<&'a Node<K, V>>) -> usize {
+        node.map_or(0, |n| n.size)
+    }
+}
+
+/// An in-order iterator over a `PersistentMap`
+pub struct Iter<'a, K, V> {
+    traversal: Traversal<'a, Refs<'a, K, V>>,
+}
+
+impl<K, V> Clone for Iter<'_, K, V> {
+    fn clone(&self) -> Self {
+        Iter { traversal: self.traversal.clone() }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.traversal.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.traversal.remaining();
+        (len, Some(len))
+    }
+}
+
+/// A stream over a `PersistentMap`, seeking like the streams over an `RBTree`:
+/// a sequence of seeks through the whole map takes O(n) time in total
+#[derive(Clone)]
+pub struct PersistentStream<'a, K, V> {
+    iter: Iter<'a, K, V>,
+}
+
+impl<'a, K: Ord + Copy, V> IndexedStream for PersistentStream<'a, K, V> {
+    type I = K;
+    type V = &'a V;
+
+    fn valid(&self) -> bool {
+        self.iter.traversal.peek().is_some()
+    }
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    fn seek(&mut self, index: K, strict: bool) {
+        self.iter.traversal.seek(|k| if strict { *k <= index } else { *k < index });
+    }
+
+    fn next(&mut self) {
+        self.iter.next();
+    }
+
+    fn index(&self) -> K {
+        *self.iter.traversal.peek().expect("stream is valid").0
+    }
+
+    fn value(&self) -> &'a V {
+        self.iter.traversal.peek().expect("stream is valid").1
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.iter.traversal.remaining())
+    }
+}
+
+impl<'a, K: Ord + Copy, V> IntoStreamIterator for &'a PersistentMap<K, V> {
+    type IndexType = K;
+    type ValueType = &'a V;
+    type StreamType = PersistentStream<'a, K, V>;
+
+    fn into_stream_iterator(self) -> Self::StreamType {
+        self.stream_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, thread};
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::streams::stream_defs::IndexedStream;
+
+    use super::{size, Link, PersistentMap, DELTA};
+
+    stream_seek_tests!(PersistentMap);
+
+    /// Checks the sizes, the weight balance and the key order of a subtree
+    fn assert_balanced<K: Ord, V>(link: &Link<K, V>) {
+        if let Some(n) = link {
+            let (l, r) = (size(&n.left), size(&n.right));
+            assert_eq!(n.size, 1 + l + r);
+            assert!(l + r <= 1 || (l <= DELTA * r && r <= DELTA * l));
+            assert!(n.left.as_ref().is_none_or(|left| left.key < n.key));
+            assert!(n.right.as_ref().is_none_or(|right| n.key < right.key));
+            assert_balanced(&n.left);
+            assert_balanced(&n.right);
+        }
+    }
+
+    #[quickcheck]
+    fn test_versions(ops: Vec<(u8, Option<u16>)>) {
+        let mut versions = vec![(PersistentMap::new(), BTreeMap::new())];
+        for (k, v) in ops {
+            let (map, expected) = versions.last().unwrap();
+            let mut expected = expected.clone();
+            let map = match v {
+                Some(v) => {
+                    expected.insert(k, v);
+                    map.insert(k, v)
+                }
+                None => {
+                    expected.remove(&k);
+                    map.remove(&k)
+                }
+            };
+            assert_balanced(&map.root);
+            assert_eq!(map.get(&k), expected.get(&k));
+            versions.push((map, expected));
+        }
+        // Every earlier version is unchanged
+        for (map, expected) in versions {
+            assert_eq!(map.len(), expected.len());
+            assert!(map.iter().eq(expected.iter()));
+        }
+    }
+
+    #[test]
+    fn test_snapshot_reads() {
+        let mut map: PersistentMap<u32, u32> = (0..1000).map(|k| (k * 2, k)).collect();
+        let snapshot = map.clone();
+        let reader = thread::spawn(move || snapshot.stream_iter().map(|_, v| *v as u64).contract());
+        for k in 0..1000 {
+            map = map.insert(k * 2 + 1, 0).remove(&(k * 2));
+        }
+        assert_eq!(reader.join().unwrap(), 999 * 1000 / 2);
+        assert!(map.iter().all(|(k, v)| k % 2 == 1 && *v == 0));
+        assert!(map.remove(&0).ptr_eq(&map));
+    }
+}
@@ -14,9 +14,12 @@ use std::hint::unreachable_unchecked;
 use std::iter::{FromIterator, IntoIterator};
 use std::marker;
 use std::mem;
-use std::ops::Index;
+use std::ops::{Bound, Index, RangeBounds};
 use std::ptr;
 
+use crate::rbtree::aggregate::Aggregate;
+use crate::streams::parallel::Splittable;
+use crate::streams::stream_defs::FromStreamIterator;
 use crate::streams::stream_defs::IndexedStream;
 use crate::streams::stream_defs::IntoStreamIterator;
 
@@ -27,23 +30,27 @@ enum Color {
 }
 
 /*****************RBTreeNode***************************/
-struct RBTreeNode<K: Ord, V> {
+struct RBTreeNode<K: Ord, V, A: Aggregate<K, V>> {
     color: Color,
-    left: NodePtr<K, V>,
-    right: NodePtr<K, V>,
-    parent: NodePtr<K, V>,
+    left: NodePtr<K, V, A>,
+    right: NodePtr<K, V, A>,
+    parent: NodePtr<K, V, A>,
+    /// The number of nodes in the subtree rooted at this node
+    size: usize,
+    /// The aggregate of the entries in the subtree rooted at this node
+    agg: A::Value,
     key: K,
     value: V,
 }
 
-impl<K: Ord, V> RBTreeNode<K, V> {
+impl<K: Ord, V, A: Aggregate<K, V>> RBTreeNode<K, V, A> {
     #[inline]
     fn pair(self) -> (K, V) {
         (self.key, self.value)
     }
 }
 
-impl<K, V> Debug for RBTreeNode<K, V>
+impl<K, V, A: Aggregate<K, V>> Debug for RBTreeNode<K, V, A>
 where
     K: Ord + Debug,
     V: Debug,
@@ -55,43 +62,46 @@ where
 
 /*****************NodePtr***************************/
 #[derive(Debug)]
-struct NodePtr<K: Ord, V>(*mut RBTreeNode<K, V>);
+struct NodePtr<K: Ord, V, A: Aggregate<K, V> = ()>(*mut RBTreeNode<K, V, A>);
 
-impl<K: Ord, V> Clone for NodePtr<K, V> {
-    fn clone(&self) -> NodePtr<K, V> {
+impl<K: Ord, V, A: Aggregate<K, V>> Clone for NodePtr<K, V, A> {
+    fn clone(&self) -> NodePtr<K, V, A> {
         NodePtr(self.0)
     }
 }
 
-impl<K: Ord, V> Copy for NodePtr<K, V> {}
+impl<K: Ord, V, A: Aggregate<K, V>> Copy for NodePtr<K, V, A> {}
 
-impl<K: Ord, V> Ord for NodePtr<K, V> {
-    fn cmp(&self, other: &NodePtr<K, V>) -> Ordering {
+impl<K: Ord, V, A: Aggregate<K, V>> Ord for NodePtr<K, V, A> {
+    fn cmp(&self, other: &NodePtr<K, V, A>) -> Ordering {
         unsafe { (*self.0).key.cmp(&(*other.0).key) }
     }
 }
 
-impl<K: Ord, V> PartialOrd for NodePtr<K, V> {
-    fn partial_cmp(&self, other: &NodePtr<K, V>) -> Option<Ordering> {
+impl<K: Ord, V, A: Aggregate<K, V>> PartialOrd for NodePtr<K, V, A> {
+    fn partial_cmp(&self, other: &NodePtr<K, V, A>) -> Option<Ordering> {
         unsafe { Some((*self.0).key.cmp(&(*other.0).key)) }
     }
 }
 
-impl<K: Ord, V> PartialEq for NodePtr<K, V> {
-    fn eq(&self, other: &NodePtr<K, V>) -> bool {
+impl<K: Ord, V, A: Aggregate<K, V>> PartialEq for NodePtr<K, V, A> {
+    fn eq(&self, other: &NodePtr<K, V, A>) -> bool {
         self.0 == other.0
     }
 }
 
-impl<K: Ord, V> Eq for NodePtr<K, V> {}
+impl<K: Ord, V, A: Aggregate<K, V>> Eq for NodePtr<K, V, A> {}
 
-impl<K: Ord, V> NodePtr<K, V> {
-    fn new(k: K, v: V) -> NodePtr<K, V> {
+impl<K: Ord, V, A: Aggregate<K, V>> NodePtr<K, V, A> {
+    fn new(k: K, v: V) -> NodePtr<K, V, A> {
+        let agg = A::lift(&k, &v);
         let node = RBTreeNode {
             color: Color::Black,
             left: NodePtr::null(),
             right: NodePtr::null(),
             parent: NodePtr::null(),
+            size: 1,
+            agg,
             key: k,
             value: v,
         };
@@ -142,6 +152,112 @@ impl<K: Ord, V> NodePtr<K, V> {
         unsafe { (*self.0).color == Color::Black }
     }
 
+    /// The number of nodes in the subtree rooted at this node, 0 for null
+    #[inline]
+    fn size(&self) -> usize {
+        if self.is_null() {
+            return 0;
+        }
+        unsafe { (*self.0).size }
+    }
+
+    #[inline]
+    fn set_size(&mut self, size: usize) {
+        if self.is_null() {
+            return;
+        }
+        unsafe { (*self.0).size = size }
+    }
+
+    /// Recomputes the size and the aggregate of this node from its children
+    #[inline]
+    fn update(&mut self) {
+        let size = 1 + self.left().size() + self.right().size();
+        self.set_size(size);
+        unsafe {
+            let node = &mut *self.0;
+            let mut agg = A::lift(&node.key, &node.value);
+            if !node.left.is_null() {
+                agg = A::combine(&(*node.left.0).agg, &agg);
+            }
+            if !node.right.is_null() {
+                agg = A::combine(&agg, &(*node.right.0).agg);
+            }
+            node.agg = agg;
+        }
+    }
+
+    /// Recomputes the size and the aggregate of this node and of all its ancestors
+    #[inline]
+    fn update_to_root(self) {
+        let mut temp = self;
+        while !temp.is_null() {
+            temp.update();
+            temp = temp.parent();
+        }
+    }
+
+    /// The aggregate of the subtree rooted at this node, empty for null
+    #[inline]
+    fn agg(&self) -> A::Value {
+        if self.is_null() {
+            return A::empty();
+        }
+        unsafe { (*self.0).agg.clone() }
+    }
+
+    /// The number of nodes of the whole tree before this one
+    fn rank(self) -> usize {
+        let mut rank = self.left().size();
+        let mut temp = self;
+        while !temp.parent().is_null() {
+            if temp.is_right_child() {
+                rank += temp.parent().left().size() + 1;
+            }
+            temp = temp.parent();
+        }
+        rank
+    }
+
+    /// The `n`-th node (from 0) of the subtree rooted at this node, or null if it is too small
+    fn select(self, mut n: usize) -> NodePtr<K, V, A> {
+        let mut temp = self;
+        while !temp.is_null() {
+            let left = temp.left().size();
+            match n.cmp(&left) {
+                Ordering::Less => temp = temp.left(),
+                Ordering::Equal => return temp,
+                Ordering::Greater => {
+                    n -= left + 1;
+                    temp = temp.right();
+                }
+            }
+        }
+        NodePtr::null()
+    }
+
+    /// The node `n` positions after this one, or null if there is none.
+    /// Takes O(log n) time, climbing only as far as needed
+    fn advance_by(self, mut n: usize) -> NodePtr<K, V, A> {
+        // Climb until the node `n` positions ahead lies in the right subtree of `temp`
+        let mut temp = self;
+        while !temp.is_null() && n > temp.right().size() {
+            n -= temp.right().size();
+            loop {
+                let child = temp;
+                temp = temp.parent();
+                if temp.is_null() || temp.left() == child {
+                    break;
+                }
+            }
+            n -= 1;
+            if n == 0 {
+                return temp;
+            }
+        }
+        if n == 0 { temp } else { temp.right().select(n - 1) }
+    }
+
     #[inline]
     fn is_left_child(&self) -> bool {
         self.parent().left() == *self
@@ -153,7 +269,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn min_node(self) -> NodePtr<K, V> {
+    fn min_node(self) -> NodePtr<K, V, A> {
         let mut temp = self.clone();
         while !temp.left().is_null() {
             temp = temp.left();
@@ -162,7 +278,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn max_node(self) -> NodePtr<K, V> {
+    fn max_node(self) -> NodePtr<K, V, A> {
         let mut temp = self.clone();
         while !temp.right().is_null() {
             temp = temp.right();
@@ -171,7 +287,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn next(self) -> NodePtr<K, V> {
+    fn next(self) -> NodePtr<K, V, A> {
         if !self.right().is_null() {
             self.right().min_node()
         } else {
@@ -192,7 +308,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     /// cmp_fn should be downwards closed i.e., if cmp_fn(a), then for all b < a, cmp_fn(b)
     #[inline]
     #[allow(dead_code)]
-    fn seek_old(self, cmp_fn: impl Fn(&K) -> bool) -> NodePtr<K, V> {
+    fn seek_old(self, cmp_fn: impl Fn(&K) -> bool) -> NodePtr<K, V, A> {
         if self.is_null() ||!cmp_fn(unsafe { &(*self.0).key }) {
             return self;
         }
@@ -224,7 +340,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     /// Takes O(log n) time, where n=size of tree
     /// In addition, if a sequence of seek calls advances through the entire data structure, it takes at most O(n) time
     #[inline]
-    fn seek(self, cmp_fn: impl Fn(&K) -> bool) -> NodePtr<K, V> {
+    fn seek(self, cmp_fn: impl Fn(&K) -> bool) -> NodePtr<K, V, A> {
         if self.is_null() || !cmp_fn(unsafe { &(*self.0).key }) {
             return self; // minor edge case: never advance backwards
         }
@@ -301,7 +417,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn prev(self) -> NodePtr<K, V> {
+    fn prev(self) -> NodePtr<K, V, A> {
         if !self.left().is_null() {
             self.left().max_node()
         } else {
@@ -319,7 +435,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn set_parent(&mut self, parent: NodePtr<K, V>) {
+    fn set_parent(&mut self, parent: NodePtr<K, V, A>) {
         if self.is_null() {
             return;
         }
@@ -327,7 +443,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn set_left(&mut self, left: NodePtr<K, V>) {
+    fn set_left(&mut self, left: NodePtr<K, V, A>) {
         if self.is_null() {
             return;
         }
@@ -335,7 +451,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn set_right(&mut self, right: NodePtr<K, V>) {
+    fn set_right(&mut self, right: NodePtr<K, V, A>) {
         if self.is_null() {
             return;
         }
@@ -343,7 +459,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn parent(&self) -> NodePtr<K, V> {
+    fn parent(&self) -> NodePtr<K, V, A> {
         if self.is_null() {
             return NodePtr::null();
         }
@@ -351,7 +467,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn left(&self) -> NodePtr<K, V> {
+    fn left(&self) -> NodePtr<K, V, A> {
         if self.is_null() {
             return NodePtr::null();
         }
@@ -359,7 +475,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn right(&self) -> NodePtr<K, V> {
+    fn right(&self) -> NodePtr<K, V, A> {
         if self.is_null() {
             return NodePtr::null();
         }
@@ -367,7 +483,7 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 
     #[inline]
-    fn null() -> NodePtr<K, V> {
+    fn null() -> NodePtr<K, V, A> {
         NodePtr(ptr::null_mut())
     }
 
@@ -377,9 +493,12 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 }
 
-impl<K: Ord + Clone, V: Clone> NodePtr<K, V> {
-    unsafe fn deep_clone(&self) -> NodePtr<K, V> {
+impl<K: Ord + Clone, V: Clone, A: Aggregate<K, V>> NodePtr<K, V, A> {
+    unsafe fn deep_clone(&self) -> NodePtr<K, V, A> {
         let mut node = NodePtr::new((*self.0).key.clone(), (*self.0).value.clone());
+        node.set_color(self.get_color());
+        node.set_size(self.size());
+        (*node.0).agg = (*self.0).agg.clone();
         if !self.left().is_null() {
             node.set_left(self.left().deep_clone());
             node.left().set_parent(node);
@@ -446,19 +565,30 @@ impl<K: Ord + Clone, V: Clone> NodePtr<K, V> {
 ///   .iter().cloned().collect();
 ///  // use the values stored in rbtree
 ///  ```
-pub struct RBTree<K: Ord, V> {
-    root: NodePtr<K, V>,
+pub struct RBTree<K: Ord, V, A: Aggregate<K, V> = ()> {
+    root: NodePtr<K, V, A>,
     len: usize,
 }
 
-unsafe impl<K: Ord, V> Send for RBTree<K, V> {
+unsafe impl<K: Ord, V, A: Aggregate<K, V>> Send for RBTree<K, V, A> where A::Value: Send {
+}
+
+// `range_aggregate` clones the aggregates through a shared reference
+unsafe impl<K: Ord, V, A: Aggregate<K, V>> Sync for RBTree<K, V, A> where A::Value: Sync {
 }
 
-unsafe impl<K: Ord, V> Sync for RBTree<K, V> {
+/// An empty tree, which unlike `RBTree::new` also works for trees with aggregates
+impl<K: Ord, V, A: Aggregate<K, V>> Default for RBTree<K, V, A> {
+    fn default() -> RBTree<K, V, A> {
+        RBTree {
+            root: NodePtr::null(),
+            len: 0,
+        }
+    }
 }
 
 // Drop all owned pointers if the tree is dropped
-impl<K: Ord, V> Drop for RBTree<K, V> {
+impl<K: Ord, V, A: Aggregate<K, V>> Drop for RBTree<K, V, A> {
     #[inline]
     fn drop(&mut self) {
         self.clear();
@@ -466,18 +596,20 @@ impl<K: Ord, V> Drop for RBTree<K, V> {
 }
 
 /// If key and value are both impl Clone, we can call clone to get a copy.
-impl<K: Ord + Clone, V: Clone> Clone for RBTree<K, V> {
-    fn clone(&self) -> RBTree<K, V> {
+impl<K: Ord + Clone, V: Clone, A: Aggregate<K, V>> Clone for RBTree<K, V, A> {
+    fn clone(&self) -> RBTree<K, V, A> {
         unsafe {
-            let mut new = RBTree::new();
-            new.root = self.root.deep_clone();
+            let mut new = RBTree::default();
+            if !self.root.is_null() {
+                new.root = self.root.deep_clone();
+            }
             new.len = self.len;
             new
         }
     }
 }
 
-impl<K, V> Debug for RBTree<K, V>
+impl<K, V, A: Aggregate<K, V>> Debug for RBTree<K, V, A>
 where
     K: Ord + Debug,
     V: Debug,
@@ -488,8 +620,8 @@ where
 }
 
 /// This is a method to help us to get inner struct.
-impl<K: Ord + Debug, V: Debug> RBTree<K, V> {
-    fn tree_print(&self, node: NodePtr<K, V>, direction: i32) {
+impl<K: Ord + Debug, V: Debug, A: Aggregate<K, V>> RBTree<K, V, A> {
+    fn tree_print(&self, node: NodePtr<K, V, A>, direction: i32) {
         if node.is_null() {
             return;
         }
@@ -523,13 +655,103 @@ impl<K: Ord + Debug, V: Debug> RBTree<K, V> {
     }
 }
 
+/// A violation of the structure of an `RBTree`, found by `RBTree::check_invariants`.
+/// Nodes are identified by their in-order position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RBTreeError {
+    /// The root is red
+    RedRoot,
+    /// The root has a parent
+    RootHasParent,
+    /// A red node has a red child
+    RedChildOfRed { at: usize },
+    /// The paths below the children of a node pass through different numbers of black nodes
+    BlackHeightMismatch { at: usize, left: usize, right: usize },
+    /// A child of a node does not point back to it as its parent
+    BadParentPointer { at: usize },
+    /// A key is less than the key of the node before it
+    OutOfOrder { at: usize },
+    /// The size stored in a node is not the number of nodes in its subtree
+    WrongSize { at: usize, expected: usize, found: usize },
+    /// `len` is not the number of nodes in the tree
+    WrongLen { expected: usize, found: usize },
+}
+
+/// Checks the subtree of `node`, given the number of nodes before it,
+/// returning its black height and its number of nodes
+fn check_subtree<K: Ord, V, A: Aggregate<K, V>>(
+    node: NodePtr<K, V, A>,
+    before: usize,
+    prev: &mut NodePtr<K, V, A>,
+) -> Result<(usize, usize), RBTreeError> {
+    if node.is_null() {
+        return Ok((0, 0));
+    }
+    let (left_height, left_count) = check_subtree(node.left(), before, prev)?;
+    let at = before + left_count;
+    if !prev.is_null() && node < *prev {
+        return Err(RBTreeError::OutOfOrder { at });
+    }
+    *prev = node;
+    let (right_height, right_count) = check_subtree(node.right(), at + 1, prev)?;
+    for child in [node.left(), node.right()] {
+        if !child.is_null() && child.parent() != node {
+            return Err(RBTreeError::BadParentPointer { at });
+        }
+        if node.is_red_color() && child.is_red_color() {
+            return Err(RBTreeError::RedChildOfRed { at });
+        }
+    }
+    if left_height != right_height {
+        return Err(RBTreeError::BlackHeightMismatch { at, left: left_height, right: right_height });
+    }
+    let count = left_count + 1 + right_count;
+    if node.size() != count {
+        return Err(RBTreeError::WrongSize { at, expected: count, found: node.size() });
+    }
+    Ok((left_height + node.is_black_color() as usize, count))
+}
+
+/// Structural checks and statistics, for tests and debugging
+impl<K: Ord, V, A: Aggregate<K, V>> RBTree<K, V, A> {
+    /// Verifies the red-black properties, the parent pointers, the key order,
+    /// the subtree sizes and `len`, reporting the first violation found.
+    /// Takes O(n) time.
+    pub fn check_invariants(&self) -> Result<(), RBTreeError> {
+        if self.root.is_red_color() {
+            return Err(RBTreeError::RedRoot);
+        }
+        if !self.root.parent().is_null() {
+            return Err(RBTreeError::RootHasParent);
+        }
+        let (_, count) = check_subtree(self.root, 0, &mut NodePtr::null())?;
+        if count != self.len {
+            return Err(RBTreeError::WrongLen { expected: count, found: self.len });
+        }
+        Ok(())
+    }
+
+    /// The number of nodes on the longest path from the root down to a leaf
+    pub fn height(&self) -> usize {
+        fn height<K: Ord, V, A: Aggregate<K, V>>(node: NodePtr<K, V, A>) -> usize {
+            if node.is_null() { 0 } else { 1 + height(node.left()).max(height(node.right())) }
+        }
+        height(self.root)
+    }
+
+    /// The number of black nodes on every path from the root down to a leaf
+    pub fn black_height(&self) -> usize {
+        black_height(self.root)
+    }
+}
+
 /// all key be same, but it has multi key, if has multi key, it perhaps no correct
-impl<K, V> PartialEq for RBTree<K, V>
+impl<K, V, A: Aggregate<K, V>> PartialEq for RBTree<K, V, A>
 where
     K: Eq + Ord,
     V: PartialEq,
 {
-    fn eq(&self, other: &RBTree<K, V>) -> bool {
+    fn eq(&self, other: &RBTree<K, V, A>) -> bool {
         if self.len() != other.len() {
             return false;
         }
@@ -539,14 +761,14 @@ where
     }
 }
 
-impl<K, V> Eq for RBTree<K, V>
+impl<K, V, A: Aggregate<K, V>> Eq for RBTree<K, V, A>
 where
     K: Eq + Ord,
     V: Eq,
 {
 }
 
-impl<'a, K, V> Index<&'a K> for RBTree<K, V>
+impl<'a, K, V, A: Aggregate<K, V>> Index<&'a K> for RBTree<K, V, A>
 where
     K: Ord,
 {
@@ -558,16 +780,30 @@ where
     }
 }
 
-impl<K: Ord, V> FromIterator<(K, V)> for RBTree<K, V> {
-    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> RBTree<K, V> {
-        let mut tree = RBTree::new();
+impl<K: Ord, V, A: Aggregate<K, V>> FromIterator<(K, V)> for RBTree<K, V, A> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> RBTree<K, V, A> {
+        let mut tree = RBTree::default();
         tree.extend(iter);
         tree
     }
 }
 
+/// Builds a tree from sorted entries in O(n) time, see `RBTree::from_sorted_iter_aggregated`
+impl<K: Ord, V, A: Aggregate<K, V>> FromStreamIterator<K, V> for RBTree<K, V, A> {
+    fn from_stream_iterator<S: IndexedStream<I = K, V = V>>(iter: S) -> Self {
+        let mut entries = Vec::new();
+        iter.for_each(|k, v| entries.push((k, v)));
+        RBTree::from_sorted_iter_aggregated(entries)
+    }
+
+    /// Takes O(n + log m) time if the stream starts after the last key of the tree
+    fn extend_from_stream_iterator<S: IndexedStream<I = K, V = V>>(&mut self, iter: S) {
+        self.append(&mut RBTree::from_stream_iterator(iter));
+    }
+}
+
 /// RBTree into iter
-impl<K: Ord, V> Extend<(K, V)> for RBTree<K, V> {
+impl<K: Ord, V, A: Aggregate<K, V>> Extend<(K, V)> for RBTree<K, V, A> {
     fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
         let iter = iter.into_iter();
         for (k, v) in iter {
@@ -588,25 +824,25 @@ impl<K: Ord, V> Extend<(K, V)> for RBTree<K, V> {
 /// let key_vec: Vec<_> = m.keys().cloned().collect();
 /// assert_eq!(vec, key_vec);
 /// ```
-pub struct Keys<'a, K: Ord + 'a, V: 'a> {
-    inner: Iter<'a, K, V>,
+pub struct Keys<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V> = ()> {
+    inner: Iter<'a, K, V, A>,
 }
 
-impl<'a, K: Ord, V> Clone for Keys<'a, K, V> {
-    fn clone(&self) -> Keys<'a, K, V> {
+impl<'a, K: Ord, V, A: Aggregate<K, V>> Clone for Keys<'a, K, V, A> {
+    fn clone(&self) -> Keys<'a, K, V, A> {
         Keys {
             inner: self.inner.clone(),
         }
     }
 }
 
-impl<'a, K: Ord + Debug, V> fmt::Debug for Keys<'a, K, V> {
+impl<'a, K: Ord + Debug, V, A: Aggregate<K, V>> fmt::Debug for Keys<'a, K, V, A> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_list().entries(self.clone()).finish()
     }
 }
 
-impl<'a, K: Ord, V> Iterator for Keys<'a, K, V> {
+impl<'a, K: Ord, V, A: Aggregate<K, V>> Iterator for Keys<'a, K, V, A> {
     type Item = &'a K;
 
     #[inline]
@@ -633,25 +869,25 @@ impl<'a, K: Ord, V> Iterator for Keys<'a, K, V> {
 /// let key_vec: Vec<_> = m.values().cloned().collect();
 /// assert_eq!(vec, key_vec);
 /// ```
-pub struct Values<'a, K: 'a + Ord, V: 'a> {
-    inner: Iter<'a, K, V>,
+pub struct Values<'a, K: 'a + Ord, V: 'a, A: Aggregate<K, V> = ()> {
+    inner: Iter<'a, K, V, A>,
 }
 
-impl<'a, K: Ord, V> Clone for Values<'a, K, V> {
-    fn clone(&self) -> Values<'a, K, V> {
+impl<'a, K: Ord, V, A: Aggregate<K, V>> Clone for Values<'a, K, V, A> {
+    fn clone(&self) -> Values<'a, K, V, A> {
         Values {
             inner: self.inner.clone(),
         }
     }
 }
 
-impl<'a, K: Ord + Debug, V: Debug> fmt::Debug for Values<'a, K, V> {
+impl<'a, K: Ord + Debug, V: Debug, A: Aggregate<K, V>> fmt::Debug for Values<'a, K, V, A> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_list().entries(self.clone()).finish()
     }
 }
 
-impl<'a, K: Ord, V> Iterator for Values<'a, K, V> {
+impl<'a, K: Ord, V, A: Aggregate<K, V>> Iterator for Values<'a, K, V, A> {
     type Item = &'a V;
 
     #[inline]
@@ -714,21 +950,21 @@ impl<'a, K: Ord, V> Iterator for ValuesMut<'a, K, V> {
 }
 
 /// Convert RBTree to iter, move out the tree.
-pub struct IntoIter<K: Ord, V> {
-    head: NodePtr<K, V>,
-    tail: NodePtr<K, V>,
+pub struct IntoIter<K: Ord, V, A: Aggregate<K, V> = ()> {
+    head: NodePtr<K, V, A>,
+    tail: NodePtr<K, V, A>,
     len: usize,
 }
 
 // Drop all owned pointers if the collection is dropped
-impl<K: Ord, V> Drop for IntoIter<K, V> {
+impl<K: Ord, V, A: Aggregate<K, V>> Drop for IntoIter<K, V, A> {
     #[inline]
     fn drop(&mut self) {
         for (_, _) in self {}
     }
 }
 
-impl<K: Ord, V> Iterator for IntoIter<K, V> {
+impl<K: Ord, V, A: Aggregate<K, V>> Iterator for IntoIter<K, V, A> {
     type Item = (K, V);
 
     fn next(&mut self) -> Option<(K, V)> {
@@ -757,7 +993,7 @@ impl<K: Ord, V> Iterator for IntoIter<K, V> {
     }
 }
 
-impl<K: Ord, V> DoubleEndedIterator for IntoIter<K, V> {
+impl<K: Ord, V, A: Aggregate<K, V>> DoubleEndedIterator for IntoIter<K, V, A> {
     #[inline]
     fn next_back(&mut self) -> Option<(K, V)> {
         if self.len == 0 {
@@ -793,15 +1029,15 @@ impl<K: Ord, V> DoubleEndedIterator for IntoIter<K, V> {
 /// }
 /// assert_eq!(observed, 0xFFFF_FFFF);
 /// ```
-pub struct Iter<'a, K: Ord + 'a, V: 'a> {
-    head: NodePtr<K, V>,
-    tail: NodePtr<K, V>,
+pub struct Iter<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V> = ()> {
+    head: NodePtr<K, V, A>,
+    tail: NodePtr<K, V, A>,
     len: usize,
     _marker: marker::PhantomData<&'a ()>,
 }
 
-impl<'a, K: Ord + 'a, V: 'a> Clone for Iter<'a, K, V> {
-    fn clone(&self) -> Iter<'a, K, V> {
+impl<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V>> Clone for Iter<'a, K, V, A> {
+    fn clone(&self) -> Iter<'a, K, V, A> {
         Iter {
             head: self.head,
             tail: self.tail,
@@ -811,7 +1047,7 @@ impl<'a, K: Ord + 'a, V: 'a> Clone for Iter<'a, K, V> {
     }
 }
 
-impl<'a, K: Ord + 'a, V: 'a> Iterator for Iter<'a, K, V> {
+impl<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V>> Iterator for Iter<'a, K, V, A> {
     type Item = (&'a K, &'a V);
 
     fn next(&mut self) -> Option<(&'a K, &'a V)> {
@@ -834,7 +1070,7 @@ impl<'a, K: Ord + 'a, V: 'a> Iterator for Iter<'a, K, V> {
     }
 }
 
-impl<'a, K: Ord + 'a, V: 'a> DoubleEndedIterator for Iter<'a, K, V> {
+impl<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V>> DoubleEndedIterator for Iter<'a, K, V, A> {
     #[inline]
     fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
         // println!("len = {:?}", self.len);
@@ -849,32 +1085,51 @@ impl<'a, K: Ord + 'a, V: 'a> DoubleEndedIterator for Iter<'a, K, V> {
     }
 }
 
+/// A stream over the nodes from `head` up to (excluding) `end`, or to the last node if `end` is null
 #[derive(Clone)]
-pub struct IndexedStreamIter<'a, K: Ord + Copy + 'a, V: 'a> {
-    head: NodePtr<K, V>,
+pub struct IndexedStreamIter<'a, K: Ord + Copy + 'a, V: 'a, A: Aggregate<K, V> = ()> {
+    head: NodePtr<K, V, A>,
+    end: NodePtr<K, V, A>,
     _marker: marker::PhantomData<&'a ()>,
 }
 
-impl<'a, K: Ord + Copy, V> RBTree<K, V> {
-    pub fn stream_iter(&self) -> IndexedStreamIter<'_, K, V> {
+// The stream only reads the tree, like a shared reference to it
+unsafe impl<'a, K: Ord + Copy + Sync, V: Sync, A: Aggregate<K, V>> Send for IndexedStreamIter<'a, K, V, A> where A::Value: Sync {}
+
+unsafe impl<'a, K: Ord + Copy + Sync, V: Sync, A: Aggregate<K, V>> Sync for IndexedStreamIter<'a, K, V, A> where A::Value: Sync {}
+
+impl<'a, K: Ord + Copy, V, A: Aggregate<K, V>> RBTree<K, V, A> {
+    pub fn stream_iter(&self) -> IndexedStreamIter<'_, K, V, A> {
         IndexedStreamIter {
             head: self.first_child(),
+            end: NodePtr::null(),
+            _marker: marker::PhantomData,
+        }
+    }
+
+    /// A stream over the entries with keys in `range`, which becomes invalid past its end
+    /// rather than scanning the rest of the tree
+    pub fn stream_range<R: RangeBounds<K>>(&self, range: R) -> IndexedStreamIter<'_, K, V, A> {
+        let (head, end) = self.range_nodes(&range);
+        IndexedStreamIter {
+            head,
+            end,
             _marker: marker::PhantomData,
         }
     }
 } 
 
-impl<'a, K: Ord + Copy, V> IntoStreamIterator for &'a RBTree<K, V> {
+impl<'a, K: Ord + Copy, V, A: Aggregate<K, V>> IntoStreamIterator for &'a RBTree<K, V, A> {
     type IndexType = K;
     type ValueType = &'a V;
-    type StreamType = IndexedStreamIter<'a, K, V>;
+    type StreamType = IndexedStreamIter<'a, K, V, A>;
 
-    fn into_stream_iterator(self) -> IndexedStreamIter<'a, K, V> {
+    fn into_stream_iterator(self) -> IndexedStreamIter<'a, K, V, A> {
         self.stream_iter()
     }
 }
 
-impl<'a, K, V> IndexedStream for IndexedStreamIter<'a, K, V>
+impl<'a, K, V, A: Aggregate<K, V>> IndexedStream for IndexedStreamIter<'a, K, V, A>
 where
     K: Ord + Copy + 'a,
     V: 'a
@@ -883,7 +1138,8 @@ where
     type V = &'a V;
 
     fn valid(&self) -> bool {
-        !self.head.is_null()
+        // Seeking may jump over `end`, so the keys are compared rather than the nodes
+        !self.head.is_null() && (self.end.is_null() || self.head < self.end)
     }
 
     fn ready(&self) -> bool {
@@ -913,6 +1169,72 @@ where
             self.head = self.head.seek(|k2| *k2 < k);
         }
     }
+
+    /// Exact, from the subtree sizes
+    fn remaining_estimate(&self) -> Option<usize> {
+        if !self.valid() {
+            return Some(0);
+        }
+        let end = if self.end.is_null() {
+            let mut root = self.head;
+            while !root.parent().is_null() {
+                root = root.parent();
+            }
+            root.size()
+        } else {
+            self.end.rank()
+        };
+        Some(end - self.head.rank())
+    }
+}
+
+impl<'a, K: Ord + Copy + 'a, V: 'a, A: Aggregate<K, V>> IndexedStreamIter<'a, K, V, A> {
+    /// Skips the next `n` entries of the stream in O(log n) time,
+    /// e.g. to jump to a page of results
+    pub fn advance_by(&mut self, n: usize) {
+        if self.valid() {
+            self.head = self.head.advance_by(n);
+        }
+    }
+}
+
+impl<'a, K, V, A: Aggregate<K, V>> Splittable for IndexedStreamIter<'a, K, V, A>
+where
+    K: Ord + Copy + 'a,
+    V: 'a
+{
+    /// Splits at the highest ancestor of `head` within the stream, i.e. at the boundary of the largest
+    /// subtree the stream starts in, or else at the root of the right subtree of `head`
+    fn split_point(&self) -> Option<K> {
+        if !self.valid() {
+            return None;
+        }
+        let in_range = |node: NodePtr<K, V, A>| self.head < node && (self.end.is_null() || node < self.end);
+        let mut best = NodePtr::null();
+        let mut node = self.head.parent();
+        while !node.is_null() {
+            if in_range(node) {
+                best = node;
+            }
+            node = node.parent();
+        }
+        if best.is_null() {
+            best = self.head.right();
+            while !best.is_null() && !in_range(best) {
+                best = best.left();
+            }
+        }
+        (!best.is_null()).then(|| unsafe { (*best.0).key })
+    }
+
+    fn split_at(self, index: K) -> (Self, Self) {
+        let mid = if self.valid() { self.head.seek(|k| *k < index) } else { self.head };
+        let mid = if !self.end.is_null() && !mid.is_null() && mid > self.end { self.end } else { mid };
+        (
+            IndexedStreamIter { head: self.head, end: mid, _marker: marker::PhantomData },
+            IndexedStreamIter { head: mid, end: self.end, _marker: marker::PhantomData },
+        )
+    }
 }
 
 /// provide iter mut ref for RBTree
@@ -990,12 +1312,12 @@ impl<'a, K: Ord + 'a, V: 'a> DoubleEndedIterator for IterMut<'a, K, V> {
     }
 }
 
-impl<K: Ord, V> IntoIterator for RBTree<K, V> {
+impl<K: Ord, V, A: Aggregate<K, V>> IntoIterator for RBTree<K, V, A> {
     type Item = (K, V);
-    type IntoIter = IntoIter<K, V>;
+    type IntoIter = IntoIter<K, V, A>;
 
     #[inline]
-    fn into_iter(mut self) -> IntoIter<K, V> {
+    fn into_iter(mut self) -> IntoIter<K, V, A> {
         let iter = if self.root.is_null() {
             IntoIter {
                 head: NodePtr::null(),
@@ -1014,15 +1336,7 @@ impl<K: Ord, V> IntoIterator for RBTree<K, V> {
     }
 }
 
-impl<K: Ord, V> RBTree<K, V> {
-    /// Creates an empty `RBTree`.
-    pub fn new() -> RBTree<K, V> {
-        RBTree {
-            root: NodePtr::null(),
-            len: 0,
-        }
-    }
-
+impl<K: Ord, V, A: Aggregate<K, V>> RBTree<K, V, A> {
     /// Returns the len of `RBTree`.
     #[inline]
     pub fn len(&self) -> usize {
@@ -1050,7 +1364,7 @@ impl<K: Ord, V> RBTree<K, V> {
      *
      */
     #[inline]
-    unsafe fn left_rotate(&mut self, mut node: NodePtr<K, V>) {
+    unsafe fn left_rotate(&mut self, mut node: NodePtr<K, V, A>) {
         let mut temp = node.right();
         node.set_right(temp.left());
 
@@ -1069,6 +1383,8 @@ impl<K: Ord, V> RBTree<K, V> {
 
         temp.set_left(node.clone());
         node.set_parent(temp.clone());
+        node.update();
+        temp.update();
     }
 
     /*
@@ -1085,7 +1401,7 @@ impl<K: Ord, V> RBTree<K, V> {
      *
      */
     #[inline]
-    unsafe fn right_rotate(&mut self, mut node: NodePtr<K, V>) {
+    unsafe fn right_rotate(&mut self, mut node: NodePtr<K, V, A>) {
         let mut temp = node.left();
         node.set_left(temp.right());
 
@@ -1104,6 +1420,8 @@ impl<K: Ord, V> RBTree<K, V> {
 
         temp.set_right(node.clone());
         node.set_parent(temp.clone());
+        node.update();
+        temp.update();
     }
 
     /// replace value if key exist, if not exist insert it.
@@ -1129,12 +1447,13 @@ impl<K: Ord, V> RBTree<K, V> {
         unsafe {
             mem::swap(&mut v, &mut (*node.0).value);
         }
+        node.update_to_root();
 
         Some(v)
     }
 
     #[inline]
-    unsafe fn insert_fixup(&mut self, mut node: NodePtr<K, V>) {
+    unsafe fn insert_fixup(&mut self, mut node: NodePtr<K, V, A>) {
         let mut parent;
         let mut gparent;
 
@@ -1196,7 +1515,7 @@ impl<K: Ord, V> RBTree<K, V> {
     #[inline]
     pub fn insert(&mut self, k: K, v: V) {
         self.len += 1;
-        let mut node = NodePtr::new(k, v);
+        let node = NodePtr::new(k, v);
         let mut y = NodePtr::null();
         let mut x = self.root;
 
@@ -1211,29 +1530,33 @@ impl<K: Ord, V> RBTree<K, V> {
                 }
             };
         }
-        node.set_parent(y);
+        let left = !y.is_null() && node < y;
+        unsafe {
+            self.attach(node, y, left);
+        }
+    }
 
-        if y.is_null() {
+    /// Links the new node `node` below `parent` (as its left child if `left`, or as the root
+    /// if `parent` is null) and rebalances the tree.
+    /// The caller accounts for the node in `len`.
+    #[inline]
+    unsafe fn attach(&mut self, mut node: NodePtr<K, V, A>, mut parent: NodePtr<K, V, A>, left: bool) {
+        node.set_parent(parent);
+        if parent.is_null() {
             self.root = node;
+        } else if left {
+            parent.set_left(node);
         } else {
-            match node.cmp(&&mut y) {
-                Ordering::Less => {
-                    y.set_left(node);
-                }
-                _ => {
-                    y.set_right(node);
-                }
-            };
+            parent.set_right(node);
         }
-
+        // The rotations below keep the sizes and aggregates of the nodes they move correct
+        parent.update_to_root();
         node.set_red_color();
-        unsafe {
-            self.insert_fixup(node);
-        }
+        self.insert_fixup(node);
     }
 
     #[inline]
-    fn find_node(&self, k: &K) -> NodePtr<K, V> {
+    fn find_node(&self, k: &K) -> NodePtr<K, V, A> {
         if self.root.is_null() {
             return NodePtr::null();
         }
@@ -1255,7 +1578,7 @@ impl<K: Ord, V> RBTree<K, V> {
     }
 
     #[inline]
-    fn first_child(&self) -> NodePtr<K, V> {
+    fn first_child(&self) -> NodePtr<K, V, A> {
         if self.root.is_null() {
             NodePtr::null()
         } else {
@@ -1268,7 +1591,7 @@ impl<K: Ord, V> RBTree<K, V> {
     }
 
     #[inline]
-    fn last_child(&self) -> NodePtr<K, V> {
+    fn last_child(&self) -> NodePtr<K, V, A> {
         if self.root.is_null() {
             NodePtr::null()
         } else {
@@ -1317,54 +1640,135 @@ impl<K: Ord, V> RBTree<K, V> {
     }
 
     #[inline]
-    pub fn get_first_mut(&mut self) -> Option<(&K, &mut V)> {
-        let first = self.first_child();
-        if first.is_null() {
+    pub fn get(&self, k: &K) -> Option<&V> {
+        let node = self.find_node(k);
+        if node.is_null() {
             return None;
         }
-        unsafe { Some((&(*first.0).key, &mut (*first.0).value)) }
-    }
 
-    #[inline]
-    pub fn get_last_mut(&mut self) -> Option<(&K, &mut V)> {
-        let last = self.last_child();
-        if last.is_null() {
-            return None;
-        }
-        unsafe { Some((&(*last.0).key, &mut (*last.0).value)) }
+        unsafe { Some(&(*node.0).value) }
     }
 
     #[inline]
-    pub fn get(&self, k: &K) -> Option<&V> {
+    pub fn contains_key(&self, k: &K) -> bool {
         let node = self.find_node(k);
         if node.is_null() {
-            return None;
+            return false;
         }
+        true
+    }
 
-        unsafe { Some(&(*node.0).value) }
+    /// Returns the number of keys less than `k`.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::rbtree_lib::RBTree;
+    /// let m: RBTree<i32, ()> = [10, 20, 30].into_iter().map(|k| (k, ())).collect();
+    /// assert_eq!(m.rank(&20), 1);
+    /// assert_eq!(m.rank(&25), 2);
+    /// ```
+    pub fn rank(&self, k: &K) -> usize {
+        let mut rank = 0;
+        let mut temp = self.root;
+        while !temp.is_null() {
+            unsafe {
+                if (*temp.0).key < *k {
+                    rank += temp.left().size() + 1;
+                    temp = temp.right();
+                } else {
+                    temp = temp.left();
+                }
+            }
+        }
+        rank
+    }
+
+    /// Returns the `n`-th smallest entry (from 0), if the tree has more than `n` entries.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::rbtree_lib::RBTree;
+    /// let m: RBTree<i32, i32> = (0..100).map(|k| (k * 2, k)).collect();
+    /// // The median
+    /// assert_eq!(m.select(m.len() / 2), Some((&100, &50)));
+    /// assert_eq!(m.select(100), None);
+    /// ```
+    pub fn select(&self, n: usize) -> Option<(&K, &V)> {
+        self.node_pair(self.root.select(n))
     }
 
     #[inline]
-    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
-        let node = self.find_node(k);
+    fn node_pair(&self, node: NodePtr<K, V, A>) -> Option<(&K, &V)> {
         if node.is_null() {
             return None;
         }
-
-        unsafe { Some(&mut (*node.0).value) }
+        unsafe { Some((&(*node.0).key, &(*node.0).value)) }
     }
 
+    /// The first node whose key does not satisfy `cmp_fn`, see `NodePtr::seek`
     #[inline]
-    pub fn contains_key(&self, k: &K) -> bool {
-        let node = self.find_node(k);
-        if node.is_null() {
-            return false;
+    fn first_node_after(&self, cmp_fn: impl Fn(&K) -> bool) -> NodePtr<K, V, A> {
+        self.first_child().seek(cmp_fn)
+    }
+
+    /// The first node in `range` and the first node after it (null if there is none).
+    /// If the range is empty, both are the same node.
+    fn range_nodes<R: RangeBounds<K>>(&self, range: &R) -> (NodePtr<K, V, A>, NodePtr<K, V, A>) {
+        let head = match range.start_bound() {
+            Bound::Included(a) => self.first_node_after(|k| k < a),
+            Bound::Excluded(a) => self.first_node_after(|k| k <= a),
+            Bound::Unbounded => self.first_child(),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(b) => head.seek(|k| k <= b),
+            Bound::Excluded(b) => head.seek(|k| k < b),
+            Bound::Unbounded => NodePtr::null(),
+        };
+        (head, end)
+    }
+
+    /// Returns an iterator over the entries with keys in `range`.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::rbtree_lib::RBTree;
+    /// let m: RBTree<i32, i32> = (0..10).map(|k| (k, k * k)).collect();
+    /// assert_eq!(m.range(3..6).map(|(_, v)| *v).collect::<Vec<_>>(), vec![9, 16, 25]);
+    /// assert_eq!(m.range(8..).rev().next(), Some((&9, &81)));
+    /// ```
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K, V, A> {
+        let (head, end) = self.range_nodes(&range);
+        let end_rank = if end.is_null() { self.len } else { end.rank() };
+        let head_rank = if head.is_null() { self.len } else { head.rank() };
+        let tail = if end.is_null() { self.last_child() } else { end.prev() };
+        Iter {
+            head,
+            tail,
+            len: end_rank.saturating_sub(head_rank),
+            _marker: marker::PhantomData,
         }
-        true
+    }
+
+    /// Returns the first entry with a key greater than or equal to `k`.
+    pub fn lower_bound(&self, k: &K) -> Option<(&K, &V)> {
+        self.node_pair(self.first_node_after(|k2| k2 < k))
+    }
+
+    /// Returns the first entry with a key greater than `k`.
+    pub fn upper_bound(&self, k: &K) -> Option<(&K, &V)> {
+        self.node_pair(self.first_node_after(|k2| k2 <= k))
+    }
+
+    /// Returns the last entry with a key less than `k`.
+    pub fn predecessor(&self, k: &K) -> Option<(&K, &V)> {
+        let next = self.first_node_after(|k2| k2 < k);
+        self.node_pair(if next.is_null() { self.last_child() } else { next.prev() })
+    }
+
+    /// Returns the first entry with a key greater than `k`, like `upper_bound`.
+    pub fn successor(&self, k: &K) -> Option<(&K, &V)> {
+        self.upper_bound(k)
     }
 
     #[inline]
-    fn clear_recurse(&mut self, current: NodePtr<K, V>) {
+    fn clear_recurse(&mut self, current: NodePtr<K, V, A>) {
         if !current.is_null() {
             unsafe {
                 self.clear_recurse(current.left());
@@ -1403,15 +1807,21 @@ impl<K: Ord, V> RBTree<K, V> {
 
     #[inline]
     pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.remove_entry(k).map(|(_, v)| v)
+    }
+
+    /// Removes the entry with the key `k`, returning the stored key along with the value
+    #[inline]
+    pub fn remove_entry(&mut self, k: &K) -> Option<(K, V)> {
         let node = self.find_node(k);
         if node.is_null() {
             return None;
         }
-        unsafe { Some(self.delete(node).1) }
+        unsafe { Some(self.delete(node)) }
     }
 
     #[inline]
-    unsafe fn delete_fixup(&mut self, mut node: NodePtr<K, V>, mut parent: NodePtr<K, V>) {
+    unsafe fn delete_fixup(&mut self, mut node: NodePtr<K, V, A>, mut parent: NodePtr<K, V, A>) {
         let mut other;
         while node != self.root && node.is_black_color() {
             if parent.left() == node {
@@ -1483,7 +1893,15 @@ impl<K: Ord, V> RBTree<K, V> {
     }
 
     #[inline]
-    unsafe fn delete(&mut self, node: NodePtr<K, V>) -> (K, V) {
+    unsafe fn delete(&mut self, node: NodePtr<K, V, A>) -> (K, V) {
+        self.unlink(node);
+        let obj = Box::from_raw(node.0);
+        obj.pair()
+    }
+
+    /// Removes `node` from the tree and rebalances it, without freeing the node
+    #[inline]
+    unsafe fn unlink(&mut self, node: NodePtr<K, V, A>) {
         let mut child;
         let mut parent;
         let color;
@@ -1524,13 +1942,13 @@ impl<K: Ord, V> RBTree<K, V> {
             replace.set_color(node.get_color());
             replace.set_left(node.left());
             node.left().set_parent(replace);
+            // `parent` is where `replace` was taken from, below its new position
+            parent.update_to_root();
 
             if color == Color::Black {
                 self.delete_fixup(child, parent);
             }
-
-            let obj = Box::from_raw(node.0);
-            return obj.pair();
+            return;
         }
 
         if !node.left().is_null() {
@@ -1554,38 +1972,28 @@ impl<K: Ord, V> RBTree<K, V> {
                 parent.set_right(child);
             }
         }
+        parent.update_to_root();
 
         if color == Color::Black {
             self.delete_fixup(child, parent);
         }
-
-        let obj = Box::from_raw(node.0);
-        return obj.pair();
     }
 
     /// Return the keys iter
     #[inline]
-    pub fn keys(&self) -> Keys<K, V> {
+    pub fn keys(&self) -> Keys<K, V, A> {
         Keys { inner: self.iter() }
     }
 
     /// Return the value iter
     #[inline]
-    pub fn values(&self) -> Values<K, V> {
+    pub fn values(&self) -> Values<K, V, A> {
         Values { inner: self.iter() }
     }
 
-    /// Return the value iter mut
-    #[inline]
-    pub fn values_mut(&mut self) -> ValuesMut<K, V> {
-        ValuesMut {
-            inner: self.iter_mut(),
-        }
-    }
-
     /// Return the key and value iter
     #[inline]
-    pub fn iter(&self) -> Iter<K, V> {
+    pub fn iter(&self) -> Iter<K, V, A> {
         Iter {
             head: self.first_child(),
             tail: self.last_child(),
@@ -1594,46 +2002,808 @@ impl<K: Ord, V> RBTree<K, V> {
         }
     }
 
-    /// Return the key and mut value iter
-    #[inline]
-    pub fn iter_mut(&mut self) -> IterMut<K, V> {
-        IterMut {
-            head: self.first_child(),
-            tail: self.last_child(),
-            len: self.len,
-            _marker: marker::PhantomData,
-        }
-    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::RBTree;
-
-    #[test]
-    fn test_insert() {
-        let mut m = RBTree::new();
-        assert_eq!(m.len(), 0);
-        m.insert(1, 2);
-        assert_eq!(m.len(), 1);
-        m.insert(2, 4);
-        assert_eq!(m.len(), 2);
-        m.insert(2, 6);
-        assert_eq!(m.len(), 3);
-        assert_eq!(*m.get(&1).unwrap(), 2);
-        assert_eq!(*m.get(&2).unwrap(), 4);
-        assert_eq!(*m.get(&2).unwrap(), 4);
-    }
+/*****************Split and join***************************/
+/// The aggregate of the nodes in the subtree of `node` with keys in `range`.
+/// Only the bounds for which `check_start` or `check_end` is set may exclude any of the nodes.
+fn range_aggregate_nodes<K: Ord, V, A: Aggregate<K, V>, R: RangeBounds<K>>(
+    node: NodePtr<K, V, A>,
+    range: &R,
+    check_start: bool,
+    check_end: bool,
+) -> A::Value {
+    if node.is_null() || !(check_start || check_end) {
+        return node.agg();
+    }
+    let (key, value) = unsafe { (&(*node.0).key, &(*node.0).value) };
+    let after_start = match range.start_bound() {
+        Bound::Included(a) => key >= a,
+        Bound::Excluded(a) => key > a,
+        Bound::Unbounded => true,
+    };
+    let before_end = match range.end_bound() {
+        Bound::Included(b) => key <= b,
+        Bound::Excluded(b) => key < b,
+        Bound::Unbounded => true,
+    };
+    if check_start && !after_start {
+        return range_aggregate_nodes(node.right(), range, check_start, check_end);
+    }
+    if check_end && !before_end {
+        return range_aggregate_nodes(node.left(), range, check_start, check_end);
+    }
+    // Below the node where the bounds part ways, each side is only cut by one of them,
+    // so one of the two calls returns at once
+    let left = range_aggregate_nodes(node.left(), range, check_start, false);
+    let right = range_aggregate_nodes(node.right(), range, false, check_end);
+    A::combine(&A::combine(&left, &A::lift(key, value)), &right)
+}
 
-    #[test]
-    fn test_replace() {
-        let mut m = RBTree::new();
-        assert_eq!(m.len(), 0);
-        m.insert(2, 4);
-        assert_eq!(m.len(), 1);
-        assert_eq!(m.replace_or_insert(2, 6).unwrap(), 4);
-        assert_eq!(m.len(), 1);
-        assert_eq!(*m.get(&2).unwrap(), 6);
+impl<K: Ord, V, A: Aggregate<K, V>> RBTree<K, V, A> {
+    /// The aggregate of all entries, in O(1) time
+    pub fn aggregate(&self) -> A::Value {
+        self.root.agg()
+    }
+
+    /// The aggregate of the entries with keys in `range`, in O(log n) time.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::{aggregate::Sum, rbtree_lib::RBTree};
+    /// let prices: RBTree<u32, u64, Sum> = (0..100).map(|day| (day, day as u64 * 10)).collect();
+    /// assert_eq!(prices.range_aggregate(10..20), (10..20).map(|day| day * 10).sum());
+    /// assert_eq!(prices.aggregate(), 49500);
+    /// ```
+    pub fn range_aggregate<R: RangeBounds<K>>(&self, range: R) -> A::Value {
+        range_aggregate_nodes(self.root, &range, true, true)
+    }
+}
+
+/// Constructors for trees without aggregates, whose type can thus be inferred.
+/// `RBTree::default` and `RBTree::from_sorted_iter_aggregated` build trees with any aggregate.
+impl<K: Ord, V> RBTree<K, V> {
+    /// Creates an empty `RBTree`.
+    pub fn new() -> RBTree<K, V> {
+        RBTree {
+            root: NodePtr::null(),
+            len: 0,
+        }
+    }
+
+    /// Builds a tree from entries sorted by key in O(n) time, see `RBTree::from_sorted_iter_aggregated`.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::rbtree_lib::RBTree;
+    /// let m = RBTree::from_sorted_iter((0..100).map(|k| (k, k * k)));
+    /// assert_eq!(m.len(), 100);
+    /// assert_eq!(m.get(&7), Some(&49));
+    /// ```
+    pub fn from_sorted_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> RBTree<K, V> {
+        RBTree::from_sorted_iter_aggregated(iter)
+    }
+}
+
+/// Mutable access to the values, only for trees without aggregates,
+/// as changing a value in place would leave the aggregates above it stale
+impl<K: Ord, V> RBTree<K, V> {
+    #[inline]
+    pub fn get_first_mut(&mut self) -> Option<(&K, &mut V)> {
+        let first = self.first_child();
+        if first.is_null() {
+            return None;
+        }
+        unsafe { Some((&(*first.0).key, &mut (*first.0).value)) }
+    }
+
+    #[inline]
+    pub fn get_last_mut(&mut self) -> Option<(&K, &mut V)> {
+        let last = self.last_child();
+        if last.is_null() {
+            return None;
+        }
+        unsafe { Some((&(*last.0).key, &mut (*last.0).value)) }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+        let node = self.find_node(k);
+        if node.is_null() {
+            return None;
+        }
+
+        unsafe { Some(&mut (*node.0).value) }
+    }
+
+    /// Return the value iter mut
+    #[inline]
+    pub fn values_mut(&mut self) -> ValuesMut<K, V> {
+        ValuesMut {
+            inner: self.iter_mut(),
+        }
+    }
+
+    /// Return the key and mut value iter
+    #[inline]
+    pub fn iter_mut(&mut self) -> IterMut<K, V> {
+        IterMut {
+            head: self.first_child(),
+            tail: self.last_child(),
+            len: self.len,
+            _marker: marker::PhantomData,
+        }
+    }
+}
+
+// The operations below work on detached subtrees, given by their root. A detached subtree
+// is a valid red-black tree once its root is colored black, with sizes kept up to date.
+
+/// Detaches `root` from its parent and colors it black
+#[inline]
+fn detach_root<K: Ord, V, A: Aggregate<K, V>>(mut root: NodePtr<K, V, A>) -> NodePtr<K, V, A> {
+    root.set_parent(NodePtr::null());
+    root.set_black_color();
+    root
+}
+
+/// The number of black nodes on the paths from `root` down to its leaves
+#[inline]
+fn black_height<K: Ord, V, A: Aggregate<K, V>>(root: NodePtr<K, V, A>) -> usize {
+    let mut height = 0;
+    let mut temp = root;
+    while !temp.is_null() {
+        if temp.is_black_color() {
+            height += 1;
+        }
+        temp = temp.left();
+    }
+    height
+}
+
+/// Joins the trees `left` and `right` with `pivot` in between, in O(|log |left| - log |right||) time.
+/// All keys of `left` must be less than the key of `pivot`, and those of `right` at least that key.
+unsafe fn join_nodes<K: Ord, V, A: Aggregate<K, V>>(left: NodePtr<K, V, A>, mut pivot: NodePtr<K, V, A>, right: NodePtr<K, V, A>) -> NodePtr<K, V, A> {
+    let (mut left, mut right) = (detach_root(left), detach_root(right));
+    let (left_height, right_height) = (black_height(left), black_height(right));
+    if left_height == right_height {
+        pivot.set_left(left);
+        left.set_parent(pivot);
+        pivot.set_right(right);
+        right.set_parent(pivot);
+        pivot.update();
+        return detach_root(pivot);
+    }
+
+    // Descend the spine of the taller tree facing the other one, down to a black node
+    // of the other tree's black height, and put the pivot in its place with the other tree beside it
+    let taller_left = left_height > right_height;
+    let (root, target) = if taller_left { (left, right_height) } else { (right, left_height) };
+    let mut parent = NodePtr::null();
+    let mut current = root;
+    let mut height = left_height.max(right_height);
+    while !(current.is_black_color() && height == target) {
+        if current.is_black_color() {
+            height -= 1;
+        }
+        parent = current;
+        current = if taller_left { current.right() } else { current.left() };
+    }
+    if taller_left {
+        pivot.set_left(current);
+        current.set_parent(pivot);
+        pivot.set_right(right);
+        right.set_parent(pivot);
+        parent.set_right(pivot);
+    } else {
+        pivot.set_left(left);
+        left.set_parent(pivot);
+        pivot.set_right(current);
+        current.set_parent(pivot);
+        parent.set_left(pivot);
+    }
+    pivot.set_parent(parent);
+    pivot.set_red_color();
+    pivot.update_to_root();
+
+    let mut tree = RBTree { root, len: 0 };
+    tree.insert_fixup(pivot);
+    tree.into_root()
+}
+
+/// Joins the trees `left` and `right`, whose keys must all be less than those of `right`
+unsafe fn join_two<K: Ord, V, A: Aggregate<K, V>>(left: NodePtr<K, V, A>, right: NodePtr<K, V, A>) -> NodePtr<K, V, A> {
+    if left.is_null() {
+        return right;
+    }
+    let mut tree = RBTree { root: detach_root(left), len: left.size() };
+    let last = tree.last_child();
+    tree.unlink(last);
+    join_nodes(tree.into_root(), last, right)
+}
+
+/// Splits `root` into the nodes with keys less than `k` and the others
+unsafe fn split_nodes<K: Ord, V, A: Aggregate<K, V>>(root: NodePtr<K, V, A>, k: &K) -> (NodePtr<K, V, A>, NodePtr<K, V, A>) {
+    if root.is_null() {
+        return (NodePtr::null(), NodePtr::null());
+    }
+    let (left, right) = (detach_root(root.left()), detach_root(root.right()));
+    if *k <= (*root.0).key {
+        let (less, rest) = split_nodes(left, k);
+        (less, join_nodes(rest, root, right))
+    } else {
+        let (less, rest) = split_nodes(right, k);
+        (join_nodes(left, root, less), rest)
+    }
+}
+
+/// Splits `root` into the nodes with keys less than `k`, a node with key `k` (or null)
+/// and the nodes with greater keys. Assumes that the keys are distinct.
+#[allow(clippy::type_complexity)]
+unsafe fn split_at_key<K: Ord, V, A: Aggregate<K, V>>(root: NodePtr<K, V, A>, k: &K) -> (NodePtr<K, V, A>, NodePtr<K, V, A>, NodePtr<K, V, A>) {
+    if root.is_null() {
+        return (NodePtr::null(), NodePtr::null(), NodePtr::null());
+    }
+    let (left, right) = (detach_root(root.left()), detach_root(root.right()));
+    match k.cmp(&(*root.0).key) {
+        Ordering::Equal => (left, root, right),
+        Ordering::Less => {
+            let (less, found, rest) = split_at_key(left, k);
+            (less, found, join_nodes(rest, root, right))
+        }
+        Ordering::Greater => {
+            let (less, found, rest) = split_at_key(right, k);
+            (join_nodes(left, root, less), found, rest)
+        }
+    }
+}
+
+/// Links `nodes` into a tree, splitting them at the middle at every level.
+/// The nodes at depth `red_depth` are red, and all others black.
+fn build_balanced<K: Ord, V, A: Aggregate<K, V>>(nodes: &[NodePtr<K, V, A>], depth: usize, red_depth: usize) -> NodePtr<K, V, A> {
+    if nodes.is_empty() {
+        return NodePtr::null();
+    }
+    let mid = nodes.len() / 2;
+    let mut root = nodes[mid];
+    let mut left = build_balanced(&nodes[..mid], depth + 1, red_depth);
+    let mut right = build_balanced(&nodes[mid + 1..], depth + 1, red_depth);
+    root.set_left(left);
+    left.set_parent(root);
+    root.set_right(right);
+    right.set_parent(root);
+    root.update();
+    root.set_color(if depth == red_depth { Color::Red } else { Color::Black });
+    root
+}
+
+/// Frees a detached subtree
+unsafe fn free_nodes<K: Ord, V, A: Aggregate<K, V>>(root: NodePtr<K, V, A>) {
+    drop(RBTree::from_root(root));
+}
+
+unsafe fn union_nodes<K: Ord, V, A: Aggregate<K, V>>(a: NodePtr<K, V, A>, b: NodePtr<K, V, A>, f: &mut impl FnMut(&K, V, V) -> V) -> NodePtr<K, V, A> {
+    if a.is_null() {
+        return b;
+    }
+    if b.is_null() {
+        return a;
+    }
+    let (b_left, b_right) = (detach_root(b.left()), detach_root(b.right()));
+    let (a_left, found, a_right) = split_at_key(a, &(*b.0).key);
+    if !found.is_null() {
+        // `b` is kept as the pivot, with the combined value
+        let (_, a_value) = Box::from_raw(found.0).pair();
+        let b_value = ptr::read(&(*b.0).value);
+        ptr::write(&mut (*b.0).value, f(&(*b.0).key, a_value, b_value));
+    }
+    let left = union_nodes(a_left, b_left, f);
+    let right = union_nodes(a_right, b_right, f);
+    join_nodes(left, b, right)
+}
+
+unsafe fn intersection_nodes<K: Ord, V, A: Aggregate<K, V>>(a: NodePtr<K, V, A>, b: NodePtr<K, V, A>) -> NodePtr<K, V, A> {
+    if a.is_null() || b.is_null() {
+        free_nodes(a);
+        free_nodes(b);
+        return NodePtr::null();
+    }
+    let (b_left, b_right) = (detach_root(b.left()), detach_root(b.right()));
+    let (a_left, found, a_right) = split_at_key(a, &(*b.0).key);
+    drop(Box::from_raw(b.0));
+    let left = intersection_nodes(a_left, b_left);
+    let right = intersection_nodes(a_right, b_right);
+    if found.is_null() {
+        join_two(left, right)
+    } else {
+        join_nodes(left, found, right)
+    }
+}
+
+unsafe fn difference_nodes<K: Ord, V, A: Aggregate<K, V>>(a: NodePtr<K, V, A>, b: NodePtr<K, V, A>) -> NodePtr<K, V, A> {
+    if a.is_null() || b.is_null() {
+        free_nodes(b);
+        return a;
+    }
+    let (b_left, b_right) = (detach_root(b.left()), detach_root(b.right()));
+    let (a_left, found, a_right) = split_at_key(a, &(*b.0).key);
+    drop(Box::from_raw(b.0));
+    if !found.is_null() {
+        drop(Box::from_raw(found.0));
+    }
+    let left = difference_nodes(a_left, b_left);
+    let right = difference_nodes(a_right, b_right);
+    join_two(left, right)
+}
+
+impl<K: Ord, V, A: Aggregate<K, V>> RBTree<K, V, A> {
+    /// Builds a tree with any aggregate from entries sorted by key in O(n) time, without any rotation.
+    /// The tree is as balanced as possible, with only its deepest level (if incomplete) colored red.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::{aggregate::Sum, rbtree_lib::RBTree};
+    /// let m = RBTree::<u32, u64, Sum>::from_sorted_iter_aggregated((0..100).map(|k| (k, 2)));
+    /// assert_eq!(m.range_aggregate(10..20), 20);
+    /// ```
+    pub fn from_sorted_iter_aggregated<T: IntoIterator<Item = (K, V)>>(iter: T) -> RBTree<K, V, A> {
+        let nodes: Vec<NodePtr<K, V, A>> = iter.into_iter().map(|(k, v)| NodePtr::new(k, v)).collect();
+        debug_assert!(nodes.windows(2).all(|pair| pair[0] <= pair[1]), "from_sorted_iter requires sorted keys");
+        // The depth of the deepest level of a tree with as many levels as needed and no more
+        let deepest = nodes.len().checked_ilog2().unwrap_or(0) as usize;
+        let red_depth = if nodes.len() + 1 == 1 << (deepest + 1) { usize::MAX } else { deepest };
+        let root = build_balanced(&nodes, 0, red_depth);
+        RBTree { root, len: nodes.len() }
+    }
+
+    /// Takes ownership of a detached subtree
+    fn from_root(root: NodePtr<K, V, A>) -> RBTree<K, V, A> {
+        let root = detach_root(root);
+        RBTree { root, len: root.size() }
+    }
+
+    /// Gives up ownership of the nodes, leaving the tree empty
+    fn into_root(mut self) -> NodePtr<K, V, A> {
+        let root = self.root;
+        self.fast_clear();
+        root
+    }
+
+    /// Joins `left`, the entry `pivot` and `right` into one tree in O(log n) time.
+    /// All keys of `left` must be less than the key of `pivot`, and those of `right` at least that key.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::rbtree_lib::RBTree;
+    /// let left: RBTree<i32, ()> = (0..10).map(|k| (k, ())).collect();
+    /// let right: RBTree<i32, ()> = (11..1000).map(|k| (k, ())).collect();
+    /// let joined = RBTree::join(left, (10, ()), right);
+    /// assert_eq!(joined.len(), 1000);
+    /// assert_eq!(joined.select(10), Some((&10, &())));
+    /// ```
+    pub fn join(left: RBTree<K, V, A>, pivot: (K, V), right: RBTree<K, V, A>) -> RBTree<K, V, A> {
+        debug_assert!(left.get_last().is_none_or(|(k, _)| *k < pivot.0));
+        debug_assert!(right.get_first().is_none_or(|(k, _)| pivot.0 <= *k));
+        let pivot = NodePtr::new(pivot.0, pivot.1);
+        unsafe { RBTree::from_root(join_nodes(left.into_root(), pivot, right.into_root())) }
+    }
+
+    /// Moves the entries with keys greater than or equal to `k` into a new tree, in O(log n) time.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::rbtree_lib::RBTree;
+    /// let mut m: RBTree<i32, ()> = (0..10).map(|k| (k, ())).collect();
+    /// let high = m.split_off(&6);
+    /// assert_eq!(m.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4, 5]);
+    /// assert_eq!(high.keys().copied().collect::<Vec<_>>(), vec![6, 7, 8, 9]);
+    /// ```
+    pub fn split_off(&mut self, k: &K) -> RBTree<K, V, A> {
+        let root = mem::take(self).into_root();
+        let (less, rest) = unsafe { split_nodes(root, k) };
+        *self = RBTree::from_root(less);
+        RBTree::from_root(rest)
+    }
+
+    /// Moves all entries of `other` into `self`, leaving `other` empty.
+    /// The values of `other` replace those of `self` with the same key.
+    /// Takes O(log n) time if the keys of one tree all come before those of the other.
+    pub fn append(&mut self, other: &mut RBTree<K, V, A>) {
+        let this = mem::take(self);
+        let other = mem::take(other);
+        let before = |a: &RBTree<K, V, A>, b: &RBTree<K, V, A>| match (a.get_last(), b.get_first()) {
+            (Some((last, _)), Some((first, _))) => last < first,
+            _ => true,
+        };
+        *self = unsafe {
+            if before(&this, &other) {
+                RBTree::from_root(join_two(this.into_root(), other.into_root()))
+            } else if before(&other, &this) {
+                RBTree::from_root(join_two(other.into_root(), this.into_root()))
+            } else {
+                this.union_with(other, |_, _, v| v)
+            }
+        };
+    }
+
+    /// The entries of both trees, combining the values of the keys in both with `f`.
+    /// Assumes that the keys of each tree are distinct.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::rbtree_lib::RBTree;
+    /// let a: RBTree<i32, i32> = [(1, 10), (2, 20)].into_iter().collect();
+    /// let b: RBTree<i32, i32> = [(2, 2), (3, 3)].into_iter().collect();
+    /// let sum = a.union_with(b, |_, x, y| x + y);
+    /// assert_eq!(sum.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(), vec![(1, 10), (2, 22), (3, 3)]);
+    /// ```
+    pub fn union_with(self, other: RBTree<K, V, A>, mut f: impl FnMut(&K, V, V) -> V) -> RBTree<K, V, A> {
+        unsafe { RBTree::from_root(union_nodes(self.into_root(), other.into_root(), &mut f)) }
+    }
+
+    /// The entries of `self` whose key is also in `other`.
+    /// Assumes that the keys of each tree are distinct.
+    pub fn intersection(self, other: RBTree<K, V, A>) -> RBTree<K, V, A> {
+        unsafe { RBTree::from_root(intersection_nodes(self.into_root(), other.into_root())) }
+    }
+
+    /// The entries of `self` whose key is not in `other`.
+    /// Assumes that the keys of each tree are distinct.
+    pub fn difference(self, other: RBTree<K, V, A>) -> RBTree<K, V, A> {
+        unsafe { RBTree::from_root(difference_nodes(self.into_root(), other.into_root())) }
+    }
+}
+
+impl<K: Ord, V, A: Aggregate<K, V>> RBTree<K, V, A> {
+    /// Gets the entry for the key `k`, to be inspected, updated, inserted or removed in place
+    /// with a single descent of the tree.
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::rbtree_lib::RBTree;
+    /// let mut counts = RBTree::new();
+    /// for word in ["a", "b", "a"] {
+    ///     counts.entry(word).and_modify(|c| *c += 1).or_insert(1);
+    /// }
+    /// assert_eq!(counts.get(&"a"), Some(&2));
+    /// assert_eq!(counts.get(&"b"), Some(&1));
+    /// ```
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V, A> {
+        let mut parent = NodePtr::null();
+        let mut left = false;
+        let mut temp = self.root;
+        while !temp.is_null() {
+            match k.cmp(unsafe { &(*temp.0).key }) {
+                Ordering::Less => {
+                    parent = temp;
+                    left = true;
+                    temp = temp.left();
+                }
+                Ordering::Greater => {
+                    parent = temp;
+                    left = false;
+                    temp = temp.right();
+                }
+                Ordering::Equal => return Entry::Occupied(OccupiedEntry { tree: self, node: temp }),
+            }
+        }
+        Entry::Vacant(VacantEntry { tree: self, key: k, parent, left })
+    }
+}
+
+/// A view into a single entry of an `RBTree`, which may either be vacant or occupied.
+/// Constructed by `RBTree::entry`.
+/// The methods handing out a mutable reference to the value are only there for trees without aggregates.
+pub enum Entry<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V> = ()> {
+    Occupied(OccupiedEntry<'a, K, V, A>),
+    Vacant(VacantEntry<'a, K, V, A>),
+}
+
+/// An entry of an `RBTree` holding a value
+pub struct OccupiedEntry<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V> = ()> {
+    tree: &'a mut RBTree<K, V, A>,
+    node: NodePtr<K, V, A>,
+}
+
+/// An entry of an `RBTree` without a value, remembering where the key belongs
+pub struct VacantEntry<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V> = ()> {
+    tree: &'a mut RBTree<K, V, A>,
+    key: K,
+    /// The node the new node is linked below, or null if the tree is empty
+    parent: NodePtr<K, V, A>,
+    left: bool,
+}
+
+impl<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V>> Entry<'a, K, V, A> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+}
+
+impl<'a, K: Ord + 'a, V: 'a> Entry<'a, K, V> {
+    /// Inserts `default` if the entry is vacant, and returns the value
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `default` if the entry is vacant, and returns the value
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Like `or_insert_with`, computing the value from the key
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default(&entry.key);
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// Updates the value in place if the entry is occupied
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K: Ord + 'a, V: Default + 'a> Entry<'a, K, V> {
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V>> OccupiedEntry<'a, K, V, A> {
+    pub fn key(&self) -> &K {
+        unsafe { &(*self.node.0).key }
+    }
+
+    pub fn get(&self) -> &V {
+        unsafe { &(*self.node.0).value }
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Removes the entry from the tree, returning the stored key along with the value
+    pub fn remove_entry(self) -> (K, V) {
+        unsafe { self.tree.delete(self.node) }
+    }
+}
+
+impl<'a, K: Ord + 'a, V: 'a> OccupiedEntry<'a, K, V> {
+    pub fn get_mut(&mut self) -> &mut V {
+        unsafe { &mut (*self.node.0).value }
+    }
+
+    /// Converts the entry into a reference to its value, living as long as the borrow of the tree
+    pub fn into_mut(self) -> &'a mut V {
+        unsafe { &mut (*self.node.0).value }
+    }
+
+    /// Replaces the value, returning the old one
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+}
+
+impl<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V>> VacantEntry<'a, K, V, A> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts the value at the position found by `RBTree::entry`, and returns the occupied entry
+    pub fn insert_entry(self, value: V) -> OccupiedEntry<'a, K, V, A> {
+        let node = NodePtr::new(self.key, value);
+        self.tree.len += 1;
+        unsafe {
+            self.tree.attach(node, self.parent, self.left);
+        }
+        OccupiedEntry { tree: self.tree, node }
+    }
+}
+
+impl<'a, K: Ord + 'a, V: 'a> VacantEntry<'a, K, V> {
+    /// Inserts the value at the position found by `RBTree::entry`, and returns it
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.insert_entry(value).into_mut()
+    }
+}
+
+impl<K: Ord, V, A: Aggregate<K, V>> RBTree<K, V, A> {
+    /// A cursor at the first entry of the tree, or at the end if it is empty
+    /// # Examples
+    /// ```
+    /// use etch::rbtree::rbtree_lib::RBTree;
+    /// let mut expiries: RBTree<u32, &str> = [(3, "a"), (5, "b"), (8, "c"), (13, "d")].into_iter().collect();
+    /// // Drop everything that expired by time 8, while walking the tree once
+    /// let mut cursor = expiries.cursor_front_mut();
+    /// while cursor.key().is_some_and(|&t| t <= 8) {
+    ///     cursor.remove_current();
+    /// }
+    /// cursor.insert_before(10, "e");
+    /// assert_eq!(expiries.keys().copied().collect::<Vec<_>>(), vec![10, 13]);
+    /// ```
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, K, V, A> {
+        let current = self.first_child();
+        CursorMut { tree: self, current }
+    }
+
+    /// A cursor at the last entry of the tree, or at the end if it is empty
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, K, V, A> {
+        let current = self.last_child();
+        CursorMut { tree: self, current }
+    }
+}
+
+/// A cursor over the entries of an `RBTree`, which can move in both directions and
+/// insert or remove entries where it stands, without descending from the root.
+/// Besides the entries, the cursor can stand at the end of the tree; moving forward from there
+/// wraps around to the first entry, and moving backward to the last one.
+/// Constructed by `RBTree::cursor_front_mut` and `RBTree::cursor_back_mut`.
+/// Removing and inserting entries keeps the aggregates up to date, but the values can only be changed
+/// in place in trees without aggregates.
+pub struct CursorMut<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V> = ()> {
+    tree: &'a mut RBTree<K, V, A>,
+    /// The current node, or null at the end
+    current: NodePtr<K, V, A>,
+}
+
+impl<'a, K: Ord + 'a, V: 'a> CursorMut<'a, K, V> {
+    /// The value of the current entry, or `None` at the end
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        if self.current.is_null() {
+            return None;
+        }
+        unsafe { Some(&mut (*self.current.0).value) }
+    }
+
+    /// The current entry, or `None` at the end
+    pub fn current(&mut self) -> Option<(&K, &mut V)> {
+        if self.current.is_null() {
+            return None;
+        }
+        unsafe { Some((&(*self.current.0).key, &mut (*self.current.0).value)) }
+    }
+}
+
+impl<'a, K: Ord + 'a, V: 'a, A: Aggregate<K, V>> CursorMut<'a, K, V, A> {
+    /// The key of the current entry, or `None` at the end
+    pub fn key(&self) -> Option<&K> {
+        self.tree.node_pair(self.current).map(|(k, _)| k)
+    }
+
+    /// The value of the current entry, or `None` at the end
+    pub fn value(&self) -> Option<&V> {
+        self.tree.node_pair(self.current).map(|(_, v)| v)
+    }
+
+    /// Moves to the next entry
+    pub fn move_next(&mut self) {
+        self.current = if self.current.is_null() { self.tree.first_child() } else { self.current.next() };
+    }
+
+    /// Moves to the previous entry
+    pub fn move_prev(&mut self) {
+        self.current = if self.current.is_null() { self.tree.last_child() } else { self.current.prev() };
+    }
+
+    /// Moves forward to the first entry with a key at least `k` (or greater than `k` if `strict`),
+    /// like `IndexedStream::seek`. The cursor never moves backward, and stays at the end once there.
+    /// A sequence of seeks through the whole tree takes O(n) time in total.
+    pub fn seek(&mut self, k: &K, strict: bool) {
+        if strict {
+            self.current = self.current.seek(|k2| k2 <= k);
+        } else {
+            self.current = self.current.seek(|k2| k2 < k);
+        }
+    }
+
+    /// Removes the current entry and moves to the next one.
+    /// Returns `None`, removing nothing, at the end.
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        if self.current.is_null() {
+            return None;
+        }
+        let node = self.current;
+        // Removing a node relinks the others without moving them, so its successor stays valid
+        self.current = node.next();
+        unsafe { Some(self.tree.delete(node)) }
+    }
+
+    /// Inserts an entry just before the current one (or as the last entry at the end),
+    /// without moving the cursor.
+    /// # Panics
+    /// Panics if `k` does not fit between the previous and the current key.
+    pub fn insert_before(&mut self, k: K, v: V) {
+        let prev = if self.current.is_null() { self.tree.last_child() } else { self.current.prev() };
+        unsafe {
+            assert!(prev.is_null() || (*prev.0).key < k, "the key is not greater than the previous key");
+            assert!(self.current.is_null() || k < (*self.current.0).key, "the key is not less than the current key");
+        }
+        // The new node is either the left child of the current node, or the right child of the previous one
+        let (parent, left) = if !self.current.is_null() && self.current.left().is_null() {
+            (self.current, true)
+        } else {
+            (prev, false)
+        };
+        let node = NodePtr::new(k, v);
+        self.tree.len += 1;
+        unsafe {
+            self.tree.attach(node, parent, left);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::{BTreeMap, BTreeSet}, mem, ops::Bound};
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::{rbtree::aggregate::{Aggregate, Count, Max, Pair, Sum}, streams::{parallel::Splittable, stream_defs::{FromStreamIterator, IndexedStream}}};
+
+    use super::{Entry, RBTree, RBTreeError};
+
+    ordered_map_tests!(RBTree);
+
+    /// An operation of a `CursorMut`, for `test_cursor`
+    #[derive(Debug, Clone, Copy)]
+    enum CursorOp {
+        Next,
+        Prev,
+        Seek(u8, bool),
+        Remove,
+        Insert(u8),
+    }
+
+    impl quickcheck::Arbitrary for CursorOp {
+        fn arbitrary(g: &mut quickcheck::Gen) -> Self {
+            match u8::arbitrary(g) % 5 {
+                0 => CursorOp::Next,
+                1 => CursorOp::Prev,
+                2 => CursorOp::Seek(u8::arbitrary(g), bool::arbitrary(g)),
+                3 => CursorOp::Remove,
+                _ => CursorOp::Insert(u8::arbitrary(g)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut m = RBTree::new();
+        assert_eq!(m.len(), 0);
+        m.insert(1, 2);
+        assert_eq!(m.len(), 1);
+        m.insert(2, 4);
+        assert_eq!(m.len(), 2);
+        m.insert(2, 6);
+        assert_eq!(m.len(), 3);
+        assert_eq!(*m.get(&1).unwrap(), 2);
+        assert_eq!(*m.get(&2).unwrap(), 4);
+        assert_eq!(*m.get(&2).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut m = RBTree::new();
+        assert_eq!(m.len(), 0);
+        m.insert(2, 4);
+        assert_eq!(m.len(), 1);
+        assert_eq!(m.replace_or_insert(2, 6).unwrap(), 4);
+        assert_eq!(m.len(), 1);
+        assert_eq!(*m.get(&2).unwrap(), 6);
     }
 
     #[test]
@@ -1649,6 +2819,8 @@ mod tests {
         assert_eq!(*m2.get(&1).unwrap(), 2);
         assert_eq!(*m2.get(&2).unwrap(), 4);
         assert_eq!(m2.len(), 2);
+        let m3 = m.clone();
+        assert!(m3.is_empty());
     }
 
     #[test]
@@ -1983,6 +3155,486 @@ mod tests {
         assert_eq!(a[&3], "three");
     }
 
+    /// Checks the subtree sizes through `rank`, `select` and `advance_by` against a sorted copy of the keys
+    fn check_order_statistics(m: &RBTree<u16, u16>, keys: &[u16]) {
+        assert_eq!(m.select(keys.len()), None);
+        for (n, k) in keys.iter().enumerate() {
+            assert_eq!(m.select(n).map(|(k, _)| *k), Some(*k));
+            assert_eq!(m.rank(k), keys.partition_point(|x| x < k));
+        }
+        for skip in [0, 1, 2, 7, keys.len()] {
+            let mut stream = m.stream_iter();
+            stream.advance_by(skip);
+            assert_eq!(stream.remaining_estimate(), Some(keys.len().saturating_sub(skip)));
+            assert_eq!(stream.collect_indices(), keys[skip.min(keys.len())..]);
+        }
+    }
+
+    #[quickcheck]
+    fn test_order_statistics(inserted: Vec<u16>, removed: Vec<u16>) {
+        let mut m = RBTree::new();
+        let mut keys = BTreeSet::new();
+        for &k in inserted.iter() {
+            m.replace_or_insert(k, k);
+            keys.insert(k);
+        }
+        check_order_statistics(&m, &keys.iter().copied().collect::<Vec<_>>());
+        for k in removed.iter().chain(inserted.iter().step_by(2)) {
+            assert_eq!(m.remove(k).is_some(), keys.remove(k));
+        }
+        let keys: Vec<u16> = keys.into_iter().collect();
+        check_order_statistics(&m, &keys);
+        check_order_statistics(&m.clone(), &keys);
+    }
+
+    #[test]
+    fn test_positional_stream() {
+        let m: RBTree<u32, u32> = (0..1000).map(|k| (k * 3, k)).collect();
+        // Pagination: the third page of 10 entries from 300 on
+        let mut page = m.stream_iter();
+        page.seek(300, false);
+        page.advance_by(20);
+        assert_eq!(page.index(), 360);
+        let mut bounded = m.stream_iter().split_at(30).0;
+        assert_eq!(bounded.remaining_estimate(), Some(10));
+        bounded.advance_by(9);
+        assert_eq!(bounded.collect_indices(), vec![27]);
+        let mut bounded = m.stream_iter().split_at(30).0;
+        bounded.advance_by(10);
+        assert!(!bounded.valid());
+        assert_eq!(bounded.remaining_estimate(), Some(0));
+    }
+
+    #[quickcheck]
+    fn test_ranges(keys: BTreeSet<u8>, a: u8, b: u8) {
+        let m: RBTree<u8, u8> = keys.iter().map(|&k| (k, k)).collect();
+        let check = |range: (Bound<u8>, Bound<u8>)| {
+            if matches!(range, (Bound::Excluded(a), Bound::Excluded(b)) if a == b) {
+                return;
+            }
+            let expected: Vec<u8> = keys.range(range).copied().collect();
+            let iter = m.range(range);
+            assert_eq!(iter.size_hint(), (expected.len(), Some(expected.len())));
+            assert_eq!(iter.clone().map(|(k, _)| *k).collect::<Vec<_>>(), expected);
+            assert_eq!(iter.rev().map(|(k, _)| *k).collect::<Vec<_>>(), expected.iter().rev().copied().collect::<Vec<_>>());
+            let stream = m.stream_range(range);
+            assert_eq!(stream.remaining_estimate(), Some(expected.len()));
+            assert_eq!(stream.collect_indices(), expected);
+        };
+        let (lo, hi) = (a.min(b), a.max(b));
+        for start in [Bound::Included(lo), Bound::Excluded(lo), Bound::Unbounded] {
+            for end in [Bound::Included(hi), Bound::Excluded(hi), Bound::Unbounded] {
+                check((start, end));
+            }
+        }
+        assert_eq!(m.lower_bound(&a).map(|(k, _)| *k), keys.range(a..).next().copied());
+        assert_eq!(m.upper_bound(&a).map(|(k, _)| *k), keys.range((Bound::Excluded(a), Bound::Unbounded)).next().copied());
+        assert_eq!(m.successor(&a), m.upper_bound(&a));
+        assert_eq!(m.predecessor(&a).map(|(k, _)| *k), keys.range(..a).next_back().copied());
+    }
+
+    #[test]
+    fn test_range_stream_join() {
+        let x: RBTree<u32, ()> = (0..1000).map(|k| (k, ())).collect();
+        let y: RBTree<u32, ()> = (0..1000).map(|k| (k * 2, ())).collect();
+        let joined = x.stream_range(100..110).zip_with(y.stream_range(105..), |_, _| ());
+        assert_eq!(joined.collect_indices(), vec![106, 108]);
+        // Seeking past the end of the range leaves the stream invalid
+        let mut stream = x.stream_range(..=10);
+        stream.seek(500, false);
+        assert!(!stream.valid());
+    }
+
+    #[quickcheck]
+    fn test_entry(ops: Vec<(u8, Option<u8>)>) {
+        let mut m = RBTree::new();
+        let mut expected = BTreeMap::new();
+        for (k, v) in ops {
+            match v {
+                // Accumulate, or insert 0
+                Some(v) => {
+                    m.entry(k).and_modify(|x| *x += v as u32).or_default();
+                    expected.entry(k).and_modify(|x| *x += v as u32).or_insert(0);
+                }
+                None => match m.entry(k) {
+                    Entry::Occupied(entry) => {
+                        assert_eq!(entry.key(), &k);
+                        assert_eq!(entry.remove_entry(), (k, expected.remove(&k).unwrap()));
+                    }
+                    Entry::Vacant(entry) => {
+                        assert!(!expected.contains_key(&k));
+                        assert_eq!(*entry.insert(k as u32 * 2), k as u32 * 2);
+                        expected.insert(k, k as u32 * 2);
+                    }
+                },
+            }
+            assert_eq!(m.len(), expected.len());
+        }
+        assert!(m.iter().map(|(k, v)| (*k, *v)).eq(expected.iter().map(|(k, v)| (*k, *v))));
+        for (n, k) in expected.keys().enumerate() {
+            assert_eq!(m.select(n).map(|(k, _)| k), Some(k));
+        }
+    }
+
+    #[test]
+    fn test_entry_in_place() {
+        let mut m: RBTree<&str, Vec<u32>> = RBTree::new();
+        m.entry("a").or_insert_with(|| Vec::with_capacity(2)).push(1);
+        m.entry("a").or_insert_with(|| Vec::with_capacity(2)).push(2);
+        assert_eq!(m.entry("b").or_insert_with_key(|k| vec![k.len() as u32]), &vec![1]);
+        if let Entry::Occupied(mut entry) = m.entry("a") {
+            assert_eq!(entry.insert(vec![3]), vec![1, 2]);
+        }
+        assert_eq!(m.get(&"a"), Some(&vec![3]));
+        assert_eq!(m.remove_entry(&"b"), Some(("b", vec![1])));
+        assert_eq!(m.entry("b").key(), &"b");
+        assert_eq!(m.len(), 1);
+    }
+
+    fn assert_valid<K: Ord, V, A: Aggregate<K, V>>(m: &RBTree<K, V, A>) {
+        assert_eq!(m.check_invariants(), Ok(()));
+    }
+
+    fn tree_of(keys: &BTreeSet<u16>) -> RBTree<u16, u32> {
+        keys.iter().map(|&k| (k, k as u32)).collect()
+    }
+
+    fn entries(m: &RBTree<u16, u32>) -> Vec<(u16, u32)> {
+        m.iter().map(|(k, v)| (*k, *v)).collect()
+    }
+
+    #[quickcheck]
+    fn test_split_join(keys: BTreeSet<u16>, k: u16) {
+        let mut low = tree_of(&keys);
+        let high = low.split_off(&k);
+        assert_valid(&low);
+        assert_valid(&high);
+        assert!(low.keys().copied().eq(keys.range(..k).copied()));
+        assert!(high.keys().copied().eq(keys.range(k..).copied()));
+
+        let mut joined = low.clone();
+        joined.append(&mut high.clone());
+        assert_valid(&joined);
+        assert!(joined.keys().eq(keys.iter()));
+        let mut reversed = high.clone();
+        reversed.append(&mut low.clone());
+        assert!(reversed.keys().eq(keys.iter()));
+
+        if !keys.contains(&k) {
+            let joined = RBTree::join(low, (k, 0), high);
+            assert_valid(&joined);
+            assert_eq!(joined.rank(&k), keys.range(..k).count());
+            assert_eq!(joined.len(), keys.len() + 1);
+        }
+    }
+
+    #[quickcheck]
+    fn test_set_operations(a: BTreeSet<u16>, b: BTreeSet<u16>) {
+        let (x, y) = (tree_of(&a), b.iter().map(|&k| (k, 1)).collect::<RBTree<u16, u32>>());
+        let union = x.clone().union_with(y.clone(), |_, v, w| v + w);
+        assert_valid(&union);
+        let expected: Vec<(u16, u32)> = a.union(&b)
+            .map(|&k| (k, if a.contains(&k) { k as u32 } else { 0 } + b.contains(&k) as u32))
+            .collect();
+        assert_eq!(entries(&union), expected);
+
+        let intersection = x.clone().intersection(y.clone());
+        assert_valid(&intersection);
+        assert_eq!(entries(&intersection), a.intersection(&b).map(|&k| (k, k as u32)).collect::<Vec<_>>());
+
+        let difference = x.clone().difference(y.clone());
+        assert_valid(&difference);
+        assert_eq!(entries(&difference), a.difference(&b).map(|&k| (k, k as u32)).collect::<Vec<_>>());
+
+        // Overlapping trees are merged, with the values of the appended tree
+        let mut appended = x;
+        appended.append(&mut y.clone());
+        assert_valid(&appended);
+        assert_eq!(entries(&appended), a.union(&b).map(|&k| (k, if b.contains(&k) { 1 } else { k as u32 })).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_join_uneven() {
+        for n in [0, 1, 2, 5, 100, 1000] {
+            let left: RBTree<u32, ()> = (0..n).map(|k| (k, ())).collect();
+            let right: RBTree<u32, ()> = (n + 1..n + 4).map(|k| (k, ())).collect();
+            let joined = RBTree::join(left.clone(), (n, ()), right.clone());
+            assert_valid(&joined);
+            assert!(joined.keys().copied().eq(0..n + 4));
+            let joined = RBTree::join(RBTree::new(), (0, ()), left.clone().split_off(&1));
+            assert_valid(&joined);
+            assert!(joined.keys().copied().eq(0..n.max(1)));
+        }
+    }
+
+    #[test]
+    fn test_from_sorted_iter() {
+        for n in 0..300u32 {
+            let m = RBTree::from_sorted_iter((0..n).map(|k| (k, k + 1)));
+            assert_valid(&m);
+            assert!(m.iter().map(|(k, v)| (*k, *v)).eq((0..n).map(|k| (k, k + 1))));
+        }
+        // Duplicate keys stay in order
+        let m = RBTree::from_sorted_iter([(1, 'a'), (1, 'b'), (2, 'c')]);
+        assert_valid(&m);
+        assert_eq!(m.values().collect::<String>(), "abc");
+    }
+
+    #[quickcheck]
+    fn test_from_stream(a: BTreeSet<u16>, b: BTreeSet<u16>) {
+        let tree: RBTree<u16, u32> = tree_of(&a).stream_iter().map(|_, v| *v).collect();
+        assert_valid(&tree);
+        assert_eq!(entries(&tree), entries(&tree_of(&a)));
+        let mut extended = tree.clone();
+        extended.extend_from_stream_iterator(tree_of(&b).stream_iter().map(|_, v| *v + 1));
+        assert_valid(&extended);
+        let expected = a.union(&b).map(|&k| (k, k as u32 + b.contains(&k) as u32)).collect::<Vec<_>>();
+        assert_eq!(entries(&extended), expected);
+        // Inserting afterwards keeps the tree valid
+        let mut grown = tree;
+        for k in b {
+            grown.replace_or_insert(k, 0);
+        }
+        assert_valid(&grown);
+    }
+
+    #[quickcheck]
+    fn test_random_operations(ops: Vec<(u8, u8, bool)>) {
+        let mut m = RBTree::new();
+        let mut keys = BTreeSet::new();
+        for (op, k, strict) in ops {
+            match op % 3 {
+                0 => {
+                    m.replace_or_insert(k, ());
+                    keys.insert(k);
+                }
+                1 => assert_eq!(m.remove(&k).is_some(), keys.remove(&k)),
+                _ => {
+                    let mut stream = m.stream_iter();
+                    if stream.valid() {
+                        stream.seek(k, strict);
+                    }
+                    let expected = if strict { keys.range(k..).find(|&&x| x > k) } else { keys.range(k..).next() };
+                    assert_eq!(stream.valid().then(|| stream.index()), expected.copied());
+                }
+            }
+            assert_valid(&m);
+            // A red-black tree is at most twice as high as its black height
+            assert!(m.height() <= 2 * m.black_height());
+        }
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let mut m: RBTree<u32, ()> = (0..10).map(|k| (k, ())).collect();
+        assert_eq!(m.check_invariants(), Ok(()));
+        assert_eq!((m.height(), m.black_height()), (5, 3));
+        m.len += 1;
+        assert_eq!(m.check_invariants(), Err(RBTreeError::WrongLen { expected: 10, found: 11 }));
+        m.len -= 1;
+        let mut first = m.first_child();
+        first.set_size(2);
+        assert_eq!(m.check_invariants(), Err(RBTreeError::WrongSize { at: 0, expected: 1, found: 2 }));
+        first.set_size(1);
+        let last = m.last_child();
+        unsafe {
+            mem::swap(&mut (*first.0).key, &mut (*last.0).key);
+        }
+        assert_eq!(m.check_invariants(), Err(RBTreeError::OutOfOrder { at: 1 }));
+        unsafe {
+            mem::swap(&mut (*first.0).key, &mut (*last.0).key);
+        }
+        m.root.set_red_color();
+        assert_eq!(m.check_invariants(), Err(RBTreeError::RedRoot));
+        m.root.set_black_color();
+        let mut red = first;
+        while !red.is_red_color() {
+            red = red.next();
+        }
+        red.set_black_color();
+        assert!(matches!(m.check_invariants(), Err(RBTreeError::BlackHeightMismatch { .. })));
+    }
+
+    #[quickcheck]
+    fn test_cursor(keys: BTreeSet<u8>, ops: Vec<CursorOp>) {
+        let mut m: RBTree<u8, u32> = keys.iter().map(|&k| (k, k as u32)).collect();
+        let mut expected: Vec<u8> = keys.into_iter().collect();
+        // The position of the cursor in `expected`, with `expected.len()` standing for the end
+        let mut pos = 0;
+        let mut cursor = m.cursor_front_mut();
+        for op in ops {
+            match op {
+                CursorOp::Next => pos = if pos == expected.len() { 0 } else { pos + 1 },
+                CursorOp::Prev => pos = if pos == 0 { expected.len() } else { pos - 1 },
+                CursorOp::Seek(k, strict) => {
+                    cursor.seek(&k, strict);
+                    if pos < expected.len() {
+                        pos += expected[pos..].iter().take_while(|&&x| x < k || (strict && x == k)).count();
+                    }
+                }
+                CursorOp::Remove => {
+                    let removed = cursor.remove_current();
+                    assert_eq!(removed, (pos < expected.len()).then(|| (expected[pos], expected[pos] as u32)));
+                    if pos < expected.len() {
+                        expected.remove(pos);
+                    }
+                }
+                CursorOp::Insert(k) => {
+                    let fits = (pos == 0 || expected[pos - 1] < k) && (pos == expected.len() || k < expected[pos]);
+                    if fits {
+                        cursor.insert_before(k, k as u32);
+                        expected.insert(pos, k);
+                        pos += 1;
+                    }
+                }
+            }
+            match op {
+                CursorOp::Next => cursor.move_next(),
+                CursorOp::Prev => cursor.move_prev(),
+                _ => {}
+            }
+            assert_eq!(cursor.key(), expected.get(pos));
+            if let Some((&k, v)) = cursor.current() {
+                assert_eq!(*v, k as u32);
+            }
+        }
+        assert_valid(&m);
+        assert_eq!(m.keys().copied().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_cursor_evict() {
+        let mut m: RBTree<u32, u32> = (0..100).map(|t| (t * 3, t)).collect();
+        let mut cursor = m.cursor_front_mut();
+        // Evict the entries at times divisible by 6 up to 150, and double the others
+        while cursor.key().is_some_and(|&t| t <= 150) {
+            if cursor.key().unwrap().is_multiple_of(6) {
+                cursor.remove_current();
+            } else {
+                *cursor.value_mut().unwrap() *= 2;
+                cursor.move_next();
+            }
+        }
+        assert_eq!(cursor.key(), Some(&153));
+        cursor.move_prev();
+        cursor.insert_before(145, 0);
+        assert_eq!(cursor.key(), Some(&147));
+        let mut back = m.cursor_back_mut();
+        assert_eq!(back.key(), Some(&297));
+        back.move_next();
+        assert_eq!(back.key(), None);
+        back.insert_before(1000, 0);
+        assert_valid(&m);
+        assert_eq!(m.len(), 100 - 26 + 2);
+        assert_eq!(m.get(&147), Some(&98));
+        assert_eq!(m.get(&150), None);
+        assert_eq!(m.get(&153), Some(&51));
+        assert_eq!(m.get_last(), Some((&1000, &0)));
+    }
+
+    #[quickcheck]
+    fn test_aggregates(ops: Vec<(u8, u8, u8)>) {
+        let mut m: RBTree<u8, u32, Pair<Sum, Max>> = RBTree::default();
+        let mut model = BTreeMap::new();
+        for (op, k, v) in ops {
+            let v = v as u32;
+            match op % 5 {
+                0 => assert_eq!(m.replace_or_insert(k, v), model.insert(k, v)),
+                1 => assert_eq!(m.remove(&k), model.remove(&k)),
+                2 => {
+                    let mut high = m.split_off(&k);
+                    m.append(&mut high);
+                }
+                3 => {
+                    let other = [(k, v)].into_iter().collect();
+                    m = m.union_with(other, |_, a, b| a + b);
+                    *model.entry(k).or_default() += v;
+                }
+                _ => m = m.clone(),
+            }
+            assert_valid(&m);
+            let (lo, hi) = (k.min(v as u8), k.max(v as u8));
+            let expected = |values: &mut dyn Iterator<Item = u32>| values.fold((0, None), |(sum, max), v| (sum + v, max.max(Some(v))));
+            assert_eq!(m.aggregate(), expected(&mut model.values().copied()));
+            assert_eq!(m.range_aggregate(lo..=hi), expected(&mut model.range(lo..=hi).map(|(_, v)| *v)));
+            assert_eq!(m.range_aggregate(lo..hi), expected(&mut model.range(lo..hi).map(|(_, v)| *v)));
+            let after = (Bound::Excluded(lo), Bound::Unbounded);
+            assert_eq!(m.range_aggregate(after), expected(&mut model.range(after).map(|(_, v)| *v)));
+        }
+        let rebuilt: RBTree<u8, u32, Pair<Sum, Max>> = RBTree::from_sorted_iter_aggregated(model.clone());
+        assert_eq!(rebuilt.aggregate(), m.aggregate());
+    }
+
+    #[test]
+    fn test_range_count() {
+        let mut m: RBTree<u32, (), Count> = (0..1000).map(|k| (k * 2, ())).collect();
+        assert_eq!(m.range_aggregate(100..200), 50);
+        assert_eq!(m.range_aggregate(..=100), 51);
+        assert_eq!(m.range_aggregate(5000..), 0);
+        while m.len() > 10 {
+            m.pop_first();
+            assert_eq!(m.aggregate(), m.len());
+        }
+        // `Count` agrees with the ranks kept by every tree
+        assert_eq!(m.range_aggregate(..1990), m.rank(&1990));
+    }
+
+    #[test]
+    fn test_aggregates_in_place() {
+        let mut m: RBTree<u32, u64, Sum> = (0..100).map(|k| (k * 2, k as u64)).collect();
+        // The cursor and the entries keep the aggregates up to date as they remove and insert entries
+        let mut cursor = m.cursor_front_mut();
+        while cursor.key().is_some_and(|&k| k < 50) {
+            if cursor.value().is_some_and(|v| v % 2 == 0) {
+                cursor.remove_current();
+            } else {
+                cursor.move_next();
+            }
+        }
+        cursor.insert_before(49, 1000);
+        let Entry::Occupied(entry) = m.entry(98) else { panic!("98 is in the tree") };
+        assert_eq!(entry.remove(), 49);
+        let Entry::Vacant(entry) = m.entry(99) else { panic!("99 is not in the tree") };
+        assert_eq!(*entry.insert_entry(2000).get(), 2000);
+        assert_valid(&m);
+        assert_eq!(m.aggregate(), m.values().sum::<u64>());
+        assert_eq!(m.range_aggregate(..50), (1..25).step_by(2).sum::<u64>() + 1000);
+    }
+
+    #[test]
+    fn test_aggregate_thread_safety() {
+        /// A summary that must not be cloned from several threads at once
+        struct RcAgg;
+
+        impl Aggregate<u32, u32> for RcAgg {
+            type Value = std::rc::Rc<u32>;
+
+            fn empty() -> Self::Value {
+                Default::default()
+            }
+
+            fn lift(_: &u32, value: &u32) -> Self::Value {
+                (*value).into()
+            }
+
+            fn combine(left: &Self::Value, right: &Self::Value) -> Self::Value {
+                (**left + **right).into()
+            }
+        }
+
+        // Resolves only if `T` is not `Sync`: otherwise both impls apply and the call is ambiguous
+        trait AmbiguousIfSync<Marker> {
+            fn check() {}
+        }
+        impl<T: ?Sized> AmbiguousIfSync<()> for T {}
+        impl<T: ?Sized + Sync> AmbiguousIfSync<u8> for T {}
+        <RBTree<u32, u32, RcAgg> as AmbiguousIfSync<_>>::check();
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<RBTree<u32, u32, Sum>>();
+        assert_send_sync::<super::IndexedStreamIter<'static, u32, u32, Sum>>();
+    }
+
     #[test]
     fn test_rev_iter() {
         let mut a = RBTree::new();
@@ -0,0 +1,376 @@
+use std::{fmt::{self, Debug}, marker::PhantomData, ptr, sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering}};
+
+use crate::streams::stream_defs::{FromStreamIterator, IndexedStream, IntoStreamIterator};
+
+/// The most lanes a node can be part of, enough for billions of entries with 1 in 4 nodes promoted per lane
+const MAX_HEIGHT: usize = 16;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    /// The next node on each of the lanes this node is part of, the first being the list of all entries
+    next: Box<[AtomicPtr<Node<K, V>>]>,
+}
+
+/// Follows a link, to a node that lives as long as the list
+fn follow<K, V>(link: &AtomicPtr<Node<K, V>>) -> Option<&Node<K, V>> {
+    // SAFETY: linked nodes are fully written before they are published with a release store,
+    // and are only freed when the list is dropped
+    unsafe { link.load(Ordering::Acquire).as_ref() }
+}
+
+/// An ordered map stored in a skip list, to which entries can be inserted through a shared reference,
+/// from several threads at once and while other threads read or stream over it.
+/// Insertions are lock-free, and entries can not be removed or modified once inserted, so readers never wait.
+/// Streams move along the express lanes when seeking: they climb the towers met on the way
+/// until a lane would overshoot the target, then descend, in O(log d) expected time for a seek d entries ahead.
+/// Readers see every entry inserted before they started, and may or may not see concurrent insertions,
+/// but always see the entries in order and at most once.
+pub struct SkipList<K, V> {
+    head: [AtomicPtr<Node<K, V>>; MAX_HEIGHT],
+    len: AtomicUsize,
+    /// The state of the generator of node heights
+    seed: AtomicU64,
+    _marker: PhantomData<*const Node<K, V>>,
+}
+
+// SAFETY: the nodes are owned by the list, and inserting moves keys and values to the thread that later drops them
+unsafe impl<K: Send, V: Send> Send for SkipList<K, V> {}
+
+// SAFETY: shared references hand out shared references to the keys and values, and insert moves them across threads
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for SkipList<K, V> {}
+
+impl<K, V> Default for SkipList<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for SkipList<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V> Drop for SkipList<K, V> {
+    fn drop(&mut self) {
+        let mut next = *self.head[0].get_mut();
+        while !next.is_null() {
+            // SAFETY: every node is linked exactly once on the bottom lane, and nothing else refers to it anymore
+            let mut node = unsafe { Box::from_raw(next) };
+            next = *node.next[0].get_mut();
+        }
+    }
+}
+
+impl<K, V> SkipList<K, V> {
+    pub fn new() -> Self {
+        SkipList {
+            head: Default::default(),
+            len: AtomicUsize::new(0),
+            seed: AtomicU64::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// The number of entries, which may lag behind insertions running concurrently
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { current: follow(&self.head[0]) }
+    }
+
+    /// A height of at least `h` with probability 4^(1 - h), from a SplitMix64 sequence
+    fn random_height(&self) -> usize {
+        let mut z = self.seed.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (1 + z.trailing_zeros() as usize / 2).min(MAX_HEIGHT)
+    }
+}
+
+impl<K: Ord, V> SkipList<K, V> {
+    /// The last lane positions before `key` on each lane, and the nodes they link to
+    #[allow(clippy::type_complexity)]
+    fn search(&self, key: &K) -> ([&[AtomicPtr<Node<K, V>>]; MAX_HEIGHT], [*mut Node<K, V>; MAX_HEIGHT]) {
+        let mut preds = [&self.head[..]; MAX_HEIGHT];
+        let mut succs = [ptr::null_mut(); MAX_HEIGHT];
+        let mut pred = &self.head[..];
+        for level in (0..MAX_HEIGHT).rev() {
+            // The successor must be the node compared to the key, not a new load of the link,
+            // which may have changed since
+            let mut succ = pred[level].load(Ordering::Acquire);
+            // SAFETY: see `follow`
+            while let Some(node) = unsafe { succ.as_ref() }.filter(|node| node.key < *key) {
+                pred = &node.next;
+                succ = pred[level].load(Ordering::Acquire);
+            }
+            preds[level] = pred;
+            succs[level] = succ;
+        }
+        (preds, succs)
+    }
+
+    /// The first node with a key at least `key`
+    fn lower_bound(&self, key: &K) -> Option<&Node<K, V>> {
+        let mut pred = &self.head[..];
+        for level in (0..MAX_HEIGHT).rev() {
+            while let Some(node) = follow(&pred[level]).filter(|node| node.key < *key) {
+                pred = &node.next;
+            }
+        }
+        follow(&pred[0])
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.lower_bound(key).filter(|node| node.key == *key).map(|node| &node.value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Inserts `value` at `key` if the key is absent, returning whether it was.
+    /// The value of a key that is already present is left as is, since readers may be holding on to it.
+    pub fn insert(&self, key: K, value: V) -> bool {
+        let (mut preds, mut succs) = self.search(&key);
+        // SAFETY (for all the dereferences of `succs` below): they come from `follow`-able links
+        if unsafe { succs[0].as_ref() }.is_some_and(|node| node.key == key) {
+            return false;
+        }
+        let height = self.random_height();
+        let next = succs[..height].iter().map(|&succ| AtomicPtr::new(succ)).collect();
+        let node = Box::into_raw(Box::new(Node { key, value, next }));
+        // SAFETY: the node is only freed by the list once linked, or below if it is not
+        let new = unsafe { &*node };
+
+        // Linking the node on the bottom lane inserts the entry, which only one thread can do for a given key
+        while preds[0][0].compare_exchange(succs[0], node, Ordering::Release, Ordering::Relaxed).is_err() {
+            (preds, succs) = self.search(&new.key);
+            if unsafe { succs[0].as_ref() }.is_some_and(|node| node.key == new.key) {
+                // SAFETY: the node was never published
+                drop(unsafe { Box::from_raw(node) });
+                return false;
+            }
+            for (link, &succ) in new.next.iter().zip(&succs) {
+                link.store(succ, Ordering::Relaxed);
+            }
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+
+        // The express lanes only speed up searches, so they can be linked one after the other
+        for level in 1..height {
+            loop {
+                new.next[level].store(succs[level], Ordering::Release);
+                if preds[level][level].compare_exchange(succs[level], node, Ordering::Release, Ordering::Relaxed).is_ok() {
+                    break;
+                }
+                (preds, succs) = self.search(&new.key);
+            }
+        }
+        true
+    }
+
+    pub fn stream_iter(&self) -> SkipListStream<'_, K, V> {
+        SkipListStream { current: follow(&self.head[0]) }
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for SkipList<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut map = SkipList::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for SkipList<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K: Ord, V> FromStreamIterator<K, V> for SkipList<K, V> {
+    fn from_stream_iterator<S: IndexedStream<I = K, V = V>>(iter: S) -> Self {
+        let mut map = SkipList::new();
+        map.extend_from_stream_iterator(iter);
+        map
+    }
+
+    fn extend_from_stream_iterator<S: IndexedStream<I = K, V = V>>(&mut self, iter: S) {
+        iter.for_each(|k, v| {
+            self.insert(k, v);
+        });
+    }
+}
+
+/// An in-order iterator over a `SkipList`
+pub struct Iter<'a, K, V> {
+    current: Option<&'a Node<K, V>>,
+}
+
+impl<K, V> Clone for Iter<'_, K, V> {
+    fn clone(&self) -> Self {
+        Iter { current: self.current }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        let node = self.current?;
+        self.current = follow(&node.next[0]);
+        Some((&node.key, &node.value))
+    }
+}
+
+/// A stream over a `SkipList`, which does not know how many entries are left
+pub struct SkipListStream<'a, K, V> {
+    current: Option<&'a Node<K, V>>,
+}
+
+impl<K, V> Clone for SkipListStream<'_, K, V> {
+    fn clone(&self) -> Self {
+        SkipListStream { current: self.current }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> IndexedStream for SkipListStream<'a, K, V> {
+    type I = K;
+    type V = &'a V;
+
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    fn seek(&mut self, index: K, strict: bool) {
+        let before = |node: &Node<K, V>| if strict { node.key <= index } else { node.key < index };
+        let Some(mut node) = self.current.filter(|node| before(node)) else {
+            return;
+        };
+        let ahead = |node: &'a Node<K, V>, level: usize| follow(&node.next[level]).filter(|next| before(next));
+        // Climb the towers met on the way while their lanes lead to nodes before the target
+        let mut level = 0;
+        loop {
+            while level + 1 < node.next.len() && ahead(node, level + 1).is_some() {
+                level += 1;
+            }
+            match ahead(node, level) {
+                Some(next) => node = next,
+                None => break,
+            }
+        }
+        // Then move down the lanes, as far along each as possible
+        loop {
+            while let Some(next) = ahead(node, level) {
+                node = next;
+            }
+            if level == 0 {
+                break;
+            }
+            level -= 1;
+        }
+        self.current = follow(&node.next[0]);
+    }
+
+    fn next(&mut self) {
+        self.current = self.current.and_then(|node| follow(&node.next[0]));
+    }
+
+    fn index(&self) -> K {
+        self.current.expect("the stream is valid").key
+    }
+
+    fn value(&self) -> &'a V {
+        &self.current.expect("the stream is valid").value
+    }
+}
+
+impl<'a, K: Ord + Copy, V> IntoStreamIterator for &'a SkipList<K, V> {
+    type IndexType = K;
+    type ValueType = &'a V;
+    type StreamType = SkipListStream<'a, K, V>;
+
+    fn into_stream_iterator(self) -> Self::StreamType {
+        self.stream_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, thread};
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::streams::stream_defs::IndexedStream;
+
+    use super::SkipList;
+
+    stream_seek_tests!(SkipList, no_estimate);
+
+    #[quickcheck]
+    fn test_operations(ops: Vec<(u8, u16)>) {
+        let map = SkipList::new();
+        let mut expected = BTreeMap::new();
+        for (k, v) in ops {
+            let absent = !expected.contains_key(&k);
+            assert_eq!(map.insert(k, v), absent);
+            expected.entry(k).or_insert(v);
+            assert_eq!(map.get(&k), expected.get(&k));
+            assert_eq!(map.len(), expected.len());
+        }
+        assert!(map.iter().eq(expected.iter()));
+        assert_eq!(map.contains_key(&0), expected.contains_key(&0));
+    }
+
+    #[test]
+    fn test_concurrent_inserts() {
+        const WRITERS: u32 = 4;
+        const PER_WRITER: u32 = 20_000;
+        let map = SkipList::new();
+        thread::scope(|s| {
+            for w in 0..WRITERS {
+                let map = &map;
+                s.spawn(move || {
+                    // Interleave the keys of the writers, and have each insert some keys of the next one
+                    for i in 0..PER_WRITER {
+                        map.insert(i * WRITERS + w, w);
+                        if i % 8 == 0 {
+                            map.insert(i * WRITERS + (w + 1) % WRITERS, (w + 1) % WRITERS);
+                        }
+                    }
+                });
+            }
+            s.spawn(|| {
+                // Streams over the list while it grows stay sorted, and seeks never go back
+                while map.len() < (WRITERS * PER_WRITER) as usize {
+                    let keys = map.stream_iter().collect_indices();
+                    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+                    let mut stream = map.stream_iter();
+                    let mut last = 0;
+                    while stream.valid() {
+                        assert!(stream.index() >= last);
+                        last = stream.index();
+                        stream.seek(last + 97, false);
+                    }
+                }
+            });
+        });
+        assert_eq!(map.len(), (WRITERS * PER_WRITER) as usize);
+        assert!(map.iter().map(|(&k, &v)| (k, v)).eq((0..WRITERS * PER_WRITER).map(|k| (k, k % WRITERS))));
+    }
+}
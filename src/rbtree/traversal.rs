@@ -0,0 +1,88 @@
+/// Read access to the nodes of a binary search tree that keeps subtree sizes, through copyable handles:
+/// references for `PersistentMap`, indices into the arena for `ArenaMap`
+pub(super) trait Nodes<'a>: Copy {
+    type Handle: Copy;
+    type K: 'a;
+    type V: 'a;
+
+    fn left(self, node: Self::Handle) -> Option<Self::Handle>;
+
+    fn right(self, node: Self::Handle) -> Option<Self::Handle>;
+
+    fn key(self, node: Self::Handle) -> &'a Self::K;
+
+    fn value(self, node: Self::Handle) -> &'a Self::V;
+
+    /// The number of entries in the subtree rooted at `node`
+    fn size(self, node: Option<Self::Handle>) -> usize;
+}
+
+/// A position in the in-order traversal of a tree, which iterators and streams are built on
+pub(super) struct Traversal<'a, T: Nodes<'a>> {
+    nodes: T,
+    /// The nodes whose left subtree has been visited, but not the node itself; the next node is on top
+    stack: Vec<T::Handle>,
+}
+
+impl<'a, T: Nodes<'a>> Clone for Traversal<'a, T> {
+    fn clone(&self) -> Self {
+        Traversal { nodes: self.nodes, stack: self.stack.clone() }
+    }
+}
+
+impl<'a, T: Nodes<'a>> Traversal<'a, T> {
+    pub(super) fn new(nodes: T, root: Option<T::Handle>) -> Self {
+        let mut traversal = Traversal { nodes, stack: Vec::new() };
+        traversal.push_left_spine(root);
+        traversal
+    }
+
+    fn push_left_spine(&mut self, mut node: Option<T::Handle>) {
+        while let Some(n) = node {
+            self.stack.push(n);
+            node = self.nodes.left(n);
+        }
+    }
+
+    /// The next entry, if any
+    pub(super) fn peek(&self) -> Option<(&'a T::K, &'a T::V)> {
+        self.stack.last().map(|&n| (self.nodes.key(n), self.nodes.value(n)))
+    }
+
+    pub(super) fn next(&mut self) -> Option<(&'a T::K, &'a T::V)> {
+        let n = self.stack.pop()?;
+        self.push_left_spine(self.nodes.right(n));
+        Some((self.nodes.key(n), self.nodes.value(n)))
+    }
+
+    /// The number of entries left
+    pub(super) fn remaining(&self) -> usize {
+        self.stack.iter().map(|&n| 1 + self.nodes.size(self.nodes.right(n))).sum()
+    }
+
+    /// Moves forward to the first entry whose key does not satisfy `before`, which must be downwards closed.
+    /// A sequence of seeks through the whole tree takes O(n) time in total, like those of the streams over an `RBTree`.
+    pub(super) fn seek(&mut self, before: impl Fn(&T::K) -> bool) {
+        let nodes = self.nodes;
+        while let Some(&top) = self.stack.last() {
+            if !before(nodes.key(top)) {
+                return;
+            }
+            self.stack.pop();
+            // The right subtree of `top` lies between it and the next node on the stack,
+            // so it only needs to be searched if the next node is past the target
+            if self.stack.last().is_none_or(|&next| !before(nodes.key(next))) {
+                let mut node = nodes.right(top);
+                while let Some(n) = node {
+                    if before(nodes.key(n)) {
+                        node = nodes.right(n);
+                    } else {
+                        self.stack.push(n);
+                        node = nodes.left(n);
+                    }
+                }
+                return;
+            }
+        }
+    }
+}
@@ -2,8 +2,14 @@ pub mod stream_defs;
 pub mod zip_stream;
 pub mod chain;
 pub mod add_stream;
+pub mod aggregate_stream;
+pub mod difference_stream;
 mod binary_search;
 pub mod sparse_vec;
 pub mod sorted_vec;
 pub mod csr_mat;
+pub mod materialize;
+pub mod parallel;
+pub mod simd;
+pub mod trie;
 pub mod macros;
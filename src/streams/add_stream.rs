@@ -1,8 +1,9 @@
-use std::ops::Add;
+use std::{cmp::Ordering, ops::{Add, ControlFlow}};
 
 use super::stream_defs::{IntoStreamIterator, IndexedStream};
 
 
+/// The sum of two streams on the indices where both of them have an entry
 pub struct AddStream<L, R> {
     left: L,
     right: R,
@@ -28,22 +29,13 @@ impl<I, L, R> IndexedStream for AddStream<L, R>
     type I = I;
     type V = <L::V as Add<R::V>>::Output;
 
+    /// An index is only ready once both streams are on it, so the sum ends with either stream
     fn valid(&self) -> bool {
-        self.left.valid() || self.right.valid()
+        self.left.valid() && self.right.valid()
     }
 
     fn ready(&self) -> bool {
-        if self.left.valid() {
-            if self.right.valid() {
-                self.left.index() == self.right.index()
-            } else {
-                self.left.ready()
-            }
-        } else if self.right.valid() {
-            self.right.ready()
-        } else {
-            panic!("AddStream::ready called when neither stream is valid")
-        }
+        self.left.index() == self.right.index() && self.left.ready() && self.right.ready()
     }
 
     fn seek(&mut self, index: I, strict: bool) {
@@ -51,6 +43,17 @@ impl<I, L, R> IndexedStream for AddStream<L, R>
         self.right.seek(index, strict);
     }
 
+    /// Moves the stream that is behind up to the other one, since the default `next`
+    /// would seek both to the smaller index and never move
+    fn next(&mut self) {
+        let (l, r) = (self.left.index(), self.right.index());
+        if l == r {
+            self.seek(l, self.ready());
+        } else {
+            self.seek(l.max(r), false);
+        }
+    }
+
     fn index(&self) -> I {
         self.left.index().min(self.right.index())
     }
@@ -58,6 +61,29 @@ impl<I, L, R> IndexedStream for AddStream<L, R>
     fn value(&self) -> Self::V {
         self.left.value() + self.right.value()
     }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.left.remaining_estimate()? + self.right.remaining_estimate()?)
+    }
+}
+
+/// Emits the entry of `stream` at `index` if it is ready and moves past it, as the only stream on `index`
+#[inline]
+fn step<S: IndexedStream, B, R, V>(
+    stream: &mut S,
+    index: S::I,
+    acc: B,
+    mut f: impl FnMut(B, S::I, V) -> ControlFlow<R, B>,
+    map: impl FnOnce(S::V) -> V
+) -> ControlFlow<R, B> {
+    if stream.ready() {
+        let v = map(stream.value());
+        stream.next();
+        f(acc, index, v)
+    } else {
+        stream.seek(index, false);
+        ControlFlow::Continue(acc)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -139,6 +165,41 @@ impl<I, V, L, R, F> IndexedStream for IntersectingUnionStream<L, R, F>
             std::cmp::Ordering::Greater => (self.f)(EitherOrBoth::Right(self.right.value())),
         }
     }
+
+    fn try_fold<B, FF, RR>(&mut self, init: B, mut f: FF) -> ControlFlow<RR, B>
+    where
+        FF: FnMut(B, Self::I, Self::V) -> ControlFlow<RR, B>
+    {
+        let mut acc = init;
+        while self.left.valid() && self.right.valid() {
+            let (l, r) = (self.left.index(), self.right.index());
+            match l.cmp(&r) {
+                Ordering::Less => acc = step(&mut self.left, l, acc, &mut f, |v| (self.f)(EitherOrBoth::Left(v)))?,
+                Ordering::Greater => acc = step(&mut self.right, r, acc, &mut f, |v| (self.f)(EitherOrBoth::Right(v)))?,
+                Ordering::Equal => match (self.left.ready(), self.right.ready()) {
+                    (true, true) => {
+                        let v = (self.f)(EitherOrBoth::Both(self.left.value(), self.right.value()));
+                        self.left.next();
+                        self.right.next();
+                        acc = f(acc, l, v)?;
+                    },
+                    (left_ready, right_ready) => {
+                        if !left_ready {
+                            self.left.seek(l, false);
+                        }
+                        if !right_ready {
+                            self.right.seek(l, false);
+                        }
+                    },
+                },
+            }
+        }
+        ControlFlow::Continue(acc)
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.left.remaining_estimate()? + self.right.remaining_estimate()?)
+    }
 }
 
 pub fn union<I, V, A, B, F>(x: A, y: B, f: F) -> impl IndexedStream<I = I, V = V>
@@ -156,3 +217,46 @@ where
     })
 }
 
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::streams::{sparse_vec::SparseVec, stream_defs::IndexedStream};
+
+    use super::{AddStream, EitherOrBoth, IntersectingUnionStream};
+
+    /// Collects a stream through `valid`/`ready`/`value`/`next` only, bypassing `try_fold`
+    fn step_through<S: IndexedStream>(mut stream: S) -> Vec<(S::I, S::V)> {
+        let mut result = Vec::new();
+        while stream.valid() {
+            if stream.ready() {
+                result.push((stream.index(), stream.value()));
+            }
+            stream.next();
+        }
+        result
+    }
+
+    #[quickcheck]
+    fn test_add(a: BTreeMap<u8, u32>, b: BTreeMap<u8, u32>) {
+        let x: SparseVec<u8, u32> = a.iter().map(|(&k, &v)| (k, v)).collect();
+        let y: SparseVec<u8, u32> = b.iter().map(|(&k, &v)| (k, v)).collect();
+        let expected: Vec<(u8, u64)> = a.iter()
+            .filter_map(|(k, &v)| Some((*k, u64::from(v) + u64::from(*b.get(k)?))))
+            .collect();
+        let sum = || AddStream::add(x.stream_iter().map(|_, &v| u64::from(v)), y.stream_iter().map(|_, &v| u64::from(v)));
+        assert_eq!(sum().collect::<Vec<_>>(), expected);
+        assert_eq!(step_through(sum()), expected);
+    }
+
+    #[quickcheck]
+    fn test_intersecting_union(a: BTreeMap<u8, u32>, b: BTreeMap<u8, u32>) {
+        let x: SparseVec<u8, u32> = a.iter().map(|(&k, &v)| (k, v)).collect();
+        let y: SparseVec<u8, u32> = b.iter().map(|(&k, &v)| (k, v)).collect();
+        let union = || IntersectingUnionStream::new(x.stream_iter(), y.stream_iter(), |e: EitherOrBoth<&u32, &u32>| e.map(|v| *v, |v| *v));
+        assert_eq!(union().collect::<Vec<_>>(), step_through(union()));
+    }
+}
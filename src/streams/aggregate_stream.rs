@@ -0,0 +1,168 @@
+use std::{cell::Cell, marker::PhantomData, ops::ControlFlow};
+
+use super::stream_defs::IndexedStream;
+
+/// A stream that reduces the value at each index (typically an inner stream) with `f`,
+/// skipping the indices where `f` returns `None`.
+/// `f` is only evaluated for entries the consumer actually inspects, and normally only once per entry:
+/// the result computed by `ready` is cached until `value` takes it or the stream moves.
+pub struct AggregateStream<S, F, O> {
+    stream: S,
+    f: F,
+    current: Cell<Aggregate<O>>,
+    _output: PhantomData<O>
+}
+
+/// The stream returned by `IndexedStream::project_out`
+pub type ProjectedStream<S> = AggregateStream<S, fn(<S as IndexedStream>::V) -> Option<()>, ()>;
+
+/// The aggregate of the current entry, if it has been computed
+#[derive(Default)]
+enum Aggregate<O> {
+    #[default]
+    Unknown,
+    Computed(Option<O>),
+    /// `value` has taken the (necessarily `Some`) aggregate
+    Taken,
+}
+
+impl<S, F, O> AggregateStream<S, F, O>
+where
+    S: IndexedStream,
+    F: Fn(S::V) -> Option<O>
+{
+    pub fn new(stream: S, f: F) -> Self {
+        AggregateStream { stream, f, current: Cell::new(Aggregate::Unknown), _output: PhantomData }
+    }
+}
+
+impl<S: Clone, F: Clone, O> Clone for AggregateStream<S, F, O> {
+    fn clone(&self) -> Self {
+        AggregateStream {
+            stream: self.stream.clone(),
+            f: self.f.clone(),
+            current: Cell::new(Aggregate::Unknown),
+            _output: PhantomData
+        }
+    }
+}
+
+impl<S, F, O> IndexedStream for AggregateStream<S, F, O>
+where
+    S: IndexedStream,
+    S::I: PartialEq,
+    F: Fn(S::V) -> Option<O>
+{
+    type I = S::I;
+    type V = O;
+
+    fn valid(&self) -> bool {
+        self.stream.valid()
+    }
+
+    fn ready(&self) -> bool {
+        if !self.stream.ready() {
+            return false;
+        }
+        let (ready, state) = match self.current.take() {
+            Aggregate::Unknown => {
+                let result = (self.f)(self.stream.value());
+                (result.is_some(), Aggregate::Computed(result))
+            },
+            Aggregate::Computed(result) => (result.is_some(), Aggregate::Computed(result)),
+            Aggregate::Taken => (true, Aggregate::Taken),
+        };
+        self.current.set(state);
+        ready
+    }
+
+    fn seek(&mut self, index: Self::I, strict: bool) {
+        // The inner stream may be ready at an entry this stream skips, in which case
+        // a non-strict seek to that entry would not make progress on the inner stream.
+        // Only a cached empty aggregate is checked, so that seeking does not evaluate `f`;
+        // consumers learn that an entry is skipped through `ready`, which fills the cache.
+        let skipped = matches!(self.current.take(), Aggregate::Computed(None));
+        let strict = strict || (skipped && self.stream.index() == index);
+        self.current.set(Aggregate::Unknown);
+        self.stream.seek(index, strict);
+    }
+
+    fn next(&mut self) {
+        self.current.set(Aggregate::Unknown);
+        self.stream.next();
+    }
+
+    fn index(&self) -> Self::I {
+        self.stream.index()
+    }
+
+    fn value(&self) -> Self::V {
+        let result = match self.current.take() {
+            Aggregate::Computed(result) => result,
+            Aggregate::Unknown | Aggregate::Taken => (self.f)(self.stream.value()),
+        };
+        self.current.set(Aggregate::Taken);
+        result.expect("AggregateStream::value called on an entry without an aggregate")
+    }
+
+    /// The entries of the inner stream, an upper bound since entries without an aggregate are skipped
+    fn remaining_estimate(&self) -> Option<usize> {
+        self.stream.remaining_estimate()
+    }
+
+    fn try_fold<B, FF, R>(&mut self, init: B, mut f: FF) -> ControlFlow<R, B> where
+            FF: FnMut(B, Self::I, Self::V) -> ControlFlow<R, B> {
+        self.current.set(Aggregate::Unknown);
+        self.stream.try_fold(init, |acc, i, v| match (self.f)(v) {
+            Some(o) => f(acc, i, o),
+            None => ControlFlow::Continue(acc),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use crate::streams::{sorted_vec::SortedVecGalloper, sparse_vec::SparseVec, stream_defs::IndexedStream};
+
+    fn nested() -> SparseVec<u32, Vec<u32>> {
+        SparseVec::from_iter([(1, vec![]), (2, vec![5, 6]), (4, vec![]), (7, vec![1]), (9, vec![2, 3, 4])])
+    }
+
+    #[test]
+    fn test_project_out() {
+        let rel = nested();
+        let visited = Cell::new(0);
+        let projected = rel.stream_iter()
+            .map(|_, v| SortedVecGalloper::new(v).map(|_, _| visited.set(visited.get() + 1)))
+            .project_out();
+        assert_eq!(projected.collect_indices(), vec![2, 7, 9]);
+        // Only the indices of the inner streams are inspected, never their values
+        assert_eq!(visited.get(), 0);
+    }
+
+    #[test]
+    fn test_aggregate_inner() {
+        let rel = nested();
+        let evaluated = Cell::new(0);
+        let sums = rel.stream_iter()
+            .map(|_, v| SortedVecGalloper::new(v).map(|i, _| i))
+            .aggregate_inner(|inner| {
+                evaluated.set(evaluated.get() + 1);
+                inner.fold(None, |acc: Option<u32>, _, v| Some(acc.unwrap_or(0) + v))
+            });
+        let filter = SparseVec::from_iter([(2, ()), (4, ()), (9, ())]);
+        let result: Vec<(u32, u32)> = sums.zip_with(filter.stream_iter(), |s, _| s).collect();
+        assert_eq!(result, vec![(2, 11), (9, 9)]);
+        // Entries 1 and 7 are skipped by the zip without being aggregated
+        assert_eq!(evaluated.get(), 3);
+    }
+
+    #[test]
+    fn test_exists() {
+        let rel = nested();
+        assert!(!SortedVecGalloper::new(&rel.vals[0]).exists());
+        assert!(SortedVecGalloper::new(&rel.vals[1]).exists());
+    }
+}
@@ -31,4 +31,147 @@ pub(crate) fn binary_search<T: Ord>(arr: &[T], target: &T, strict: bool) -> usiz
         }
     }
     left
-}
\ No newline at end of file
+}
+
+/// The number of consecutive linear steps after which the rest of a skip is galloped over,
+/// and the search switches to galloping
+const LINEAR_STEPS: usize = 8;
+/// Galloping skips shorter than this count towards switching back to linear steps
+const SHORT_SKIP: usize = 4;
+/// The number of consecutive short skips after which the search switches back to linear steps
+const SHORT_SKIPS: usize = 4;
+
+/// The state of a search that steps linearly while seeks only skip a few entries at a time,
+/// and gallops with `binary_search` once they start skipping more.
+///
+/// Like `SortedVecLinear`, a linear search moves by at most one entry per call, leaving the rest of the
+/// skip to the following seeks, so that the hot path stays free of unpredictable branches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct AdaptiveSearch {
+    galloping: bool,
+    /// While stepping linearly, the number of consecutive steps that left the search short of its target;
+    /// while galloping, the number of consecutive short skips
+    run: usize,
+}
+
+impl AdaptiveSearch {
+    /// Moves towards the first entry of `arr` past `target` (as in `binary_search`),
+    /// returning the number of entries skipped, which is nonzero unless `arr[0]` is already past `target`
+    #[inline(always)]
+    pub(crate) fn search<T: Ord>(&mut self, arr: &[T], target: &T, strict: bool) -> usize {
+        if self.galloping {
+            return self.gallop(arr, target, strict);
+        }
+        let before = |x: &T| if strict { x <= target } else { x < target };
+        if !arr.first().is_some_and(before) {
+            return 0;
+        }
+        // Only steps that are part of a longer skip count towards galloping:
+        // dense inputs, where every seek moves by one entry, keep stepping linearly
+        if !arr.get(1).is_some_and(before) {
+            self.run = 0;
+            return 1;
+        }
+        self.run += 1;
+        if self.run < LINEAR_STEPS {
+            return 1;
+        }
+        self.galloping = true;
+        self.run = 0;
+        1 + gallop(&arr[1..], target, strict)
+    }
+
+    #[inline]
+    fn gallop<T: Ord>(&mut self, arr: &[T], target: &T, strict: bool) -> usize {
+        let skip = gallop(arr, target, strict);
+        // Seeks that do not move say nothing about the distances between entries
+        if skip > 0 {
+            self.run = if skip < SHORT_SKIP { self.run + 1 } else { 0 };
+            if self.run >= SHORT_SKIPS {
+                self.galloping = false;
+                self.run = 0;
+            }
+        }
+        skip
+    }
+}
+
+/// `binary_search`, kept out of line so that the linear steps stay small enough
+/// to be inlined into the caller's loop
+#[inline(never)]
+fn gallop<T: Ord>(arr: &[T], target: &T, strict: bool) -> usize {
+    binary_search(arr, target, strict)
+}
+
+#[cfg(test)]
+mod test {
+    use quickcheck_macros::quickcheck;
+
+    use super::{binary_search, AdaptiveSearch, SHORT_SKIPS};
+
+    /// Searches until reaching the entry `binary_search` would, returning the distance
+    fn search_fully(search: &mut AdaptiveSearch, arr: &[u32], target: u32, strict: bool) -> usize {
+        let mut cur = 0;
+        loop {
+            let skip = search.search(&arr[cur..], &target, strict);
+            if skip == 0 {
+                return cur;
+            }
+            cur += skip;
+        }
+    }
+
+    #[quickcheck]
+    fn test_adaptive_agrees(arr: Vec<u16>, targets: Vec<(u16, bool)>) {
+        let mut arr: Vec<u32> = arr.into_iter().map(u32::from).collect();
+        arr.sort_unstable();
+        arr.dedup();
+        let mut search = AdaptiveSearch::default();
+        let mut cur = 0;
+        let mut targets: Vec<(u32, bool)> = targets.into_iter().map(|(t, strict)| (u32::from(t), strict)).collect();
+        targets.sort_unstable();
+        for (target, strict) in targets {
+            let expected = cur + binary_search(&arr[cur..], &target, strict);
+            cur += search_fully(&mut search, &arr[cur..], target, strict);
+            assert_eq!(cur, expected);
+        }
+    }
+
+    #[test]
+    fn test_adaptive_switches() {
+        let arr: Vec<u32> = (0..1000).collect();
+        let mut search = AdaptiveSearch::default();
+        let mut cur = 0;
+        for _ in 0..5 {
+            cur += search_fully(&mut search, &arr[cur..], arr[cur] + 100, false);
+        }
+        assert!(search.galloping);
+        assert_eq!(cur, 500);
+        for _ in 0..SHORT_SKIPS {
+            cur += search_fully(&mut search, &arr[cur..], arr[cur], true);
+        }
+        assert!(!search.galloping);
+        assert_eq!(cur, 500 + SHORT_SKIPS);
+    }
+
+    #[test]
+    fn test_adaptive_dense_stays_linear() {
+        // Intersecting the even and the odd numbers: every seek moves by exactly one entry
+        let evens: Vec<u32> = (0..1000).map(|x| 2 * x).collect();
+        let odds: Vec<u32> = (0..1000).map(|x| 2 * x + 1).collect();
+        let (mut a, mut b) = (AdaptiveSearch::default(), AdaptiveSearch::default());
+        let (mut i, mut j) = (0, 0);
+        loop {
+            i += a.search(&evens[i..], &odds[j], false);
+            if i == evens.len() {
+                break;
+            }
+            j += b.search(&odds[j..], &evens[i], false);
+            if j == odds.len() {
+                break;
+            }
+            assert!(!a.galloping && !b.galloping);
+        }
+        assert_eq!((i, j), (1000, 999));
+    }
+}
@@ -116,6 +116,14 @@ where
         }
     }
 
+    /// The second stream is only built once the first one is exhausted, so its size is unknown until then
+    fn remaining_estimate(&self) -> Option<usize> {
+        match &self {
+            ChainStream::First { .. } => None,
+            ChainStream::Second { stream: b } => b.remaining_estimate(),
+        }
+    }
+
     fn try_fold<BB, FF, R>(&mut self, init: BB, mut f: FF) -> ControlFlow<R, BB> where
             FF: FnMut(BB, Self::I, Self::V) -> ControlFlow<R, BB> {
         replace_with_or_abort_and_return(self, |self_| {
@@ -217,6 +225,10 @@ where
         }
     }
 
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.first.remaining_estimate()? + self.second.remaining_estimate()?)
+    }
+
     fn try_fold<BB, F, R>(&mut self, init: BB, mut f: F) -> ControlFlow<R, BB> where
             F: FnMut(BB, Self::I, Self::V) -> ControlFlow<R, BB> {
         let acc = self.first.try_fold(init, &mut f)?;
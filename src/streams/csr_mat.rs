@@ -1,4 +1,4 @@
-use super::{sparse_vec::SparseVecGalloper, stream_defs::{FromStreamIterator, IndexedStream, IntoStreamIterator}};
+use super::{parallel::Splittable, sparse_vec::SparseVecGalloper, stream_defs::{FromStreamIterator, IndexedStream, IntoStreamIterator}};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SparseCSRMat<T> {
@@ -14,6 +14,16 @@ impl<T> SparseCSRMat<T> {
         self.rows.len() - 1
     }
 
+    /// The number of stored entries
+    pub fn nnz(&self) -> usize {
+        self.cols.len()
+    }
+
+    /// The number of stored entries in the given row
+    pub fn row_len(&self, row: usize) -> usize {
+        self.rows[row + 1] - self.rows[row]
+    }
+
     pub fn empty() -> Self {
         SparseCSRMat {
             rows: vec![0],
@@ -96,6 +106,32 @@ impl<'a, T> IndexedStream for SparseCSRMatIterator<'a, T> {
         let end = self.rows[self.cur + 1];
         SparseVecGalloper::new(&self.cols[start..end], &self.vals[start..end])
     }
+
+    /// The number of rows left, including empty ones
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some((self.rows.len() - 1).saturating_sub(self.cur))
+    }
+}
+
+impl<T> Splittable for SparseCSRMatIterator<'_, T> {
+    /// Splits at the row where half of the remaining entries have been seen
+    fn split_point(&self) -> Option<usize> {
+        let last = self.rows.len() - 1;
+        if last <= self.cur + 1 {
+            return None;
+        }
+        let half = (self.rows[self.cur] + self.rows[last]) / 2;
+        let row = self.cur + self.rows[self.cur..last].partition_point(|&start| start <= half);
+        Some(row.clamp(self.cur + 1, last - 1))
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        let mid = index.clamp(self.cur, self.rows.len() - 1);
+        (
+            SparseCSRMatIterator { rows: &self.rows[..=mid], cols: self.cols, vals: self.vals, cur: self.cur },
+            SparseCSRMatIterator { cur: mid, ..self },
+        )
+    }
 }
 
 impl<'a, T> IntoStreamIterator for &'a SparseCSRMat<T> {
@@ -0,0 +1,215 @@
+use super::stream_defs::{IntoStreamIterator, IndexedStream};
+
+/// Moves `right` to its first ready entry at or after `index`
+fn align<I: Ord + Copy, R: IndexedStream<I = I>>(right: &mut R, index: I) {
+    while right.valid() && (right.index() < index || (right.index() == index && !right.ready())) {
+        right.seek(index, false);
+    }
+}
+
+/// The entries of `left` whose index is not an entry of `right`.
+/// `right` is kept on its first entry at or after the current index of `left`, so it gallops
+/// along `left` through `seek`; a `right` of `None` removes nothing.
+#[derive(Debug, Clone)]
+pub struct DifferenceStream<L, R> {
+    left: L,
+    right: Option<R>,
+}
+
+impl<I, L, R> DifferenceStream<L, R>
+    where L: IndexedStream<I=I>,
+          R: IndexedStream<I=I>,
+          I: Ord + Copy {
+    pub fn new(
+        left: impl IntoStreamIterator<StreamType = L>,
+        right: Option<impl IntoStreamIterator<StreamType = R>>,
+    ) -> Self {
+        let mut result = DifferenceStream {
+            left: left.into_stream_iterator(),
+            right: right.map(|right| right.into_stream_iterator()),
+        };
+        result.align();
+        result
+    }
+
+    fn align(&mut self) {
+        if let Some(right) = self.right.as_mut() {
+            if self.left.valid() {
+                align(right, self.left.index());
+            }
+        }
+    }
+
+    /// Whether the current index of `left` is an entry of `right`
+    fn excluded(&self) -> bool {
+        self.right.as_ref().is_some_and(|right| right.valid() && right.index() == self.left.index())
+    }
+}
+
+impl<I, L, R> IndexedStream for DifferenceStream<L, R>
+    where L: IndexedStream<I=I>,
+          R: IndexedStream<I=I>,
+          I: Ord + Copy {
+    type I = I;
+    type V = L::V;
+
+    fn valid(&self) -> bool {
+        self.left.valid()
+    }
+
+    fn ready(&self) -> bool {
+        self.left.ready() && !self.excluded()
+    }
+
+    fn seek(&mut self, index: I, strict: bool) {
+        // `left` may be ready at an excluded entry, in which case it has to move past it
+        let strict = strict || (index == self.left.index() && self.excluded());
+        self.left.seek(index, strict);
+        self.align();
+    }
+
+    fn index(&self) -> I {
+        self.left.index()
+    }
+
+    fn value(&self) -> Self::V {
+        self.left.value()
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        self.left.remaining_estimate()
+    }
+}
+
+/// The anti-join of two nested streams: every entry of `left`, with its inner stream
+/// reduced to the entries absent from the inner stream of `right` at the same index.
+/// Entries of `left` are kept even when their difference is empty.
+#[derive(Debug, Clone)]
+pub struct NestedDifferenceStream<L, R> {
+    left: L,
+    right: R,
+}
+
+impl<I, L, R> NestedDifferenceStream<L, R>
+    where L: IndexedStream<I=I>,
+          R: IndexedStream<I=I>,
+          I: Ord + Copy {
+    pub fn new(
+        left: impl IntoStreamIterator<StreamType = L>,
+        right: impl IntoStreamIterator<StreamType = R>,
+    ) -> Self {
+        let mut result = NestedDifferenceStream {
+            left: left.into_stream_iterator(),
+            right: right.into_stream_iterator(),
+        };
+        if result.left.valid() {
+            align(&mut result.right, result.left.index());
+        }
+        result
+    }
+}
+
+impl<I, L, R> IndexedStream for NestedDifferenceStream<L, R>
+    where L: IndexedStream<I=I>,
+          R: IndexedStream<I=I>,
+          I: Ord + Copy,
+          L::V: IndexedStream,
+          R::V: IndexedStream<I = <L::V as IndexedStream>::I>,
+          <L::V as IndexedStream>::I: Ord + Copy {
+    type I = I;
+    type V = DifferenceStream<L::V, R::V>;
+
+    fn valid(&self) -> bool {
+        self.left.valid()
+    }
+
+    fn ready(&self) -> bool {
+        self.left.ready()
+    }
+
+    fn seek(&mut self, index: I, strict: bool) {
+        self.left.seek(index, strict);
+        if self.left.valid() {
+            align(&mut self.right, self.left.index());
+        }
+    }
+
+    fn index(&self) -> I {
+        self.left.index()
+    }
+
+    fn value(&self) -> Self::V {
+        let matched = self.right.valid() && self.right.index() == self.left.index();
+        DifferenceStream::new(self.left.value(), matched.then(|| self.right.value()))
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        self.left.remaining_estimate()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::streams::{sorted_vec::SortedVecGalloper, sparse_vec::SparseVec, stream_defs::IndexedStream};
+
+    #[test]
+    fn test_minus() {
+        let viewed = SparseVec::from_iter([(1, "a"), (3, "b"), (4, "c"), (8, "d"), (9, "e")]);
+        let bought = [3, 5, 9];
+        let result: Vec<(u32, &str)> = viewed.stream_iter().cloned()
+            .minus(SortedVecGalloper::new(&bought))
+            .collect();
+        assert_eq!(result, vec![(1, "a"), (4, "c"), (8, "d")]);
+    }
+
+    #[test]
+    fn test_minus_not_ready() {
+        // Entries of `right` that are not ready do not remove anything
+        let left = [1, 2, 3, 4, 5, 6];
+        let a = [2, 3, 5, 6];
+        let b = [1, 3, 4, 6];
+        let both = SortedVecGalloper::new(&a).zip_with(SortedVecGalloper::new(&b), |_, _| ());
+        assert_eq!(SortedVecGalloper::new(&left).minus(both.clone()).collect_indices(), vec![1, 2, 4, 5]);
+        // The other way around: a zip on the left skips some entries itself
+        assert_eq!(both.minus(SortedVecGalloper::new(&[6])).collect_indices(), vec![3]);
+    }
+
+    #[test]
+    fn test_seek() {
+        let left = [1, 2, 3, 4, 5];
+        let right = [2, 3];
+        let mut stream = SortedVecGalloper::new(&left).minus(SortedVecGalloper::new(&right));
+        stream.seek(2, false);
+        assert_eq!(stream.index(), 2);
+        assert!(!stream.ready());
+        stream.seek(2, false);
+        assert_eq!(stream.index(), 3);
+        assert!(!stream.ready());
+        stream.next();
+        assert_eq!(stream.index(), 4);
+        assert!(stream.ready());
+    }
+
+    #[test]
+    fn test_minus_nested() {
+        let viewed: SparseVec<u32, Vec<u32>> = SparseVec::from_iter([(1, vec![10, 11, 12]), (2, vec![20]), (4, vec![40, 41])]);
+        let bought: SparseVec<u32, Vec<u32>> = SparseVec::from_iter([(1, vec![11]), (3, vec![30]), (4, vec![40, 41])]);
+        let result: Vec<(u32, Vec<u32>)> = viewed.stream_iter().map(|_, x| SortedVecGalloper::new(x))
+            .minus_nested(bought.stream_iter().map(|_, x| SortedVecGalloper::new(x)))
+            .map(|_, inner| inner.collect_indices())
+            .collect();
+        assert_eq!(result, vec![(1, vec![10, 12]), (2, vec![20]), (4, vec![])]);
+    }
+
+    #[quickcheck]
+    fn test_difference(a: BTreeSet<u8>, b: BTreeSet<u8>) {
+        let a_vec: Vec<u8> = a.iter().cloned().collect();
+        let b_vec: Vec<u8> = b.iter().cloned().collect();
+        let result = SortedVecGalloper::new(&a_vec).minus(SortedVecGalloper::new(&b_vec)).collect_indices();
+        assert_eq!(result, a.difference(&b).cloned().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,212 @@
+use std::rc::Rc;
+
+use super::{binary_search::binary_search, sparse_vec::SparseVec, stream_defs::IndexedStream};
+
+/// A galloping stream over an owned, shared copy of the entries of another stream,
+/// produced by `IndexedStream::materialize`.
+/// Cloning it is cheap, so it can be re-scanned (e.g. once per binding of an outer index)
+/// without recomputing the stream it was collected from.
+#[derive(Debug)]
+pub struct MaterializedStream<I, V> {
+    data: Rc<SparseVec<I, V>>,
+    cur: usize,
+    end: usize,
+}
+
+impl<I, V> Clone for MaterializedStream<I, V> {
+    fn clone(&self) -> Self {
+        MaterializedStream { data: self.data.clone(), cur: self.cur, end: self.end }
+    }
+}
+
+impl<I, V> MaterializedStream<I, V> {
+    pub fn new(data: Rc<SparseVec<I, V>>) -> Self {
+        let end = data.len();
+        MaterializedStream { data, cur: 0, end }
+    }
+
+    /// The entries the stream was collected into
+    pub fn data(&self) -> &Rc<SparseVec<I, V>> {
+        &self.data
+    }
+}
+
+impl<I: Ord + Copy, V: Clone> IndexedStream for MaterializedStream<I, V> {
+    type I = I;
+    type V = V;
+
+    fn valid(&self) -> bool {
+        self.cur < self.end
+    }
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    fn seek(&mut self, index: I, strict: bool) {
+        self.cur += binary_search(&self.data.inds[self.cur..self.end], &index, strict);
+    }
+
+    fn next(&mut self) {
+        self.cur += 1;
+    }
+
+    fn index(&self) -> I {
+        self.data.inds[self.cur]
+    }
+
+    fn value(&self) -> V {
+        self.data.vals[self.cur].clone()
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.end - self.cur)
+    }
+}
+
+/// The entries of a nested stream, stored like the rows of a `SparseCSRMat`
+/// but with arbitrary outer and inner index types
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct NestedEntries<I, J, V> {
+    inds: Vec<I>,
+    /// The inner entries of `inds[p]` are `inner.inds[offsets[p]..offsets[p + 1]]`
+    offsets: Vec<usize>,
+    inner: Rc<SparseVec<J, V>>,
+}
+
+/// A galloping stream over an owned, shared copy of the entries of a nested stream,
+/// produced by `IndexedStream::materialize_nested`.
+/// Its values are `MaterializedStream`s over the inner entries, sharing the same storage.
+#[derive(Debug)]
+pub struct MaterializedNestedStream<I, J, V> {
+    data: Rc<NestedEntries<I, J, V>>,
+    cur: usize,
+    end: usize,
+}
+
+impl<I, J, V> Clone for MaterializedNestedStream<I, J, V> {
+    fn clone(&self) -> Self {
+        MaterializedNestedStream { data: self.data.clone(), cur: self.cur, end: self.end }
+    }
+}
+
+impl<I, J, V> MaterializedNestedStream<I, J, V> {
+    /// Collects `stream`, keeping the indices whose inner stream is empty
+    pub(crate) fn from_nested<S>(stream: S) -> Self
+    where
+        S: IndexedStream<I = I>,
+        S::V: IndexedStream<I = J, V = V>
+    {
+        let mut inds = Vec::new();
+        let mut offsets = vec![0];
+        let mut inner = SparseVec::empty();
+        stream.for_each(|i, v| {
+            v.for_each(|j, x| {
+                inner.inds.push(j);
+                inner.vals.push(x);
+            });
+            inds.push(i);
+            offsets.push(inner.len());
+        });
+        let end = inds.len();
+        MaterializedNestedStream {
+            data: Rc::new(NestedEntries { inds, offsets, inner: Rc::new(inner) }),
+            cur: 0,
+            end,
+        }
+    }
+}
+
+impl<I: Ord + Copy, J: Ord + Copy, V: Clone> IndexedStream for MaterializedNestedStream<I, J, V> {
+    type I = I;
+    type V = MaterializedStream<J, V>;
+
+    fn valid(&self) -> bool {
+        self.cur < self.end
+    }
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    fn seek(&mut self, index: I, strict: bool) {
+        self.cur += binary_search(&self.data.inds[self.cur..self.end], &index, strict);
+    }
+
+    fn next(&mut self) {
+        self.cur += 1;
+    }
+
+    fn index(&self) -> I {
+        self.data.inds[self.cur]
+    }
+
+    fn value(&self) -> MaterializedStream<J, V> {
+        MaterializedStream {
+            data: self.data.inner.clone(),
+            cur: self.data.offsets[self.cur],
+            end: self.data.offsets[self.cur + 1],
+        }
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.end - self.cur)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use crate::streams::{sorted_vec::SortedVecGalloper, sparse_vec::SparseVec, stream_defs::IndexedStream};
+
+    #[test]
+    fn test_materialize() {
+        let x = SparseVec::from_iter([(1, 10), (4, 40), (6, 60), (9, 90)]);
+        let y = SparseVec::from_iter([(4, 1), (5, 1), (9, 2)]);
+        let evaluated = Cell::new(0);
+        let product = x.stream_iter().zip_with(y.stream_iter(), |a, b| {
+            evaluated.set(evaluated.get() + 1);
+            a * b
+        });
+        let materialized = product.materialize();
+        assert_eq!(evaluated.get(), 2);
+        assert_eq!(materialized.remaining_estimate(), Some(2));
+        for _ in 0..3 {
+            let collected: Vec<(i32, i32)> = materialized.clone().collect();
+            assert_eq!(collected, vec![(4, 40), (9, 180)]);
+        }
+        assert_eq!(evaluated.get(), 2);
+
+        let mut stream = materialized.clone();
+        stream.seek(4, true);
+        assert_eq!((stream.index(), stream.value()), (9, 180));
+        let zipped = materialized.zip_with(x.stream_iter(), |a, b| a + b);
+        assert_eq!(zipped.collect::<Vec<_>>(), vec![(4, 80), (9, 270)]);
+    }
+
+    #[test]
+    fn test_materialize_nested() {
+        let rows = SparseVec::from_iter([(1, vec![2, 3]), (5, vec![]), (7, vec![1, 3, 4])]);
+        let filter = [3, 4];
+        let nested = rows.stream_iter()
+            .map(|_, v| SortedVecGalloper::new(v).zip_with(SortedVecGalloper::new(&filter), |_, _| ()))
+            .materialize_nested();
+        assert_eq!(nested.remaining_estimate(), Some(3));
+        let collect = |stream: super::MaterializedNestedStream<i32, i32, ()>| stream
+            .map(|_, inner| inner.collect_indices())
+            .collect::<Vec<_>>();
+        // Empty inner streams are kept, as `collect` would
+        assert_eq!(collect(nested.clone()), vec![(1, vec![3]), (5, vec![]), (7, vec![3, 4])]);
+
+        let mut seeked = nested.clone();
+        seeked.seek(5, false);
+        assert_eq!(collect(seeked), vec![(5, vec![]), (7, vec![3, 4])]);
+        let mut inner = nested.clone();
+        inner.seek(7, false);
+        let mut inner = inner.value();
+        assert_eq!(inner.remaining_estimate(), Some(2));
+        inner.seek(3, true);
+        assert_eq!(inner.collect_indices(), vec![4]);
+    }
+}
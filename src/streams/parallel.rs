@@ -0,0 +1,137 @@
+use std::{ops::AddAssign, thread};
+
+use num_traits::Zero;
+
+use super::stream_defs::IndexedStream;
+
+/// Streams that can be split at an index into two independent streams,
+/// so that the halves can be consumed on different threads
+pub trait Splittable: IndexedStream + Sized {
+    /// An index splitting the remaining entries into two parts of roughly equal size,
+    /// or `None` if the stream is too short to be split
+    fn split_point(&self) -> Option<Self::I>;
+
+    /// Splits the stream into its entries before `index` and its entries from `index` on
+    fn split_at(self, index: Self::I) -> (Self, Self);
+
+    /// Folds the stream on up to `available_parallelism` threads,
+    /// folding each part starting from `identity()` and merging the parts in order with `combine`
+    fn par_fold<B, Id, F, C>(self, identity: Id, f: F, combine: C) -> B
+    where
+        Self: Send,
+        B: Send,
+        Id: Fn() -> B + Sync,
+        F: Fn(B, Self::I, Self::V) -> B + Sync,
+        C: Fn(B, B) -> B + Sync
+    {
+        let parts = thread::available_parallelism().map_or(1, |n| n.get());
+        par_fold_parts(self, parts, &identity, &f, &combine)
+    }
+
+    /// Like `contract`, summing the parts of the stream in parallel
+    fn par_contract(self) -> Self::V
+    where
+        Self: Send,
+        Self::V: AddAssign + Zero + Send
+    {
+        self.par_fold(Self::V::zero, |acc, _, v| acc + v, |a, b| a + b)
+    }
+}
+
+/// Splits `stream` into (at most) `parts` parts, each of them folded on its own thread
+fn par_fold_parts<S, B, Id, F, C>(stream: S, parts: usize, identity: &Id, f: &F, combine: &C) -> B
+where
+    S: Splittable + Send,
+    B: Send,
+    Id: Fn() -> B + Sync,
+    F: Fn(B, S::I, S::V) -> B + Sync,
+    C: Fn(B, B) -> B + Sync
+{
+    let split = if parts > 1 && stream.valid() { stream.split_point() } else { None };
+    match split {
+        Some(index) => {
+            let (left, right) = stream.split_at(index);
+            thread::scope(|scope| {
+                let left = scope.spawn(|| par_fold_parts(left, parts / 2, identity, f, combine));
+                let right = par_fold_parts(right, parts - parts / 2, identity, f, combine);
+                combine(left.join().expect("par_fold worker panicked"), right)
+            })
+        },
+        None => stream.fold(identity(), f),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::{
+        rbtree::rbtree_lib::RBTree,
+        streams::{csr_mat::SparseCSRMat, sorted_vec::SortedVecGalloper, sparse_vec::SparseVec, stream_defs::{IndexedStream, IntoStreamIterator}},
+    };
+
+    use super::{par_fold_parts, Splittable};
+
+    /// Checks that splitting at every point of `stream` partitions its entries
+    fn check_splits<S: Splittable + Clone>(stream: S)
+    where
+        S::I: Ord + std::fmt::Debug
+    {
+        let all = stream.clone().collect_indices();
+        for &index in all.iter() {
+            let (left, right) = stream.clone().split_at(index);
+            let mut joined = left.collect_indices();
+            assert!(joined.iter().all(|&i| i < index));
+            joined.extend(right.collect_indices());
+            assert_eq!(joined, all);
+        }
+    }
+
+    #[test]
+    fn test_split_sources() {
+        let inds = [1, 4, 5, 9, 12, 13];
+        check_splits(SortedVecGalloper::new(&inds));
+        let vec = SparseVec::from_iter(inds.iter().map(|&i| (i, i * 2)));
+        check_splits(vec.stream_iter());
+        let mat = SparseCSRMat::from_iter([(0, 1, 1), (2, 0, 2), (2, 3, 3), (5, 1, 4)]);
+        check_splits(mat.into_stream_iterator());
+        let tree: RBTree<u32, ()> = inds.iter().map(|&i| (i, ())).collect();
+        check_splits(tree.stream_iter());
+        assert!(tree.stream_iter().split_point().is_some());
+        let mut partial = tree.stream_iter();
+        partial.seek(5, false);
+        check_splits(partial);
+        check_splits(SortedVecGalloper::new(&inds).zip_with(tree.stream_iter(), |_, _| ()).map(|i, _| i));
+    }
+
+    #[test]
+    fn test_par_contract() {
+        let x = SparseVec::from_iter((0..10_000u64).map(|i| (i * 2, i)));
+        let y = SparseVec::from_iter((0..10_000u64).map(|i| (i * 3, 1)));
+        let dot = || x.stream_iter().zip_with(y.stream_iter(), |a, b| a * b);
+        assert_eq!(dot().par_contract(), dot().contract());
+        for parts in 1..9 {
+            let count = par_fold_parts(dot(), parts, &|| 0, &|acc, _, _| acc + 1, &|a, b| a + b);
+            assert_eq!(count, 3334);
+        }
+    }
+
+    #[quickcheck]
+    fn test_par_fold_order(a: BTreeSet<u16>, b: BTreeSet<u16>, parts: u8) {
+        let a: Vec<u16> = a.into_iter().collect();
+        let b: Vec<u16> = b.into_iter().collect();
+        let tree: RBTree<u16, ()> = b.iter().map(|&i| (i, ())).collect();
+        let stream = SortedVecGalloper::new(&a).zip_with(tree.stream_iter(), |_, _| ());
+        let expected = stream.clone().collect_indices();
+        let result = par_fold_parts(
+            stream,
+            parts as usize % 16 + 1,
+            &Vec::new,
+            &|mut acc, i, _| { acc.push(i); acc },
+            &|mut left, right| { left.extend(right); left }
+        );
+        assert_eq!(result, expected);
+    }
+}
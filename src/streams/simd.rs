@@ -0,0 +1,273 @@
+//! Block-compare intersection of sorted `u32` slices.
+//!
+//! `ZipStream::try_fold` runs this kernel instead of stepping through its streams one entry at a time
+//! when both sides are streams over sorted `u32` slices (see `IndexedStream::sorted_u32`),
+//! so every consumer built on `try_fold` benefits, in generic code as well.
+
+use std::{any::TypeId, marker::PhantomData, mem, ops::ControlFlow, slice};
+
+/// Above this ratio between the remaining lengths, galloping beats a linear merge
+const SKEW_RATIO: usize = 32;
+
+pub(crate) fn is_skewed(a: usize, b: usize) -> bool {
+    a > b.saturating_mul(SKEW_RATIO) || b > a.saturating_mul(SKEW_RATIO)
+}
+
+/// The `TypeId` of `T` with its lifetimes erased, which is enough to tell whether `T` is some `'static` type
+fn erased_type_id<T: ?Sized>() -> TypeId {
+    trait NonStaticAny {
+        fn get_type_id(&self) -> TypeId where Self: 'static;
+    }
+
+    impl<T: ?Sized> NonStaticAny for PhantomData<T> {
+        fn get_type_id(&self) -> TypeId where Self: 'static {
+            TypeId::of::<T>()
+        }
+    }
+
+    let phantom: &dyn NonStaticAny = &PhantomData::<T>;
+    // SAFETY: only the lifetime of the trait object changes, and `TypeId::of` does not depend on lifetimes
+    unsafe { mem::transmute::<&dyn NonStaticAny, &(dyn NonStaticAny + 'static)>(phantom) }.get_type_id()
+}
+
+/// `slice` as a slice of `u32`, if that is its element type
+pub(crate) fn as_u32_slice<T>(slice: &[T]) -> Option<&[u32]> {
+    // SAFETY: `T` is `u32`
+    (erased_type_id::<T>() == TypeId::of::<u32>()).then(|| unsafe { slice::from_raw_parts(slice.as_ptr() as *const u32, slice.len()) })
+}
+
+/// Folds `f` over the positions `(p, q)` with `a[p] == b[q]`, starting from `a[*i..]` and `b[*j..]`.
+/// Both slices must be strictly increasing.
+/// On return, `*i` and `*j` point past the last match passed to `f` (on a break)
+/// or to the end of one of the slices.
+pub(crate) fn intersect_u32<B, R, F>(a: &[u32], b: &[u32], i: &mut usize, j: &mut usize, init: B, f: F) -> ControlFlow<R, B>
+where
+    F: FnMut(B, usize, usize) -> ControlFlow<R, B>
+{
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 is available
+            unsafe { x86::intersect_avx2(a, b, i, j, init, f) }
+        } else {
+            // SAFETY: SSE2 is part of the x86_64 baseline
+            unsafe { x86::intersect_sse2(a, b, i, j, init, f) }
+        }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        intersect_scalar(a, b, i, j, init, f)
+    }
+}
+
+/// The portable merge loop, also used for the tails shorter than a block
+pub(crate) fn intersect_scalar<B, R, F>(a: &[u32], b: &[u32], i: &mut usize, j: &mut usize, init: B, mut f: F) -> ControlFlow<R, B>
+where
+    F: FnMut(B, usize, usize) -> ControlFlow<R, B>
+{
+    let mut acc = init;
+    while *i < a.len() && *j < b.len() {
+        let (x, y) = (a[*i], b[*j]);
+        if x < y {
+            *i += 1;
+        } else if y < x {
+            *j += 1;
+        } else {
+            let (p, q) = (*i, *j);
+            *i += 1;
+            *j += 1;
+            acc = f(acc, p, q)?;
+        }
+    }
+    ControlFlow::Continue(acc)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::{arch::x86_64::*, ops::ControlFlow};
+
+    use super::intersect_scalar;
+
+    /// Emits the matches of the block `a[*i..*i + N]` against `b[*j..*j + N]`, given the mask of
+    /// the lanes of `a` found in `b`, then advances past the block(s) with the smaller maximum
+    #[inline(always)]
+    fn emit_block<const N: usize, B, R, F>(a: &[u32], b: &[u32], i: &mut usize, j: &mut usize, mut mask: u32, acc: B, f: &mut F) -> ControlFlow<R, B>
+    where
+        F: FnMut(B, usize, usize) -> ControlFlow<R, B>
+    {
+        let mut acc = acc;
+        while mask != 0 {
+            let k = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            let x = a[*i + k];
+            let l = b[*j..*j + N].iter().position(|&y| y == x).expect("masked lane must match");
+            match f(acc, *i + k, *j + l) {
+                ControlFlow::Continue(next) => acc = next,
+                ControlFlow::Break(r) => {
+                    *i += k + 1;
+                    *j += l + 1;
+                    return ControlFlow::Break(r);
+                },
+            }
+        }
+        let (a_max, b_max) = (a[*i + N - 1], b[*j + N - 1]);
+        if a_max <= b_max {
+            *i += N;
+        }
+        if b_max <= a_max {
+            *j += N;
+        }
+        ControlFlow::Continue(acc)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn intersect_sse2<B, R, F>(a: &[u32], b: &[u32], i: &mut usize, j: &mut usize, init: B, mut f: F) -> ControlFlow<R, B>
+    where
+        F: FnMut(B, usize, usize) -> ControlFlow<R, B>
+    {
+        let mut acc = init;
+        while *i + 4 <= a.len() && *j + 4 <= b.len() {
+            // SAFETY: both blocks are in bounds
+            let mask = unsafe {
+                let va = _mm_loadu_si128(a.as_ptr().add(*i) as *const __m128i);
+                let vb = _mm_loadu_si128(b.as_ptr().add(*j) as *const __m128i);
+                // Compare every lane of `va` with every lane of `vb` through the rotations of `vb`
+                let r1 = _mm_shuffle_epi32::<0b00_11_10_01>(vb);
+                let r2 = _mm_shuffle_epi32::<0b01_00_11_10>(vb);
+                let r3 = _mm_shuffle_epi32::<0b10_01_00_11>(vb);
+                let eq = _mm_or_si128(
+                    _mm_or_si128(_mm_cmpeq_epi32(va, vb), _mm_cmpeq_epi32(va, r1)),
+                    _mm_or_si128(_mm_cmpeq_epi32(va, r2), _mm_cmpeq_epi32(va, r3)),
+                );
+                _mm_movemask_ps(_mm_castsi128_ps(eq)) as u32
+            };
+            acc = emit_block::<4, B, R, F>(a, b, i, j, mask, acc, &mut f)?;
+        }
+        intersect_scalar(a, b, i, j, acc, f)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn intersect_avx2<B, R, F>(a: &[u32], b: &[u32], i: &mut usize, j: &mut usize, init: B, mut f: F) -> ControlFlow<R, B>
+    where
+        F: FnMut(B, usize, usize) -> ControlFlow<R, B>
+    {
+        let mut acc = init;
+        while *i + 8 <= a.len() && *j + 8 <= b.len() {
+            // SAFETY: both blocks are in bounds
+            let mask = unsafe {
+                let va = _mm256_loadu_si256(a.as_ptr().add(*i) as *const __m256i);
+                let mut vb = _mm256_loadu_si256(b.as_ptr().add(*j) as *const __m256i);
+                let rotate = _mm256_setr_epi32(1, 2, 3, 4, 5, 6, 7, 0);
+                let mut eq = _mm256_cmpeq_epi32(va, vb);
+                for _ in 1..8 {
+                    vb = _mm256_permutevar8x32_epi32(vb, rotate);
+                    eq = _mm256_or_si256(eq, _mm256_cmpeq_epi32(va, vb));
+                }
+                _mm256_movemask_ps(_mm256_castsi256_ps(eq)) as u32
+            };
+            acc = emit_block::<8, B, R, F>(a, b, i, j, mask, acc, &mut f)?;
+        }
+        unsafe { intersect_sse2(a, b, i, j, acc, f) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeSet, ops::ControlFlow};
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::streams::{parallel::Splittable, sorted_vec::SortedVecGalloper, sparse_vec::SparseVec, stream_defs::IndexedStream};
+
+    use super::{as_u32_slice, intersect_scalar, intersect_u32};
+
+    fn matches(kernel: impl Fn(&[u32], &[u32], &mut usize, &mut usize) -> Vec<(usize, usize)>, a: &[u32], b: &[u32]) -> Vec<(usize, usize)> {
+        kernel(a, b, &mut 0, &mut 0)
+    }
+
+    fn collect_scalar(a: &[u32], b: &[u32], i: &mut usize, j: &mut usize) -> Vec<(usize, usize)> {
+        match intersect_scalar(a, b, i, j, Vec::new(), |mut acc, p, q| { acc.push((p, q)); ControlFlow::<(), _>::Continue(acc) }) {
+            ControlFlow::Continue(acc) => acc,
+            ControlFlow::Break(()) => unreachable!(),
+        }
+    }
+
+    fn collect_dispatched(a: &[u32], b: &[u32], i: &mut usize, j: &mut usize) -> Vec<(usize, usize)> {
+        match intersect_u32(a, b, i, j, Vec::new(), |mut acc, p, q| { acc.push((p, q)); ControlFlow::<(), _>::Continue(acc) }) {
+            ControlFlow::Continue(acc) => acc,
+            ControlFlow::Break(()) => unreachable!(),
+        }
+    }
+
+    #[quickcheck]
+    fn test_kernels_agree(a: BTreeSet<u16>, b: BTreeSet<u16>) {
+        // Small values so that the sets overlap
+        let a: Vec<u32> = a.into_iter().map(|x| x as u32 % 512).collect::<BTreeSet<_>>().into_iter().collect();
+        let b: Vec<u32> = b.into_iter().map(|x| x as u32 % 512).collect::<BTreeSet<_>>().into_iter().collect();
+        let expected = matches(collect_scalar, &a, &b);
+        assert_eq!(matches(collect_dispatched, &a, &b), expected);
+        #[cfg(target_arch = "x86_64")]
+        {
+            let sse2 = |a: &[u32], b: &[u32], i: &mut usize, j: &mut usize| {
+                let mut out = Vec::new();
+                let _ = unsafe { super::x86::intersect_sse2(a, b, i, j, (), |(), p, q| { out.push((p, q)); ControlFlow::<()>::Continue(()) }) };
+                out
+            };
+            assert_eq!(matches(sse2, &a, &b), expected);
+            if is_x86_feature_detected!("avx2") {
+                let avx2 = |a: &[u32], b: &[u32], i: &mut usize, j: &mut usize| {
+                    let mut out = Vec::new();
+                    let _ = unsafe { super::x86::intersect_avx2(a, b, i, j, (), |(), p, q| { out.push((p, q)); ControlFlow::<()>::Continue(()) }) };
+                    out
+                };
+                assert_eq!(matches(avx2, &a, &b), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_zip_fast_path() {
+        let a: Vec<u32> = (0..1000).map(|x| x * 3).collect();
+        let b: Vec<u32> = (0..1000).map(|x| x * 5).collect();
+        let expected: Vec<u32> = (0..200).map(|x| x * 15).collect();
+        assert_eq!(SortedVecGalloper::new(&a).zip_with(SortedVecGalloper::new(&b), |_, _| ()).collect_indices(), expected);
+
+        let x = SparseVec::from_iter(a.iter().map(|&i| (i, i as u64)));
+        let y = SparseVec::from_iter(b.iter().map(|&i| (i, 2u64)));
+        let dot = x.stream_iter().zip_with(y.stream_iter(), |u, v| u * v).contract();
+        assert_eq!(dot, expected.iter().map(|&i| 2 * i as u64).sum::<u64>());
+
+        // Skewed inputs take the galloping path
+        let few = [15, 2970, 2985];
+        assert_eq!(SortedVecGalloper::new(&few).zip_with(SortedVecGalloper::new(&b), |_, _| ()).collect_indices(), few.to_vec());
+    }
+
+    #[test]
+    fn test_try_fold_resumes() {
+        let a: Vec<u32> = (0..100).collect();
+        let b: Vec<u32> = (0..100).filter(|x| x % 7 == 0).collect();
+        let mut zip = SortedVecGalloper::new(&a).zip_with(SortedVecGalloper::new(&b), |_, _| ());
+        let first = zip.try_fold((), |(), i, _| if i > 20 { ControlFlow::Break(i) } else { ControlFlow::Continue(()) });
+        assert_eq!(first, ControlFlow::Break(21));
+        // The stream is left past the entry it broke on
+        assert_eq!(zip.collect_indices(), vec![28, 35, 42, 49, 56, 63, 70, 77, 84, 91, 98]);
+    }
+
+    #[test]
+    fn test_fast_path_through_adapters() {
+        // Generic consumers and adapters reach the kernel through `ZipStream::try_fold`
+        fn count<S: IndexedStream>(stream: S) -> usize {
+            stream.fold(0, |n, _, _| n + 1)
+        }
+        let a: Vec<u32> = (0..10_000).map(|x| x * 2).collect();
+        let b: Vec<u32> = (0..10_000).map(|x| x * 3).collect();
+        let zip = || SortedVecGalloper::new(&a).zip_with(SortedVecGalloper::new(&b), |_, _| 1u64);
+        assert_eq!(count(zip()), 3334);
+        assert_eq!(zip().map(|i, v| v * i as u64).contract(), (0..3334).map(|x| x * 6).sum::<u64>());
+        assert_eq!(zip().par_contract(), 3334);
+
+        assert!(as_u32_slice(&a).is_some());
+        assert!(as_u32_slice(&[1i32, 2]).is_none());
+        assert!(as_u32_slice(&[&1u32]).is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use super::{binary_search::binary_search, stream_defs::IndexedStream};
+use super::{binary_search::{binary_search, AdaptiveSearch}, parallel::Splittable, simd::as_u32_slice, stream_defs::IndexedStream};
 
 #[derive(Debug, Clone)]
 pub struct SortedVecGalloper<'a, T> {
@@ -12,6 +12,14 @@ pub struct SortedVecLinear<'a, T> {
     cur: usize,
 }
 
+/// Switches between stepping linearly and galloping depending on how far recent seeks skipped
+#[derive(Debug, Clone)]
+pub struct SortedVecAdaptive<'a, T> {
+    inds: &'a [T],
+    cur: usize,
+    search: AdaptiveSearch,
+}
+
 impl<'a, T> SortedVecGalloper<'a, T> {
     pub fn new(inds: &'a [T]) -> Self {
         SortedVecGalloper { inds, cur: 0 }
@@ -45,6 +53,43 @@ impl<T: Ord + Copy> IndexedStream for SortedVecGalloper<'_, T> {
     }
 
     fn value(&self) -> () {}
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.inds.len().saturating_sub(self.cur))
+    }
+
+    fn sorted_u32(&self) -> Option<(&[u32], usize)> {
+        Some((as_u32_slice(self.inds)?, self.cur))
+    }
+
+    fn entry_at(&self, position: usize) -> (T, ()) {
+        (self.inds[position], ())
+    }
+
+    fn set_position(&mut self, position: usize) {
+        self.cur = position;
+    }
+}
+
+impl<T: Ord + Copy> Splittable for SortedVecGalloper<'_, T> {
+    fn split_point(&self) -> Option<T> {
+        let remaining = self.inds.len().saturating_sub(self.cur);
+        (remaining >= 2).then(|| self.inds[self.cur + remaining / 2])
+    }
+
+    fn split_at(self, index: T) -> (Self, Self) {
+        let mid = self.cur + binary_search(&self.inds[self.cur..], &index, false);
+        (
+            SortedVecGalloper { inds: &self.inds[..mid], cur: self.cur },
+            SortedVecGalloper { inds: self.inds, cur: mid },
+        )
+    }
+}
+
+impl<'a, T> SortedVecAdaptive<'a, T> {
+    pub fn new(inds: &'a [T]) -> Self {
+        SortedVecAdaptive { inds, cur: 0, search: AdaptiveSearch::default() }
+    }
 }
 
 impl<T: Ord + Copy> IndexedStream for SortedVecLinear<'_, T> {
@@ -70,5 +115,40 @@ impl<T: Ord + Copy> IndexedStream for SortedVecLinear<'_, T> {
     }
 
     fn value(&self) -> () {}
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.inds.len().saturating_sub(self.cur))
+    }
 }
 
+impl<T: Ord + Copy> IndexedStream for SortedVecAdaptive<'_, T> {
+    type I = T;
+    type V = ();
+
+    fn valid(&self) -> bool {
+        self.cur < self.inds.len()
+    }
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    #[inline(always)]
+    fn seek(&mut self, index: T, strict: bool) {
+        self.cur += self.search.search(&self.inds[self.cur..], &index, strict);
+    }
+
+    fn next(&mut self) {
+        self.cur += 1;
+    }
+
+    fn index(&self) -> T {
+        self.inds[self.cur]
+    }
+
+    fn value(&self) {}
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.inds.len().saturating_sub(self.cur))
+    }
+}
@@ -1,6 +1,6 @@
 use num_traits::Zero;
 
-use super::{binary_search::binary_search, stream_defs::{FromStreamIterator, IndexedStream, IntoStreamIterator}};
+use super::{binary_search::{binary_search, AdaptiveSearch}, parallel::Splittable, simd::as_u32_slice, stream_defs::{FromStreamIterator, IndexedStream, IntoStreamIterator}};
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct SparseVec<I, T> {
@@ -87,6 +87,15 @@ pub struct SparseVecIterator<'a, I, T> {
     cur: usize
 }
 
+/// Switches between stepping linearly and galloping depending on how far recent seeks skipped
+#[derive(Debug, Clone)]
+pub struct SparseVecAdaptive<'a, I, T> {
+    inds: &'a [I],
+    vals: &'a [T],
+    cur: usize,
+    search: AdaptiveSearch,
+}
+
 impl<'a, I, T> SparseVecIterator<'a, I, T> {
     pub fn new(inds: &'a [I], vals: &'a [T]) -> Self {
         SparseVecIterator { inds, vals, cur: 0 }
@@ -128,6 +137,71 @@ impl<'a, I: Ord + Copy, T> IndexedStream for SparseVecGalloper<'a, I, T> {
     fn value(&self) -> &'a T {
         &self.vals[self.cur]
     }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.inds.len().saturating_sub(self.cur))
+    }
+
+    fn sorted_u32(&self) -> Option<(&[u32], usize)> {
+        Some((as_u32_slice(self.inds)?, self.cur))
+    }
+
+    fn entry_at(&self, position: usize) -> (I, &'a T) {
+        (self.inds[position], &self.vals[position])
+    }
+
+    fn set_position(&mut self, position: usize) {
+        self.cur = position;
+    }
+}
+
+impl<'a, I: Ord + Copy, T> IndexedStream for SparseVecAdaptive<'a, I, T> {
+    type I = I;
+    type V = &'a T;
+
+    fn valid(&self) -> bool {
+        self.cur < self.inds.len()
+    }
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    #[inline(always)]
+    fn seek(&mut self, index: I, strict: bool) {
+        self.cur += self.search.search(&self.inds[self.cur..], &index, strict);
+    }
+
+    fn next(&mut self) {
+        self.cur += 1;
+    }
+
+    fn index(&self) -> I {
+        self.inds[self.cur]
+    }
+
+    fn value(&self) -> &'a T {
+        &self.vals[self.cur]
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.inds.len().saturating_sub(self.cur))
+    }
+}
+
+impl<I: Ord + Copy, T> Splittable for SparseVecGalloper<'_, I, T> {
+    fn split_point(&self) -> Option<I> {
+        let remaining = self.inds.len().saturating_sub(self.cur);
+        (remaining >= 2).then(|| self.inds[self.cur + remaining / 2])
+    }
+
+    fn split_at(self, index: I) -> (Self, Self) {
+        let mid = self.cur + binary_search(&self.inds[self.cur..], &index, false);
+        (
+            SparseVecGalloper { inds: &self.inds[..mid], vals: &self.vals[..mid], cur: self.cur },
+            SparseVecGalloper { inds: self.inds, vals: self.vals, cur: mid },
+        )
+    }
 }
 
 impl<'a, I: Ord + Copy, T> IndexedStream for SparseVecIterator<'a, I, T> {
@@ -162,6 +236,10 @@ impl<'a, I: Ord + Copy, T> IndexedStream for SparseVecIterator<'a, I, T> {
     fn value(&self) -> &'a T {
         &self.vals[self.cur]
     }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.inds.len().saturating_sub(self.cur))
+    }
 }
 
 impl<I, T> SparseVec<I, T> {
@@ -180,6 +258,16 @@ impl<I, T> SparseVec<I, T> {
             cur: 0
         }
     }
+
+    /// A stream choosing between linear stepping and galloping as it goes
+    pub fn stream_iter_adaptive(&self) -> SparseVecAdaptive<'_, I, T> {
+        SparseVecAdaptive {
+            inds: &self.inds,
+            vals: &self.vals,
+            cur: 0,
+            search: AdaptiveSearch::default()
+        }
+    }
 }
 
 impl<'a, I: Ord + Copy, T> IntoStreamIterator for &'a SparseVec<I, T> {
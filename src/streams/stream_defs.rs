@@ -1,8 +1,8 @@
-use std::{convert::Infallible, marker::PhantomData, ops::{AddAssign, ControlFlow}};
+use std::{convert::Infallible, marker::PhantomData, ops::{AddAssign, ControlFlow}, rc::Rc};
 
 use num_traits::Zero;
 
-use super::{chain::{ChainStream, FixedChainStream}, zip_stream::ZipStream};
+use super::{aggregate_stream::{AggregateStream, ProjectedStream}, chain::{ChainStream, FixedChainStream}, difference_stream::{DifferenceStream, NestedDifferenceStream}, materialize::{MaterializedNestedStream, MaterializedStream}, parallel::Splittable, sparse_vec::SparseVec, zip_stream::ZipStream};
 
 pub trait IndexedStream {
     type I: Copy;
@@ -38,6 +38,31 @@ pub trait IndexedStream {
     /// Will only be called when `valid` and `ready` are true
     fn value(&self) -> Self::V;
 
+    /// An estimate of the number of entries left in the stream (exact for most formats),
+    /// or `None` if the stream can not tell without scanning itself.
+    /// Intersections use it to be driven by their sparser side.
+    fn remaining_estimate(&self) -> Option<usize> {
+        None
+    }
+
+    /// For a stream over a strictly increasing slice of `u32` indices, all of them ready:
+    /// the slice and the position of the stream in it.
+    /// `ZipStream::try_fold` intersects two such streams with the block kernels of `simd`,
+    /// reading their entries with `entry_at` and moving them with `set_position`.
+    fn sorted_u32(&self) -> Option<(&[u32], usize)> {
+        None
+    }
+
+    /// The entry at a position of the slice given by `sorted_u32`
+    fn entry_at(&self, _position: usize) -> (Self::I, Self::V) {
+        unreachable!("only called on streams that give `sorted_u32`")
+    }
+
+    /// Moves the stream to a position of the slice given by `sorted_u32`
+    fn set_position(&mut self, _position: usize) {
+        unreachable!("only called on streams that give `sorted_u32`")
+    }
+
     /// Get the value of the stream by folding over it.
     /// A default implementation is given.
     /// Stream combinators can override with more efficient implementations
@@ -122,6 +147,42 @@ pub trait IndexedStream {
         ).is_break()
     }
 
+    /// Determines if the stream yields any element, stopping at the first one.
+    /// Unlike `any_nonzero`, values are never computed.
+    fn exists(mut self) -> bool
+    where
+        Self: Sized
+    {
+        while self.valid() {
+            if self.ready() {
+                return true;
+            }
+            self.next();
+        }
+        false
+    }
+
+    /// For a nested stream, keeps the indices whose inner stream is non-empty.
+    /// Each inner stream is only scanned up to its first element.
+    fn project_out(self) -> ProjectedStream<Self>
+    where
+        Self: Sized,
+        Self::V: IndexedStream
+    {
+        AggregateStream::new(self, |inner| inner.exists().then_some(()))
+    }
+
+    /// Reduces the value at each index (typically an inner stream) with `f`,
+    /// keeping the indices where it returns `Some`.
+    /// Unlike `map`, the reduction can drop entries, and it is only evaluated for the entries
+    /// the consumer inspects.
+    fn aggregate_inner<O, F: Fn(Self::V) -> Option<O>>(self, f: F) -> AggregateStream<Self, F, O>
+    where
+        Self: Sized
+    {
+        AggregateStream::new(self, f)
+    }
+
     fn map<O, F: Fn(Self::I, Self::V) -> O>(self, map: F) -> MappedStream<Self, F, O>
     where
         Self: Sized
@@ -144,6 +205,25 @@ pub trait IndexedStream {
         ZipStream::new(self, right, f)
     }
 
+    /// The entries of `self` whose index is not an entry of `right`
+    fn minus<R: IndexedStream<I = Self::I>>(self, right: R) -> DifferenceStream<Self, R>
+    where
+        Self: Sized,
+        Self::I: Ord
+    {
+        DifferenceStream::new(self, Some(right))
+    }
+
+    /// The anti-join of two nested streams: the inner streams of `self`
+    /// minus the inner streams of `right` at the same index
+    fn minus_nested<R: IndexedStream<I = Self::I>>(self, right: R) -> NestedDifferenceStream<Self, R>
+    where
+        Self: Sized,
+        Self::I: Ord
+    {
+        NestedDifferenceStream::new(self, right)
+    }
+
     fn collect<O: FromStreamIterator<Self::I, Self::V>>(self) -> O
     where
         Self: Sized
@@ -151,6 +231,27 @@ pub trait IndexedStream {
         O::from_stream_iterator(self)
     }
 
+    /// Evaluates the stream once into owned storage and returns a stream over it,
+    /// cutting fusion: consumers (and clones) of the result no longer recompute `self`
+    fn materialize(self) -> MaterializedStream<Self::I, Self::V>
+    where
+        Self: Sized,
+        Self::I: Ord,
+        Self::V: Clone
+    {
+        MaterializedStream::new(Rc::new(self.collect::<SparseVec<_, _>>()))
+    }
+
+    /// Like `materialize`, for a nested stream: both levels are evaluated into
+    /// flat, CSR-like storage, and the inner streams share it
+    fn materialize_nested(self) -> MaterializedNestedStream<Self::I, <Self::V as IndexedStream>::I, <Self::V as IndexedStream>::V>
+    where
+        Self: Sized,
+        Self::V: IndexedStream
+    {
+        MaterializedNestedStream::from_nested(self)
+    }
+
     fn and_then_chain<B, F>(self, second: F) -> ChainStream<Self, B, F>
     where
         Self: Sized,
@@ -258,12 +359,29 @@ impl<S, F, O> IndexedStream for MappedStream<S, F, O>
         (self.map)(self.stream.index(), self.stream.value())
     }
 
+    fn remaining_estimate(&self) -> Option<usize> {
+        self.stream.remaining_estimate()
+    }
+
     fn try_fold<B, FF, R>(&mut self, init: B, mut f: FF) -> ControlFlow<R, B> where
             FF: FnMut(B, Self::I, Self::V) -> ControlFlow<R, B> {
         self.stream.try_fold(init, |acc, i, v| f(acc, i, (self.map)(i, v)))
     }
 }
 
+impl<S, F, O> Splittable for MappedStream<S, F, O>
+    where S: Splittable,
+          F: Fn(S::I, S::V) -> O + Clone {
+    fn split_point(&self) -> Option<Self::I> {
+        self.stream.split_point()
+    }
+
+    fn split_at(self, index: Self::I) -> (Self, Self) {
+        let (left, right) = self.stream.split_at(index);
+        (MappedStream::map(left, self.map.clone()), MappedStream::map(right, self.map))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ClonedStream<S> 
 where
@@ -313,6 +431,10 @@ where
         self.stream.value().clone()
     }
 
+    fn remaining_estimate(&self) -> Option<usize> {
+        self.stream.remaining_estimate()
+    }
+
     fn try_fold<B, F, R>(&mut self, init: B, mut f: F) -> ControlFlow<R, B>
     where
         F: FnMut(B, Self::I, Self::V) -> ControlFlow<R, B>
@@ -0,0 +1,229 @@
+use super::{binary_search::binary_search, stream_defs::{IndexedStream, IntoStreamIterator}};
+
+/// A relation of fixed arity stored as a trie, one level per column.
+/// Each level is a flat sorted run of keys per prefix, similar to the rows of a `SparseCSRMat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trie<I> {
+    /// `keys[l]` holds the keys of level `l`, grouped by their prefix and sorted within each group
+    keys: Vec<Vec<I>>,
+    /// The children of the `p`-th entry of level `l` are `keys[l + 1][offsets[l][p]..offsets[l][p + 1]]`
+    offsets: Vec<Vec<usize>>,
+}
+
+impl<I: Ord + Copy> Trie<I> {
+    /// Builds a trie from unsorted tuples, removing duplicates.
+    /// Level `l` of the trie is column `l` of the tuples.
+    pub fn from_tuples<T: AsRef<[I]>>(arity: usize, tuples: impl IntoIterator<Item = T>) -> Self {
+        Self::from_tuples_permuted(&(0..arity).collect::<Vec<_>>(), tuples)
+    }
+
+    /// Builds a trie from unsorted tuples, removing duplicates.
+    /// Level `l` of the trie is column `permutation[l]` of the tuples,
+    /// so columns left out of `permutation` are projected away.
+    pub fn from_tuples_permuted<T: AsRef<[I]>>(permutation: &[usize], tuples: impl IntoIterator<Item = T>) -> Self {
+        assert!(!permutation.is_empty(), "a trie must have at least one level");
+        let mut rows: Vec<Vec<I>> = tuples.into_iter()
+            .map(|tuple| permutation.iter().map(|&c| tuple.as_ref()[c]).collect())
+            .collect();
+        rows.sort_unstable();
+        rows.dedup();
+
+        let arity = permutation.len();
+        let mut keys: Vec<Vec<I>> = vec![Vec::new(); arity];
+        let mut offsets: Vec<Vec<usize>> = vec![vec![0]; arity - 1];
+        for (r, row) in rows.iter().enumerate() {
+            // The first level at which this row differs from the previous one starts a new entry
+            // there and at every level below it
+            let first_new = match r.checked_sub(1) {
+                Some(prev) => row.iter().zip(rows[prev].iter()).position(|(a, b)| a != b).unwrap_or(arity),
+                None => 0,
+            };
+            for l in first_new..arity {
+                if l > 0 {
+                    *offsets[l - 1].last_mut().unwrap() += 1;
+                }
+                if l + 1 < arity {
+                    let end = *offsets[l].last().unwrap();
+                    offsets[l].push(end);
+                }
+                keys[l].push(row[l]);
+            }
+        }
+        Trie { keys, offsets }
+    }
+
+    /// The number of levels of the trie
+    pub fn arity(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// The number of (distinct) tuples in the relation
+    pub fn len(&self) -> usize {
+        self.keys.last().map_or(0, |keys| keys.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of distinct prefixes of length `level + 1`,
+    /// i.e. the number of entries at the given level.
+    pub fn distinct_prefixes(&self, level: usize) -> usize {
+        self.keys[level].len()
+    }
+
+    /// The number of children of each key at `level`, for every level but the last
+    pub fn degrees(&self, level: usize) -> impl Iterator<Item = usize> + '_ {
+        self.offsets[level].windows(2).map(|w| w[1] - w[0])
+    }
+
+    pub fn stream_iter(&self) -> TrieStream<'_, I> {
+        TrieStream {
+            trie: self,
+            level: 0,
+            cur: 0,
+            end: self.keys[0].len(),
+        }
+    }
+
+    /// Lists the tuples of the relation in lexicographic order (of the trie levels)
+    pub fn to_tuples(&self) -> Vec<Vec<I>> {
+        fn visit<I: Ord + Copy>(stream: TrieStream<'_, I>, prefix: &mut Vec<I>, out: &mut Vec<Vec<I>>) {
+            let arity = stream.trie.arity();
+            stream.for_each(|i, child| {
+                prefix.push(i);
+                if prefix.len() == arity {
+                    out.push(prefix.clone());
+                } else {
+                    visit(child, prefix, out);
+                }
+                prefix.pop();
+            });
+        }
+        let mut result = Vec::with_capacity(self.len());
+        visit(self.stream_iter(), &mut Vec::with_capacity(self.arity()), &mut result);
+        result
+    }
+}
+
+/// A galloping stream over one level of a `Trie`.
+/// Its values are the streams over the next level; below the last level they are empty.
+#[derive(Debug)]
+pub struct TrieStream<'a, I> {
+    trie: &'a Trie<I>,
+    level: usize,
+    cur: usize,
+    end: usize,
+}
+
+impl<I> TrieStream<'_, I> {
+    /// Whether the stream is over the last level of the trie, whose values are empty streams
+    pub fn is_last_level(&self) -> bool {
+        self.level + 1 >= self.trie.keys.len()
+    }
+}
+
+impl<I> Clone for TrieStream<'_, I> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<I> Copy for TrieStream<'_, I> {}
+
+impl<'a, I: Ord + Copy> IndexedStream for TrieStream<'a, I> {
+    type I = I;
+    type V = TrieStream<'a, I>;
+
+    fn valid(&self) -> bool {
+        self.cur < self.end
+    }
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    fn seek(&mut self, index: I, strict: bool) {
+        self.cur += binary_search(&self.trie.keys[self.level][self.cur..self.end], &index, strict);
+    }
+
+    fn next(&mut self) {
+        self.cur += 1;
+    }
+
+    fn index(&self) -> I {
+        self.trie.keys[self.level][self.cur]
+    }
+
+    fn value(&self) -> TrieStream<'a, I> {
+        let (cur, end) = match self.trie.offsets.get(self.level) {
+            Some(offsets) => (offsets[self.cur], offsets[self.cur + 1]),
+            None => (0, 0),
+        };
+        TrieStream { trie: self.trie, level: self.level + 1, cur, end }
+    }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        Some(self.end.saturating_sub(self.cur))
+    }
+}
+
+impl<'a, I: Ord + Copy> IntoStreamIterator for &'a Trie<I> {
+    type IndexType = I;
+    type ValueType = TrieStream<'a, I>;
+    type StreamType = TrieStream<'a, I>;
+
+    fn into_stream_iterator(self) -> Self::StreamType {
+        self.stream_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::streams::stream_defs::IndexedStream;
+
+    use super::Trie;
+
+    #[test]
+    fn test_build() {
+        let trie = Trie::from_tuples(3, [[2, 1, 1], [1, 5, 2], [1, 3, 4], [2, 1, 1], [1, 3, 0]]);
+        assert_eq!(trie.len(), 4);
+        assert_eq!(trie.arity(), 3);
+        assert_eq!(
+            (0..3).map(|l| trie.distinct_prefixes(l)).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert_eq!(trie.to_tuples(), vec![vec![1, 3, 0], vec![1, 3, 4], vec![1, 5, 2], vec![2, 1, 1]]);
+
+        let mut stream = trie.stream_iter();
+        assert_eq!(stream.index(), 1);
+        assert_eq!(stream.value().collect_indices(), vec![3, 5]);
+        let mut inner = stream.value();
+        inner.seek(4, false);
+        assert_eq!(inner.value().collect_indices(), vec![2]);
+        stream.seek(1, true);
+        assert_eq!(stream.index(), 2);
+        stream.next();
+        assert!(!stream.valid());
+    }
+
+    #[test]
+    fn test_permuted() {
+        let trie = Trie::from_tuples_permuted(&[2, 0], [[1, 10, 7], [2, 20, 7], [3, 30, 5]]);
+        assert_eq!(trie.to_tuples(), vec![vec![5, 3], vec![7, 1], vec![7, 2]]);
+        assert_eq!(trie.distinct_prefixes(0), 2);
+    }
+
+    #[quickcheck]
+    fn test_roundtrip(tuples: Vec<(u8, u8, u8)>) {
+        let trie = Trie::from_tuples(3, tuples.iter().map(|&(a, b, c)| [a, b, c]));
+        let expected: BTreeSet<Vec<u8>> = tuples.iter().map(|&(a, b, c)| vec![a, b, c]).collect();
+        assert_eq!(trie.to_tuples(), expected.iter().cloned().collect::<Vec<_>>());
+        let prefixes: BTreeSet<(u8, u8)> = tuples.iter().map(|&(a, b, _)| (a, b)).collect();
+        assert_eq!(trie.distinct_prefixes(1), prefixes.len());
+    }
+}
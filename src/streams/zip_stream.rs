@@ -1,24 +1,45 @@
-use super::stream_defs::{IntoStreamIterator, IndexedStream};
+use std::ops::ControlFlow;
 
+use super::{parallel::Splittable, simd::{intersect_u32, is_skewed}, stream_defs::{IntoStreamIterator, IndexedStream}};
+
+
+/// Which side of a `ZipStream` is sought first; the other side is then only sought up to its index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lead {
+    Both,
+    Left,
+    Right,
+}
+
+impl Lead {
+    /// The side expected to have fewer entries leads, if both sides can tell
+    fn choose(left: Option<usize>, right: Option<usize>) -> Self {
+        match (left, right) {
+            (Some(l), Some(r)) if l <= r => Lead::Left,
+            (Some(_), Some(_)) => Lead::Right,
+            _ => Lead::Both,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ZipStream<L, R, F> {
     left: L,
     right: R,
     f: F,
+    lead: Lead,
 }
 
-impl<L, R, F> ZipStream<L, R, F> {
+impl<L: IndexedStream, R: IndexedStream, F> ZipStream<L, R, F> {
     pub fn new(
         left: impl IntoStreamIterator<StreamType = L>,
         right: impl IntoStreamIterator<StreamType = R>,
         f: F,
     ) -> Self {
-        ZipStream {
-            left: left.into_stream_iterator(),
-            right: right.into_stream_iterator(),
-            f
-        }
+        let left = left.into_stream_iterator();
+        let right = right.into_stream_iterator();
+        let lead = Lead::choose(left.remaining_estimate(), right.remaining_estimate());
+        ZipStream { left, right, f, lead }
     }
 }
 
@@ -35,12 +56,28 @@ impl<I, L, R, F, O> IndexedStream for ZipStream<L, R, F>
     }
 
     fn ready(&self) -> bool {
-        self.left.ready() && self.right.ready() && self.left.index() == self.right.index()
+        self.left.index() == self.right.index() && self.left.ready() && self.right.ready()
     }
 
     fn seek(&mut self, index: I, strict: bool) {
-        self.left.seek(index, strict);
-        self.right.seek(index, strict);
+        match self.lead {
+            Lead::Both => {
+                self.left.seek(index, strict);
+                self.right.seek(index, strict);
+            },
+            Lead::Left => {
+                self.left.seek(index, strict);
+                if self.left.valid() {
+                    self.right.seek(self.left.index(), false);
+                }
+            },
+            Lead::Right => {
+                self.right.seek(index, strict);
+                if self.right.valid() {
+                    self.left.seek(self.right.index(), false);
+                }
+            },
+        }
     }
 
     fn index(&self) -> I {
@@ -50,4 +87,161 @@ impl<I, L, R, F, O> IndexedStream for ZipStream<L, R, F>
     fn value(&self) -> Self::V {
         (self.f)(self.left.value(), self.right.value())
     }
+
+    fn remaining_estimate(&self) -> Option<usize> {
+        match (self.left.remaining_estimate(), self.right.remaining_estimate()) {
+            (Some(l), Some(r)) => Some(l.min(r)),
+            (l, r) => l.or(r),
+        }
+    }
+
+    /// Loops over the two streams, comparing their indices once per step,
+    /// or intersects them block by block if both are over sorted `u32` slices of similar lengths
+    fn try_fold<B, FF, RR>(&mut self, init: B, mut f: FF) -> ControlFlow<RR, B>
+    where
+        FF: FnMut(B, Self::I, Self::V) -> ControlFlow<RR, B>
+    {
+        if let (Some((a, mut i)), Some((b, mut j))) = (self.left.sorted_u32(), self.right.sorted_u32()) {
+            if !is_skewed(a.len().saturating_sub(i), b.len().saturating_sub(j)) {
+                let (left, right, g) = (&self.left, &self.right, &self.f);
+                let result = intersect_u32(a, b, &mut i, &mut j, init, |acc, p, q| {
+                    let (index, l) = left.entry_at(p);
+                    f(acc, index, g(l, right.entry_at(q).1))
+                });
+                self.left.set_position(i);
+                self.right.set_position(j);
+                return result;
+            }
+        }
+        let mut acc = init;
+        while self.left.valid() && self.right.valid() {
+            let (l, r) = (self.left.index(), self.right.index());
+            if l == r && self.left.ready() && self.right.ready() {
+                let v = (self.f)(self.left.value(), self.right.value());
+                self.seek(l, true);
+                acc = f(acc, l, v)?;
+            } else {
+                self.seek(l.max(r), false);
+            }
+        }
+        ControlFlow::Continue(acc)
+    }
+}
+
+impl<I, L, R, F, O> Splittable for ZipStream<L, R, F>
+    where L: Splittable<I=I>,
+          R: Splittable<I=I>,
+          I: Ord + Copy,
+          F: Fn(L::V, R::V) -> O + Clone {
+    fn split_point(&self) -> Option<I> {
+        self.left.split_point().or_else(|| self.right.split_point())
+    }
+
+    fn split_at(self, index: I) -> (Self, Self) {
+        let (left_lo, left_hi) = self.left.split_at(index);
+        let (right_lo, right_hi) = self.right.split_at(index);
+        (
+            ZipStream::new(left_lo, right_lo, self.f.clone()),
+            ZipStream::new(left_hi, right_hi, self.f),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, collections::BTreeSet};
+
+    use quickcheck_macros::quickcheck;
+
+    use crate::streams::{add_stream::AddStream, sorted_vec::{SortedVecGalloper, SortedVecLinear}, sparse_vec::SparseVec, stream_defs::IndexedStream};
+
+    use super::{Lead, ZipStream};
+
+    /// Counts the seeks made on the stream it wraps, and hides its size
+    #[derive(Clone)]
+    struct CountSeeks<'c, S> {
+        stream: S,
+        seeks: &'c Cell<usize>,
+    }
+
+    impl<S: IndexedStream> IndexedStream for CountSeeks<'_, S> {
+        type I = S::I;
+        type V = S::V;
+
+        fn valid(&self) -> bool {
+            self.stream.valid()
+        }
+
+        fn ready(&self) -> bool {
+            self.stream.ready()
+        }
+
+        fn seek(&mut self, index: S::I, strict: bool) {
+            self.seeks.set(self.seeks.get() + 1);
+            self.stream.seek(index, strict);
+        }
+
+        fn index(&self) -> S::I {
+            self.stream.index()
+        }
+
+        fn value(&self) -> S::V {
+            self.stream.value()
+        }
+    }
+
+    #[test]
+    fn test_sparse_side_leads() {
+        let sparse = [10, 500, 990];
+        let dense: Vec<u32> = (0..1000).collect();
+        let zip = SortedVecGalloper::new(&dense).zip_with(SortedVecGalloper::new(&sparse), |_, _| ());
+        assert_eq!(zip.lead, Lead::Right);
+        assert_eq!(zip.remaining_estimate(), Some(3));
+
+        let seeks = Cell::new(0);
+        let counted = || CountSeeks { stream: SortedVecGalloper::new(&dense), seeks: &seeks };
+        let led = ZipStream::new(counted(), SortedVecGalloper::new(&sparse), |_, _| ());
+        assert_eq!(led.lead, Lead::Both);
+        assert_eq!(IndexedStream::collect_indices(led), sparse.to_vec());
+        let unled = seeks.replace(0);
+        let mut led = ZipStream::new(counted(), SortedVecGalloper::new(&sparse), |_, _| ());
+        led.lead = Lead::Right;
+        assert_eq!(IndexedStream::collect_indices(led), sparse.to_vec());
+        // The dense side is only sought once per entry of the sparse side
+        assert_eq!(seeks.get(), 3);
+        assert_eq!(unled, 6);
+    }
+
+    #[test]
+    fn test_remaining_estimate() {
+        let x = SparseVec::from_iter([(1, 1), (4, 2), (6, 3)]);
+        let y = SparseVec::from_iter([(4, 1), (5, 1)]);
+        let mut stream = x.stream_iter();
+        stream.seek(4, false);
+        assert_eq!(stream.remaining_estimate(), Some(2));
+        assert_eq!(AddStream::add(x.stream_iter().cloned(), y.stream_iter().cloned()).remaining_estimate(), Some(5));
+        assert_eq!(x.stream_iter().zip_with(y.stream_iter_linear(), |a, b| a * b).remaining_estimate(), Some(2));
+        assert_eq!(x.stream_iter().map(|_, v| v).chain(y.stream_iter()).remaining_estimate(), Some(5));
+    }
+
+    #[quickcheck]
+    fn test_lead_agrees(a: BTreeSet<u16>, b: BTreeSet<u16>) {
+        let a: Vec<u16> = a.into_iter().collect();
+        let b: Vec<u16> = b.into_iter().collect();
+        let expected: Vec<u16> = a.iter().filter(|x| b.contains(x)).copied().collect();
+        for lead in [Lead::Both, Lead::Left, Lead::Right] {
+            let mut zip = SortedVecGalloper::new(&a).zip_with(SortedVecLinear::new(&b), |_, _| ());
+            zip.lead = lead;
+            assert_eq!(zip.clone().collect_indices(), expected);
+            // Without `try_fold`
+            let mut stepped = Vec::new();
+            while zip.valid() {
+                if zip.ready() {
+                    stepped.push(zip.index());
+                }
+                zip.next();
+            }
+            assert_eq!(stepped, expected);
+        }
+    }
 }